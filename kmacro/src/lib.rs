@@ -1,49 +1,163 @@
 //! Macro definitions for kernel module functions.
 use proc_macro::TokenStream;
-use quote::quote;
+use quote::{format_ident, quote};
 use syn::{
     Ident, LitStr, Token,
     parse::{Parse, ParseStream},
     parse_macro_input,
 };
 
+/// Arguments of `#[init_fn]` and `#[exit_fn]`
+struct CallArgs {
+    priority: Option<syn::LitInt>,
+}
+
+impl Parse for CallArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut priority = None;
+        while !input.is_empty() {
+            let key: Ident = input.parse()?;
+            input.parse::<Token![=]>()?;
+
+            match key.to_string().as_str() {
+                "priority" => priority = Some(input.parse()?),
+                _ => {
+                    return Err(syn::Error::new(
+                        key.span(),
+                        format!("Unknown field: {}", key),
+                    ));
+                }
+            }
+
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+        Ok(CallArgs { priority })
+    }
+}
+
+impl CallArgs {
+    /// Get the priority, `None` for the plain init/exit pair
+    fn priority(&self) -> syn::Result<Option<u32>> {
+        let Some(lit) = &self.priority else {
+            return Ok(None);
+        };
+        let priority: u32 = lit.base10_parse()?;
+        if priority < 1 {
+            return Err(syn::Error::new(
+                lit.span(),
+                "priority must be at least 1, 0 is the plain init/exit pair",
+            ));
+        }
+        Ok(Some(priority))
+    }
+}
+
 /// Attribute macro to mark the initialization function of a kernel module. It
-/// places the function in the `.text.init` section.
+/// places the function in the `.text.init` section. The module names it in the
+/// `init` field of `module!`.
+///
+/// A module whose setup happens in phases can have more init functions, each
+/// with a priority of at least 1. They are placed in `.text.init.N` and run in
+/// ascending priority after the plain `#[init_fn]`, which is optional then.
+/// Init functions with the same priority run in link order. If one returns
+/// nonzero, the exit functions of the lower priorities run, in descending
+/// priority, and the module fails to load.
 /// # Example:
 /// ```ignore
 /// #[init_fn]
 /// fn init() -> i32 { ... }
+///
+/// #[init_fn(priority = 1)]
+/// fn init_devices() -> i32 { ... }
 /// ```
 #[proc_macro_attribute]
-pub fn init_fn(_attr: TokenStream, item: TokenStream) -> TokenStream {
+pub fn init_fn(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as CallArgs);
     let func = parse_macro_input!(item as syn::ItemFn);
     let func_name = &func.sig.ident;
+    let priority = match args.priority() {
+        Ok(priority) => priority,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let vis = &func.vis;
+    let wrapper = format_ident!("__kmod_init_{}", func_name);
+    let Some(priority) = priority else {
+        return quote! {
+            // Named in `module!` through its `init` field
+            #vis unsafe extern "C" fn #wrapper() -> core::ffi::c_int {
+                #func_name() as core::ffi::c_int
+            }
+            #[unsafe(link_section = ".text.init")]
+            #func
+        }
+        .into();
+    };
+    let call_ident = format_ident!("__KMOD_INIT_CALL_{}", func_name);
+    let section = format!(".text.init.{}", priority);
     quote! {
-        unsafe extern "C" fn init_module() -> core::ffi::c_int {
+        unsafe extern "C" fn #wrapper() -> core::ffi::c_int {
             #func_name() as core::ffi::c_int
         }
-        #[unsafe(link_section = ".text.init")]
+        #[used]
+        #[unsafe(link_section = ".init_calls")]
+        static #call_ident: kmod::InitCall = kmod::InitCall::new(#priority, #wrapper);
+        #[unsafe(link_section = #section)]
         #func
     }
     .into()
 }
 
 /// Attribute macro to mark the cleanup function of a kernel module. It places
-/// the function in the `.text.exit` section.
+/// the function in the `.text.exit` section. The module names it in the `exit`
+/// field of `module!`.
+///
+/// It is optional, a module without one can not be unloaded.
+///
+/// Like `#[init_fn]`, it takes an optional priority of at least 1 and is then
+/// placed in `.text.exit.N`. Exit functions run in descending priority, the
+/// plain `#[exit_fn]` last.
 /// # Example:
 /// ```ignore
 /// #[exit_fn]
 /// fn cleanup() { ... }
+///
+/// #[exit_fn(priority = 1)]
+/// fn cleanup_devices() { ... }
 /// ```
 #[proc_macro_attribute]
-pub fn exit_fn(_attr: TokenStream, item: TokenStream) -> TokenStream {
+pub fn exit_fn(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as CallArgs);
     let func = parse_macro_input!(item as syn::ItemFn);
     let func_name = &func.sig.ident;
+    let priority = match args.priority() {
+        Ok(priority) => priority,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let vis = &func.vis;
+    let wrapper = format_ident!("__kmod_exit_{}", func_name);
+    let Some(priority) = priority else {
+        return quote! {
+            // Named in `module!` through its `exit` field
+            #vis unsafe extern "C" fn #wrapper() {
+                #func_name()
+            }
+            #[unsafe(link_section = ".text.exit")]
+            #func
+        }
+        .into();
+    };
+    let call_ident = format_ident!("__KMOD_EXIT_CALL_{}", func_name);
+    let section = format!(".text.exit.{}", priority);
     quote! {
-        unsafe extern "C" fn cleanup_module() {
+        unsafe extern "C" fn #wrapper() {
             #func_name()
         }
-        #[unsafe(link_section = ".text.exit")]
+        #[used]
+        #[unsafe(link_section = ".exit_calls")]
+        static #call_ident: kmod::ExitCall = kmod::ExitCall::new(#priority, #wrapper);
+        #[unsafe(link_section = #section)]
         #func
     }
     .into()
@@ -54,6 +168,11 @@ struct ModuleArgs {
     version: Option<LitStr>,
     license: Option<LitStr>,
     description: Option<LitStr>,
+    author: Option<LitStr>,
+    import_ns: Vec<LitStr>,
+    panic_handler: Option<syn::LitBool>,
+    init: Option<syn::Path>,
+    exit: Option<syn::Path>,
 }
 
 impl Parse for ModuleArgs {
@@ -62,6 +181,11 @@ impl Parse for ModuleArgs {
         let mut version = None;
         let mut license = None;
         let mut description = None;
+        let mut author = None;
+        let mut import_ns = Vec::new();
+        let mut panic_handler = None;
+        let mut init = None;
+        let mut exit = None;
         while !input.is_empty() {
             let key: Ident = input.parse()?;
             input.parse::<Token![:]>()?;
@@ -83,6 +207,25 @@ impl Parse for ModuleArgs {
                     let value: LitStr = input.parse()?;
                     description = Some(value);
                 }
+                "author" => {
+                    let value: LitStr = input.parse()?;
+                    author = Some(value);
+                }
+                "import_ns" => {
+                    let content;
+                    syn::bracketed!(content in input);
+                    let values = content.parse_terminated(
+                        |input: ParseStream| input.parse::<LitStr>(),
+                        Token![,],
+                    )?;
+                    import_ns.extend(values);
+                }
+                "panic_handler" => {
+                    let value: syn::LitBool = input.parse()?;
+                    panic_handler = Some(value);
+                }
+                "init" => init = Some(input.parse()?),
+                "exit" => exit = Some(input.parse()?),
                 _ => {
                     return Err(syn::Error::new(
                         key.span(),
@@ -101,12 +244,24 @@ impl Parse for ModuleArgs {
             version,
             license,
             description,
+            author,
+            import_ns,
+            panic_handler,
+            init,
+            exit,
         })
     }
 }
 
 /// Macro to declare module metadata in the `.modinfo` section.
 ///
+/// The generated statics are named after the module, so several modules can
+/// be declared in one image and the loader can enumerate each of them.
+///
+/// The optional `init` and `exit` fields name the `#[init_fn]` and
+/// `#[exit_fn]` functions of the module, like Linux `module_init()` and
+/// `module_exit()`.
+///
 /// # Example:
 /// ```ignore
 /// module! {
@@ -114,9 +269,25 @@ impl Parse for ModuleArgs {
 ///     version: "1.0.0",
 ///     license: "GPL",
 ///     description: "A simple hello world kernel module",
+///     init: hello_init,
+///     exit: hello_exit,
 /// }
 /// ```
 ///
+/// The `format_version` of [`kmod::FORMAT_VERSION`] is recorded as well, the
+/// loader refuses modules built for another one.
+///
+/// Symbol namespaces used by the module are listed with the optional
+/// `import_ns` field, e.g. `import_ns: ["DMA_BUF"]`, and its author with the
+/// optional `author` field.
+///
+/// A license that is not GPL compatible taints the kernel when the module is
+/// loaded.
+///
+/// On `target_os = "none"` a `#[panic_handler]` is defined for the module.
+/// Set the optional `panic_handler` field to `false` to leave it out, e.g. to
+/// link several modules into one binary that supplies its own.
+///
 /// Parameters can be in any order, for example:
 /// ```ignore
 /// module! {
@@ -135,50 +306,312 @@ pub fn module(item: TokenStream) -> TokenStream {
     let license = args.license.expect("license is required");
     let description = args.description.expect("description is required");
 
-    // Build complete byte arrays for each modinfo entry
-    let mut name_array = b"name=".to_vec();
-    name_array.extend_from_slice(name.value().as_bytes());
-    name_array.push(0);
+    // One array of all `key=value\0` entries, starting with the name, so the
+    // linker can not interleave them with those of another module
+    let mut entries = Vec::new();
+    let mut push_entry = |key: &str, value: &LitStr| {
+        entries.extend_from_slice(key.as_bytes());
+        entries.push(b'=');
+        entries.extend_from_slice(value.value().as_bytes());
+        entries.push(0);
+    };
+    push_entry("name", &name);
+    push_entry("version", &version);
+    push_entry("license", &license);
+    push_entry("description", &description);
+    if let Some(author) = &args.author {
+        push_entry("author", author);
+    }
+    for ns in &args.import_ns {
+        push_entry("import_ns", ns);
+    }
 
-    let mut version_array = b"version=".to_vec();
-    version_array.extend_from_slice(version.value().as_bytes());
-    version_array.push(0);
+    // e.g. "hello-world" -> "HELLO_WORLD"
+    let ident_base: String = name
+        .value()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let modinfo_ident = format_ident!("__{}_MODINFO", ident_base);
+    let this_module_ident = format_ident!("__{}_THIS_MODULE", ident_base);
 
-    let mut license_array = b"license=".to_vec();
-    license_array.extend_from_slice(license.value().as_bytes());
-    license_array.push(0);
+    // The wrappers `#[init_fn]` and `#[exit_fn]` define next to the functions
+    let init = match args.init {
+        Some(path) => {
+            let wrapper = wrapper_path(path, "__kmod_init_");
+            quote!(Some(#wrapper))
+        }
+        None => quote!(None),
+    };
+    let exit = match args.exit {
+        Some(path) => {
+            let wrapper = wrapper_path(path, "__kmod_exit_");
+            quote!(Some(#wrapper))
+        }
+        None => quote!(None),
+    };
 
-    let mut description_array = b"description=".to_vec();
-    description_array.extend_from_slice(description.value().as_bytes());
-    description_array.push(0);
+    let panic_handler = args.panic_handler.is_none_or(|value| value.value).then(|| {
+        quote! {
+            #[cfg(target_os = "none")]
+            #[panic_handler]
+            fn panic(_info: &core::panic::PanicInfo) -> ! {
+                loop {}
+            }
+        }
+    });
 
-    let name_len = name_array.len();
-    let version_len = version_array.len();
-    let license_len = license_array.len();
-    let description_len = description_array.len();
+    let entries_len = entries.len();
 
     quote! {
+        // The entries followed by the `format_version` one
         #[used]
         #[unsafe(link_section = ".modinfo")]
-        static MODULE_NAME: [u8; #name_len] = [#(#name_array),*];
-        #[used]
-        #[unsafe(link_section = ".modinfo")]
-        static MODULE_VERSION: [u8; #version_len] = [#(#version_array),*];
-        #[used]
-        #[unsafe(link_section = ".modinfo")]
-        static MODULE_LICENSE: [u8; #license_len] = [#(#license_array),*];
-        #[used]
-        #[unsafe(link_section = ".modinfo")]
-        static MODULE_DESCRIPTION: [u8; #description_len] = [#(#description_array),*];
+        static #modinfo_ident: [u8; #entries_len + kmod::__private::__KMOD_FORMAT_VERSION_LEN] = {
+            let entries: [u8; #entries_len] = [#(#entries),*];
+            let mut modinfo = [0u8; #entries_len + kmod::__private::__KMOD_FORMAT_VERSION_LEN];
+            let mut i = 0;
+            while i < entries.len() {
+                modinfo[i] = entries[i];
+                i += 1;
+            }
+            let mut j = 0;
+            while j < kmod::__private::__KMOD_FORMAT_VERSION_LEN {
+                modinfo[i + j] = kmod::__private::__KMOD_FORMAT_VERSION[j];
+                j += 1;
+            }
+            modinfo
+        };
         #[used]
         #[unsafe(link_section = ".gnu.linkonce.this_module")]
-        static __this_module: kmod::Module = kmod::Module::new(Some(init_module), Some(cleanup_module));
+        static #this_module_ident: kmod::Module = kmod::Module::new(#name, #init, #exit);
+
+        #panic_handler
+    }
+    .into()
+}
+
+/// `path` with its last segment `f` replaced by `{prefix}f`
+fn wrapper_path(mut path: syn::Path, prefix: &str) -> syn::Path {
+    if let Some(last) = path.segments.last_mut() {
+        last.ident = format_ident!("{}{}", prefix, last.ident);
+    }
+    path
+}
 
-        #[cfg(target_os = "none")]
-        #[panic_handler]
-        fn panic(_info: &core::panic::PanicInfo) -> ! {
-            loop {}
+struct ModuleParamArgs {
+    name: Option<LitStr>,
+    default: Option<syn::Expr>,
+}
+
+impl Parse for ModuleParamArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut name = None;
+        let mut default = None;
+        while !input.is_empty() {
+            let key: Ident = input.parse()?;
+            input.parse::<Token![=]>()?;
+
+            match key.to_string().as_str() {
+                "name" => name = Some(input.parse()?),
+                "default" => default = Some(input.parse()?),
+                _ => {
+                    return Err(syn::Error::new(
+                        key.span(),
+                        format!("Unknown field: {}", key),
+                    ));
+                }
+            }
+
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
         }
+        Ok(ModuleParamArgs { name, default })
+    }
+}
+
+/// Attribute macro to declare a load-time parameter of a kernel module.
+///
+/// It tags a `static mut` of type `i32`, `u32` or `bool`, or a static
+/// `AtomicI32`, `AtomicU32` or `AtomicBool`, and records its name, type and
+/// default value in the `.modparam` section, where the loader finds it to set
+/// the value before calling the init function.
+///
+/// `name` defaults to the lowercase name of the static, and `default`, if
+/// given, replaces its initial value.
+/// # Example:
+/// ```ignore
+/// #[module_param(name = "debug", default = true)]
+/// static mut DEBUG: bool = false;
+///
+/// #[module_param(default = 16)]
+/// static QUEUE_DEPTH: AtomicU32 = AtomicU32::new(0);
+/// ```
+#[proc_macro_attribute]
+pub fn module_param(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as ModuleParamArgs);
+    let mut item = parse_macro_input!(item as syn::ItemStatic);
+    let ident = &item.ident;
+
+    let ty_name = match &*item.ty {
+        syn::Type::Path(path) => path.path.segments.last().map(|seg| seg.ident.to_string()),
+        _ => None,
+    };
+    let (param_ty, value_ty, atomic) = match ty_name.as_deref() {
+        Some("i32") => (quote!(I32), quote!(i32), false),
+        Some("u32") => (quote!(U32), quote!(u32), false),
+        Some("bool") => (quote!(Bool), quote!(bool), false),
+        Some("AtomicI32") => (quote!(I32), quote!(i32), true),
+        Some("AtomicU32") => (quote!(U32), quote!(u32), true),
+        Some("AtomicBool") => (quote!(Bool), quote!(bool), true),
+        _ => {
+            return syn::Error::new_spanned(
+                &item.ty,
+                "module parameters must be i32, u32, bool or their atomic types",
+            )
+            .to_compile_error()
+            .into();
+        }
+    };
+    let is_mut = matches!(item.mutability, syn::StaticMutability::Mut(_));
+    if is_mut == atomic {
+        let msg = if atomic {
+            "atomic module parameters must not be `static mut`"
+        } else {
+            "module parameters must be `static mut` or atomic"
+        };
+        return syn::Error::new_spanned(&item.ty, msg)
+            .to_compile_error()
+            .into();
+    }
+
+    let default = match args.default {
+        Some(default) => {
+            let ty = &item.ty;
+            *item.expr = if atomic {
+                syn::parse_quote!(<#ty>::new(#default))
+            } else {
+                default.clone()
+            };
+            default
+        }
+        None if !atomic => (*item.expr).clone(),
+        // The default of `AtomicX::new(default)`
+        None => match &*item.expr {
+            syn::Expr::Call(call) if call.args.len() == 1 => call.args[0].clone(),
+            expr => {
+                return syn::Error::new_spanned(
+                    expr,
+                    "`default` is required unless initialized with `new(value)`",
+                )
+                .to_compile_error()
+                .into();
+            }
+        },
+    };
+    let name = args
+        .name
+        .map(|name| name.value())
+        .unwrap_or_else(|| ident.to_string().to_lowercase());
+    let desc_ident = format_ident!("__MODULE_PARAM_{}", ident);
+
+    quote! {
+        #item
+        #[used]
+        #[unsafe(link_section = ".modparam")]
+        static #desc_ident: kmod::ModuleParam = kmod::ModuleParam::new(
+            #name,
+            kmod::ModuleParamType::#param_ty,
+            (#default as #value_ty) as u64,
+            &raw const #ident as *mut core::ffi::c_void,
+        );
+    }
+    .into()
+}
+
+/// Arguments of `#[export_symbol]`
+struct ExportSymbolArgs {
+    ns: Option<LitStr>,
+}
+
+impl Parse for ExportSymbolArgs {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let mut ns = None;
+        while !input.is_empty() {
+            let key: Ident = input.parse()?;
+            input.parse::<Token![=]>()?;
+
+            match key.to_string().as_str() {
+                "ns" => ns = Some(input.parse()?),
+                _ => {
+                    return Err(syn::Error::new(
+                        key.span(),
+                        format!("Unknown field: {}", key),
+                    ));
+                }
+            }
+
+            if !input.is_empty() {
+                input.parse::<Token![,]>()?;
+            }
+        }
+        Ok(ExportSymbolArgs { ns })
+    }
+}
+
+/// Attribute macro to export a function or static of a kernel module, so
+/// modules loaded after it can use it.
+///
+/// It records the name and address of the item in the `.ksymtab` section.
+/// Other modules declare it in an `extern "C"` block, so exported functions
+/// should use the C ABI.
+///
+/// With `ns`, the symbol is exported in that namespace, like
+/// `EXPORT_SYMBOL_NS` in Linux, and only modules listing it in the
+/// `import_ns` field of `module!` can use it.
+/// # Example:
+/// ```ignore
+/// #[export_symbol]
+/// pub extern "C" fn hello_count() -> u32 { ... }
+///
+/// #[export_symbol(ns = "HELLO")]
+/// pub extern "C" fn hello_reset() { ... }
+/// ```
+#[proc_macro_attribute]
+pub fn export_symbol(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as ExportSymbolArgs);
+    let item = parse_macro_input!(item as syn::Item);
+    let (ident, address) = match &item {
+        syn::Item::Fn(func) => {
+            let ident = &func.sig.ident;
+            (ident, quote!(#ident as *const core::ffi::c_void))
+        }
+        syn::Item::Static(stat) => {
+            let ident = &stat.ident;
+            (ident, quote!(&raw const #ident as *const core::ffi::c_void))
+        }
+        _ => {
+            return syn::Error::new_spanned(&item, "only functions and statics can be exported")
+                .to_compile_error()
+                .into();
+        }
+    };
+    let name = ident.to_string();
+    let ksym_ident = format_ident!("__KSYMTAB_{}", ident);
+    let namespace = args.ns.map(|ns| quote!(.with_namespace(#ns)));
+    quote! {
+        #item
+        #[used]
+        #[unsafe(link_section = ".ksymtab")]
+        static #ksym_ident: kmod::KernelSymbol =
+            kmod::KernelSymbol::new(#name, #address)#namespace;
     }
     .into()
 }
@@ -1,3 +1,7 @@
+//! Parse and load a module ELF.
+//!
+//! The module is not executed, see `tests/host_exec.rs` for a module run on
+//! the host.
 use kmod_loader::ElfParser;
 use kmod_loader::loader::{KernelModuleHelper, ModuleLoader, SectionMemOps, SectionPerm};
 use std::env;
@@ -40,6 +44,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
 
+    // The relocation handlers of any architecture run on the host
+    let arch = kmod_loader::module_arch(&data_box)?;
+    kmod_loader::self_test_arch(arch)?;
+    println!("Relocation self test of {:?} passed", arch);
+
     let loader = ModuleLoader::<FakeHelper>::new(&data_box)?;
     let owner = loader.load_module().unwrap();
     drop(owner);
@@ -49,6 +58,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 struct FakeHelper;
 
 impl KernelModuleHelper for FakeHelper {
+    type UserData = ();
+
     fn vmalloc(size: usize) -> Box<dyn SectionMemOps> {
         assert!(size % 4096 == 0);
         let mmap = memmap2::MmapOptions::new()
@@ -2,7 +2,10 @@ mod insn;
 
 use crate::{
     BIT, BIT_U64, ModuleErr, Result,
-    arch::{Ptr, aarch64::insn::*, get_rela_sym_idx, get_rela_type},
+    arch::{
+        FixtureTarget, Ptr, RelocFixture, RelocProgress, aarch64::insn::*, get_rela_sym_idx,
+        get_rela_type, run_self_test, section_header,
+    },
     loader::*,
 };
 use alloc::{format, string::ToString as _};
@@ -56,6 +59,15 @@ pub enum Aarch64RelocationType {
     R_AARCH64_MOVW_PREL_G2 = 291,
     R_AARCH64_MOVW_PREL_G2_NC = 292,
     R_AARCH64_MOVW_PREL_G3 = 293,
+    // Local-exec thread-local storage
+    R_AARCH64_TLSLE_MOVW_TPREL_G2 = 544,
+    R_AARCH64_TLSLE_MOVW_TPREL_G1 = 545,
+    R_AARCH64_TLSLE_MOVW_TPREL_G1_NC = 546,
+    R_AARCH64_TLSLE_MOVW_TPREL_G0 = 547,
+    R_AARCH64_TLSLE_MOVW_TPREL_G0_NC = 548,
+    R_AARCH64_TLSLE_ADD_TPREL_HI12 = 549,
+    R_AARCH64_TLSLE_ADD_TPREL_LO12 = 550,
+    R_AARCH64_TLSLE_ADD_TPREL_LO12_NC = 551,
     R_AARCH64_RELATIVE = 1027,
 }
 
@@ -77,7 +89,32 @@ const fn is_forbidden_offset_for_adrp(_address: u64) -> bool {
     false
 }
 
+/// Reach of a B/BL instruction, a signed 26-bit word offset
+const BRANCH26_RANGE: core::ops::Range<i64> = -(1 << 27)..(1 << 27);
+
 impl Aarch64RelocationType {
+    /// Whether the relocation takes the thread pointer offset TPREL(S + A)
+    /// instead of an address.
+    ///
+    /// AArch64 uses TLS variant I: `tpidr_el1` points at a 16 byte thread
+    /// control block and the static TLS blocks follow it, so the module's
+    /// block starts at the (positive) offset returned by
+    /// [`KernelModuleHelper::tls_block_offset`] and TPREL(S + A) is that
+    /// offset plus the offset of the variable inside the block.
+    fn is_tprel(&self) -> bool {
+        matches!(
+            self,
+            Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G2
+                | Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G1
+                | Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G1_NC
+                | Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G0
+                | Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G0_NC
+                | Arm64RelTy::R_AARCH64_TLSLE_ADD_TPREL_HI12
+                | Arm64RelTy::R_AARCH64_TLSLE_ADD_TPREL_LO12
+                | Arm64RelTy::R_AARCH64_TLSLE_ADD_TPREL_LO12_NC
+        )
+    }
+
     /// See <https://elixir.bootlin.com/linux/v6.6/source/arch/arm64/kernel/module.c#L177>
     fn reloc_data(
         &self,
@@ -100,7 +137,7 @@ impl Aarch64RelocationType {
          */
         match len {
             16 => {
-                location.write::<i16>(s_addr as i16);
+                location.write_checked::<i16>(s_addr as i16)?;
                 match op {
                     Aarch64RelocOp::RELOC_OP_ABS => Ok(s_addr < 0 || s_addr > u16::MAX as i64),
                     Aarch64RelocOp::RELOC_OP_PREL => {
@@ -112,7 +149,7 @@ impl Aarch64RelocationType {
                 }
             }
             32 => {
-                location.write::<i32>(s_addr as i32);
+                location.write_checked::<i32>(s_addr as i32)?;
                 match op {
                     Aarch64RelocOp::RELOC_OP_ABS => Ok(s_addr < 0 || s_addr > u32::MAX as i64),
                     Aarch64RelocOp::RELOC_OP_PREL => {
@@ -124,7 +161,7 @@ impl Aarch64RelocationType {
                 }
             }
             64 => {
-                location.write::<u64>(s_addr as u64);
+                location.write_checked::<u64>(s_addr as u64)?;
                 Ok(false)
             }
             _ => unreachable!("Unsupported length for AArch64 relocation"),
@@ -140,7 +177,7 @@ impl Aarch64RelocationType {
         lsb: i32,
         imm_type: Aarch64InsnMovwImmType,
     ) -> Result<bool> {
-        let mut insn = location.read::<u32>();
+        let mut insn = location.read_checked::<u32>()?;
         let s_addr = do_reloc(op, location, address) as i64;
 
         let mut imm = (s_addr >> lsb) as u64;
@@ -166,7 +203,7 @@ impl Aarch64RelocationType {
         }
         // Update the instruction with the new encoding.
         insn = aarch64_insn_encode_immediate(Aarch64InsnImmType::AARCH64_INSN_IMM_16, insn, imm);
-        location.write::<u32>(insn);
+        location.write_checked::<u32>(insn)?;
 
         if imm > u16::MAX as u64 {
             Ok(true)
@@ -185,7 +222,7 @@ impl Aarch64RelocationType {
         len: i32,
         imm_type: Aarch64InsnImmType,
     ) -> Result<bool> {
-        let mut insn = location.read::<u32>();
+        let mut insn = location.read_checked::<u32>()?;
         // Calculate the relocation value.
         let mut s_addr = do_reloc(op, location, address) as i64;
         s_addr >>= lsb;
@@ -196,7 +233,7 @@ impl Aarch64RelocationType {
         // Update the instruction's immediate field.
         insn = aarch64_insn_encode_immediate(imm_type, insn, imm);
 
-        location.write::<u32>(insn);
+        location.write_checked::<u32>(insn)?;
 
         /*
          * Extract the upper value bits (including the sign bit) and
@@ -238,9 +275,9 @@ impl Aarch64RelocationType {
             Aarch64InsnImmType::AARCH64_INSN_IMM_ADR,
         )?;
         if !ovf {
-            let mut insn = location.read::<u32>();
+            let mut insn = location.read_checked::<u32>()?;
             insn &= !BIT!(31); // clear bit 31 to convert ADRP to ADR
-            location.write::<u32>(insn);
+            location.write_checked::<u32>(insn)?;
             Ok(false)
         } else {
             //  out of range for ADR -> emit a veneer
@@ -250,10 +287,9 @@ impl Aarch64RelocationType {
         }
     }
 
-    fn apply_relocation(&self, location: u64, address: u64) -> Result<()> {
+    fn apply_relocation(&self, location: Ptr, address: u64) -> Result<()> {
         // Check for overflow by default.
         let mut check_overflow = true;
-        let location = Ptr(location);
         let ovf = match self {
             Arm64RelTy::R_ARM_NONE | Arm64RelTy::R_AARCH64_NONE => false,
             // Data relocations.
@@ -400,6 +436,60 @@ impl Aarch64RelocationType {
                     Aarch64InsnMovwImmType::AARCH64_INSN_IMM_MOVNZ,
                 )?
             }
+            // Local-exec TLS relocations, `address` is already TPREL(S + A)
+            Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G2 => self.reloc_insn_movw(
+                Aarch64RelocOp::RELOC_OP_ABS,
+                location,
+                address,
+                32,
+                Aarch64InsnMovwImmType::AARCH64_INSN_IMM_MOVNZ,
+            )?,
+            Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G1_NC
+            | Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G1 => {
+                let mut imm_type = Aarch64InsnMovwImmType::AARCH64_INSN_IMM_MOVNZ;
+                if *self == Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G1_NC {
+                    check_overflow = false;
+                    imm_type = Aarch64InsnMovwImmType::AARCH64_INSN_IMM_MOVKZ;
+                }
+                self.reloc_insn_movw(
+                    Aarch64RelocOp::RELOC_OP_ABS,
+                    location,
+                    address,
+                    16,
+                    imm_type,
+                )?
+            }
+            Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G0_NC
+            | Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G0 => {
+                let mut imm_type = Aarch64InsnMovwImmType::AARCH64_INSN_IMM_MOVNZ;
+                if *self == Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G0_NC {
+                    check_overflow = false;
+                    imm_type = Aarch64InsnMovwImmType::AARCH64_INSN_IMM_MOVKZ;
+                }
+                self.reloc_insn_movw(Aarch64RelocOp::RELOC_OP_ABS, location, address, 0, imm_type)?
+            }
+            Arm64RelTy::R_AARCH64_TLSLE_ADD_TPREL_HI12 => self.reloc_insn_imm(
+                Aarch64RelocOp::RELOC_OP_ABS,
+                location,
+                address,
+                12,
+                12,
+                Aarch64InsnImmType::AARCH64_INSN_IMM_12,
+            )?,
+            Arm64RelTy::R_AARCH64_TLSLE_ADD_TPREL_LO12_NC
+            | Arm64RelTy::R_AARCH64_TLSLE_ADD_TPREL_LO12 => {
+                if *self == Arm64RelTy::R_AARCH64_TLSLE_ADD_TPREL_LO12_NC {
+                    check_overflow = false;
+                }
+                self.reloc_insn_imm(
+                    Aarch64RelocOp::RELOC_OP_ABS,
+                    location,
+                    address,
+                    0,
+                    12,
+                    Aarch64InsnImmType::AARCH64_INSN_IMM_12,
+                )?
+            }
             // Immediate instruction relocations.
             Arm64RelTy::R_AARCH64_LD_PREL_LO19 => self.reloc_insn_imm(
                 Aarch64RelocOp::RELOC_OP_PREL,
@@ -506,31 +596,129 @@ impl Aarch64RelocationType {
                 )?;
                 if ovf {
                     // TODO: address = module_emit_plt_entry()
-                    unimplemented!(
-                        "Veneer emission for out-of-range AArch64 JUMP26/CALL26 not implemented"
-                    );
+                    return Err(ModuleErr::RelocationFailed(format!(
+                        "{:?} target {:#x} is out of the branch range of PC = {:#x}",
+                        self, address, location.0
+                    )));
                 }
                 ovf
             }
             _ => {
-                return Err(ModuleErr::RelocationFailed(format!(
-                    "Unsupported relocation type: {:?}",
-                    self
-                )));
+                return Err(ModuleErr::UnsupportedRelocation {
+                    arch: "AArch64",
+                    reloc_type: *self as u32,
+                });
             }
         };
         if check_overflow && ovf {
+            // e.g. R_AARCH64_ABS32/ABS16 whose S + A does not fit the field
             return Err(ModuleErr::RelocationFailed(format!(
-                "Overflow detected during relocation type {:?}",
-                self
+                "Overflow detected during relocation type {:?}, target address {:#x}, PC = {:#x}",
+                self, address, location.0
             )));
         }
         Ok(())
     }
 }
 
+/// Known-good encodings checked by [`Aarch64ArchRelocate::self_test`]
+const SELF_TEST_FIXTURES: &[RelocFixture<Arm64RelTy>] = &[
+    // bl 0
+    RelocFixture {
+        reloc: Arm64RelTy::R_AARCH64_CALL26,
+        insn: 0x94000000,
+        target: FixtureTarget::PcRel(0x1000),
+        expected: 0x94000400,
+    },
+    // b 0
+    RelocFixture {
+        reloc: Arm64RelTy::R_AARCH64_JUMP26,
+        insn: 0x14000000,
+        target: FixtureTarget::PcRel(-4),
+        expected: 0x17ffffff,
+    },
+    // The ends of the ±128MB branch range
+    RelocFixture {
+        reloc: Arm64RelTy::R_AARCH64_CALL26,
+        insn: 0x94000000,
+        target: FixtureTarget::PcRel(0x7fffffc),
+        expected: 0x95ffffff,
+    },
+    RelocFixture {
+        reloc: Arm64RelTy::R_AARCH64_JUMP26,
+        insn: 0x14000000,
+        target: FixtureTarget::PcRel(-0x8000000),
+        expected: 0x16000000,
+    },
+    // adrp x0, 0
+    RelocFixture {
+        reloc: Arm64RelTy::R_AARCH64_ADR_PREL_PG_HI21,
+        insn: 0x90000000,
+        target: FixtureTarget::PageRel(0x3000),
+        expected: 0xf0000000,
+    },
+    // add x0, x0, #0
+    RelocFixture {
+        reloc: Arm64RelTy::R_AARCH64_ADD_ABS_LO12_NC,
+        insn: 0x91000000,
+        target: FixtureTarget::Abs(0x12345abc),
+        expected: 0x912af000,
+    },
+    // movz x0, #0, lsl #32
+    RelocFixture {
+        reloc: Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G2,
+        insn: 0xd2c00000,
+        target: FixtureTarget::Abs(0x0001_6789_abcd),
+        expected: 0xd2c00020,
+    },
+    // movk x0, #0, lsl #16
+    RelocFixture {
+        reloc: Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G1_NC,
+        insn: 0xf2a00000,
+        target: FixtureTarget::Abs(0x0001_2345_6789_abcd),
+        expected: 0xf2acf120,
+    },
+    // movk x0, #0
+    RelocFixture {
+        reloc: Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G0_NC,
+        insn: 0xf2800000,
+        target: FixtureTarget::Abs(0x0001_2345_6789_abcd),
+        expected: 0xf29579a0,
+    },
+    // movz x0, #0, lsl #16
+    RelocFixture {
+        reloc: Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G1,
+        insn: 0xd2a00000,
+        target: FixtureTarget::Abs(0x1234_0010),
+        expected: 0xd2a24680,
+    },
+    // add x0, x0, #0, lsl #12
+    RelocFixture {
+        reloc: Arm64RelTy::R_AARCH64_TLSLE_ADD_TPREL_HI12,
+        insn: 0x91400000,
+        target: FixtureTarget::Abs(0x12_3010),
+        expected: 0x91448c00,
+    },
+    // add x0, x0, #0
+    RelocFixture {
+        reloc: Arm64RelTy::R_AARCH64_TLSLE_ADD_TPREL_LO12,
+        insn: 0x91000000,
+        target: FixtureTarget::Abs(0x10),
+        expected: 0x91004000,
+    },
+];
+
 pub struct Aarch64ArchRelocate;
 
+impl Aarch64ArchRelocate {
+    /// Check the relocation handlers against known-good encodings
+    pub fn self_test() -> Result<()> {
+        run_self_test(SELF_TEST_FIXTURES, |reloc, location, address| {
+            reloc.apply_relocation(location, address)
+        })
+    }
+}
+
 #[allow(unused_assignments)]
 impl Aarch64ArchRelocate {
     /// See <https://elixir.bootlin.com/linux/v6.6/source/arch/arm64/kernel/module.c#L344>
@@ -540,39 +728,280 @@ impl Aarch64ArchRelocate {
         sechdrs: &[SectionHeader],
         load_info: &ModuleLoadInfo,
         module: &ModuleOwner<H>,
+        progress: &mut RelocProgress,
     ) -> Result<()> {
+        let section_base = section_header(sechdrs, rel_section.sh_info as usize)?.sh_addr;
+        let bounds = module
+            .section_bounds(section_base)
+            .unwrap_or(section_base..section_base);
         for rela in rela_list {
             let rel_type = get_rela_type(rela.r_info);
             let sym_idx = get_rela_sym_idx(rela.r_info);
 
             // loc corresponds to P in the AArch64 ELF document.
-            let location = sechdrs[rel_section.sh_info as usize].sh_addr + rela.r_offset;
-            let (sym, sym_name) = &load_info.syms[sym_idx];
+            let location = section_base + rela.r_offset;
+            let (sym, sym_name) = match load_info.symbol(sym_idx) {
+                Ok(symbol) => symbol,
+                Err(err) => {
+                    progress.fail(progress.context(rela, ""), err)?;
+                    continue;
+                }
+            };
 
-            let reloc_type = Arm64RelTy::try_from(rel_type).map_err(|_| {
-                ModuleErr::RelocationFailed(format!("Invalid relocation type: {}", rel_type))
-            })?;
+            let Ok(reloc_type) = Arm64RelTy::try_from(rel_type) else {
+                progress.fail(
+                    progress.context(rela, sym_name),
+                    ModuleErr::UnsupportedRelocation {
+                        arch: "AArch64",
+                        reloc_type: rel_type,
+                    },
+                )?;
+                continue;
+            };
             // val corresponds to (S + A) in the AArch64 ELF document.
-            let target_addr = sym.st_value.wrapping_add(rela.r_addend as u64);
+            let mut target_addr = sym.st_value.wrapping_add(rela.r_addend as u64);
+            if reloc_type.is_tprel() {
+                // S is the offset of the variable in the module's TLS block
+                let Some(tls_base) = load_info.tls_base else {
+                    module_log!(
+                        error,
+                        module.name(),
+                        sym = sym_name,
+                        "{:?} needs a TLS block, but the host provides none",
+                        reloc_type
+                    );
+                    progress.fail(progress.context(rela, sym_name), ModuleErr::TlsNotSupported)?;
+                    continue;
+                };
+                target_addr = target_addr.wrapping_add(tls_base);
+            }
+            if matches!(
+                reloc_type,
+                Arm64RelTy::R_AARCH64_CALL26 | Arm64RelTy::R_AARCH64_JUMP26
+            ) && !BRANCH26_RANGE.contains(&(target_addr.wrapping_sub(location) as i64))
+            {
+                // There are no PLT veneers yet, the instruction is left untouched
+                module_log!(
+                    error,
+                    module.name(),
+                    sym = sym_name,
+                    "{:?} target {:#x} is out of the ±128MB range of {:#x}",
+                    reloc_type,
+                    target_addr,
+                    location
+                );
+                progress.fail(
+                    progress.context(rela, sym_name),
+                    ModuleErr::RelocationFailed(format!(
+                        "{:?} to '{}' at {:#x} is out of the ±128MB branch range",
+                        reloc_type, sym_name, location
+                    )),
+                )?;
+                continue;
+            }
 
             // Perform the static relocation.
-            log::info!(
-                "[{}]: Applying relocation {:?} at location {:#x} with target addr {:#x}",
+            module_log!(
+                info,
                 module.name(),
+                "Applying relocation {:?} at location {:#x} with target addr {:#x}",
                 reloc_type,
                 location,
                 target_addr
             );
 
-            let res = reloc_type.apply_relocation(location, target_addr);
+            let res = Ptr::bounded(location, bounds.clone())
+                .and_then(|ptr| reloc_type.apply_relocation(ptr, target_addr));
             match res {
-                Err(e) => {
-                    log::error!("[{}]: ({}) {:?}", module.name(), sym_name, e);
-                    return Err(e);
-                }
-                Ok(_) => { /* Successfully applied relocation */ }
+                Err(e) => progress.fail(progress.context(rela, sym_name), e)?,
+                Ok(_) => progress.applied += 1,
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::ModuleLoadInfoBuilder;
+    use alloc::vec::Vec;
+    use goblin::elf::header::EM_AARCH64;
+    use goblin::elf::sym::Sym;
+    use goblin::elf64::reloc::Rela;
+
+    fn rela(r_offset: u64, reloc: Arm64RelTy, r_addend: i64) -> Rela {
+        Rela {
+            r_offset,
+            r_info: (1 << 32) | reloc as u64,
+            r_addend,
+        }
+    }
+
+    /// Apply `reloc` against the symbol at `value` to a zeroed `.data`
+    fn apply_data(reloc: Arm64RelTy, value: u64, r_addend: i64) -> (Result<()>, [u8; 8]) {
+        let mut data = [0u8; 8];
+        let sym = Sym {
+            st_value: value,
+            ..Default::default()
+        };
+        let res = ModuleLoadInfoBuilder::new(EM_AARCH64)
+            .with_section(".data", &mut data, 8)
+            .with_symbol("var", sym)
+            .apply(1, &[rela(0, reloc, r_addend)]);
+        (res, data)
+    }
+
+    fn is_overflow(res: &Result<()>) -> bool {
+        matches!(
+            res,
+            Err(ModuleErr::RelocationEntry { err, .. })
+                if matches!(**err, ModuleErr::RelocationFailed(_))
+        )
+    }
+
+    #[test]
+    fn abs32_abs16() {
+        let (res, data) = apply_data(Arm64RelTy::R_AARCH64_ABS32, 0xffff_0000, 0xfff0);
+        res.unwrap();
+        assert_eq!(data[..4], 0xffff_fff0u32.to_le_bytes());
+        assert_eq!(data[4..], [0; 4]);
+
+        let (res, data) = apply_data(Arm64RelTy::R_AARCH64_ABS16, 0xff00, 0xf0);
+        res.unwrap();
+        assert_eq!(data[..2], 0xfff0u16.to_le_bytes());
+        assert_eq!(data[2..], [0; 6]);
+
+        // S + A above the unsigned maximum of the field, or below 0
+        for (reloc, value, addend) in [
+            (Arm64RelTy::R_AARCH64_ABS32, 0xffff_ffff, 1),
+            (Arm64RelTy::R_AARCH64_ABS32, 0, -1),
+            (Arm64RelTy::R_AARCH64_ABS16, 0xffff, 1),
+            (Arm64RelTy::R_AARCH64_ABS16, 0, -1),
+        ] {
+            let (res, _) = apply_data(reloc, value, addend);
+            assert!(is_overflow(&res), "{:?} of {:#x}{:+}", reloc, value, addend);
+        }
+    }
+
+    /// Apply a local-exec TLS sequence against the variable at offset `value`
+    /// of the TLS block at `tls_base`, returning the patched instructions
+    fn apply_tprel(
+        insns: &[u32],
+        relocs: &[Arm64RelTy],
+        value: u64,
+        tls_base: Option<u64>,
+    ) -> (Result<()>, Vec<u32>) {
+        let mut text: Vec<u8> = insns.iter().flat_map(|insn| insn.to_le_bytes()).collect();
+        let size = text.len();
+        let relas: Vec<Rela> = relocs
+            .iter()
+            .enumerate()
+            .map(|(i, &reloc)| rela(4 * i as u64, reloc, 0))
+            .collect();
+        let sym = Sym {
+            st_value: value,
+            ..Default::default()
+        };
+        let mut builder = ModuleLoadInfoBuilder::new(EM_AARCH64)
+            .with_section(".text", &mut text, size)
+            .with_symbol("var", sym);
+        if let Some(tls_base) = tls_base {
+            builder = builder.with_tls_base(tls_base);
+        }
+        let res = builder.apply(1, &relas);
+        let insns = text
+            .chunks_exact(4)
+            .map(|insn| u32::from_le_bytes(insn.try_into().unwrap()))
+            .collect();
+        (res, insns)
+    }
+
+    #[test]
+    fn tprel() {
+        // movz x0, #0, lsl #32; movk x0, #0, lsl #16; movk x0, #0
+        let (res, insns) = apply_tprel(
+            &[0xd2c0_0000, 0xf2a0_0000, 0xf280_0000],
+            &[
+                Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G2,
+                Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G1_NC,
+                Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G0_NC,
+            ],
+            0x1_2345_6780,
+            Some(0x10),
+        );
+        res.unwrap();
+        // TPREL = 0x1_2345_6790
+        assert_eq!(
+            insns,
+            [
+                0xd2c0_0000 | 1 << 5,
+                0xf2a0_0000 | 0x2345 << 5,
+                0xf280_0000 | 0x6790 << 5
+            ]
+        );
+
+        // movz x0, #0, lsl #16; movk x0, #0; add x0, x0, #0, lsl #12;
+        // add x0, x0, #0
+        let (res, insns) = apply_tprel(
+            &[0xd2a0_0000, 0xf280_0000, 0x9140_0000, 0x9100_0000],
+            &[
+                Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G1,
+                Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G0_NC,
+                Arm64RelTy::R_AARCH64_TLSLE_ADD_TPREL_HI12,
+                Arm64RelTy::R_AARCH64_TLSLE_ADD_TPREL_LO12_NC,
+            ],
+            0x12_3450,
+            Some(0x10),
+        );
+        res.unwrap();
+        // TPREL = 0x12_3460
+        assert_eq!(
+            insns,
+            [
+                0xd2a0_0000 | 0x12 << 5,
+                0xf280_0000 | 0x3460 << 5,
+                0x9140_0000 | 0x123 << 10,
+                0x9100_0000 | 0x460 << 10
+            ]
+        );
+
+        // movz x0, #0; add x0, x0, #0
+        let (res, insns) = apply_tprel(
+            &[0xd280_0000, 0x9100_0000],
+            &[
+                Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G0,
+                Arm64RelTy::R_AARCH64_TLSLE_ADD_TPREL_LO12,
+            ],
+            0x450,
+            Some(0x10),
+        );
+        res.unwrap();
+        assert_eq!(insns, [0xd280_0000 | 0x460 << 5, 0x9100_0000 | 0x460 << 10]);
+
+        // The checked forms reject offsets wider than their field
+        for reloc in [
+            Arm64RelTy::R_AARCH64_TLSLE_MOVW_TPREL_G0,
+            Arm64RelTy::R_AARCH64_TLSLE_ADD_TPREL_LO12,
+        ] {
+            let (res, _) = apply_tprel(&[0xd280_0000], &[reloc], 0x1_0000, Some(0));
+            assert!(is_overflow(&res), "{:?}", reloc);
+        }
+    }
+
+    #[test]
+    fn tprel_without_tls_block() {
+        let (res, insns) = apply_tprel(
+            &[0x9100_0000],
+            &[Arm64RelTy::R_AARCH64_TLSLE_ADD_TPREL_LO12_NC],
+            0x460,
+            None,
+        );
+        assert!(matches!(
+            res,
+            Err(ModuleErr::RelocationEntry { ref err, ref symbol, .. })
+                if matches!(**err, ModuleErr::TlsNotSupported) && symbol == "var"
+        ));
+        assert_eq!(insns, [0x9100_0000]);
+    }
+}
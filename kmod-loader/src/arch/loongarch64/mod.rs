@@ -147,12 +147,20 @@ const fn unsigned_imm_check(value: u64, bits: u32) -> bool {
     value < limit
 }
 
-fn module_emit_got_entry() -> u64 {
-    unimplemented!("module_emit_got_entry is not implemented yet");
+/// Module GOT entries are not supported yet, so GOT relocations are not either
+fn module_emit_got_entry(reloc: Loongarch64RelocationType) -> Result<u64> {
+    Err(ModuleErr::UnsupportedRelocation {
+        arch: "LoongArch",
+        reloc_type: reloc as u32,
+    })
 }
 
-fn module_emit_plt_entry() -> u64 {
-    unimplemented!("module_emit_plt_entry is not implemented yet");
+/// Module PLT entries are not supported yet, a call must reach its target
+fn module_emit_plt_entry(offset: i64) -> Result<u64> {
+    Err(ModuleErr::RelocationFailed(format!(
+        "R_LARCH_SOP_PUSH_PLT_PCREL offset {:#x} is out of range and PLT entries are not supported",
+        offset
+    )))
 }
 
 fn rela_stack_push(
@@ -195,6 +203,37 @@ fn rela_stack_pop(
 }
 
 impl Loongarch64RelocationType {
+    /// Whether the relocation belongs to a TLS model other than local-exec,
+    /// or its relaxable form, which the loader does not support
+    fn is_tls(&self) -> bool {
+        matches!(
+            *self as u32,
+            // TLS_DTPMOD32..=TLS_DTPREL64, TLS_DESC32/64, SOP_PUSH_TLS_*,
+            // TLS_IE_PC_HI20..=TLS_GD_HI20 and TLS_DESC_PC_HI20..
+            6..=9 | 13..=14 | 26..=28 | 87..=98 | 111..=126
+        )
+    }
+
+    /// Whether the relocation takes the thread pointer offset TPREL(S + A)
+    /// instead of an address.
+    ///
+    /// LoongArch uses TLS variant I with no thread control block in front:
+    /// `$tp` points at the static TLS blocks, so the module's block starts at
+    /// the offset returned by [`KernelModuleHelper::tls_block_offset`] and
+    /// TPREL(S + A) is that offset plus the offset of the variable inside
+    /// the block.
+    fn is_tprel(&self) -> bool {
+        matches!(
+            self,
+            LaRelTy::R_LARCH_TLS_TPREL32
+                | LaRelTy::R_LARCH_TLS_TPREL64
+                | LaRelTy::R_LARCH_TLS_LE_HI20
+                | LaRelTy::R_LARCH_TLS_LE_LO12
+                | LaRelTy::R_LARCH_TLS_LE64_LO20
+                | LaRelTy::R_LARCH_TLS_LE64_HI12
+        )
+    }
+
     /// See <https://elixir.bootlin.com/linux/v6.6/source/arch/loongarch/kernel/module.c#L278>
     fn apply_r_larch_b26(&self, location: Ptr, address: u64) -> Result<()> {
         let mut offset = address as i64 - location.0 as i64;
@@ -219,7 +258,7 @@ impl Loongarch64RelocationType {
                 offset, self
             )));
         }
-        let instruction = location.read::<u32>();
+        let instruction = location.read_checked::<u32>()?;
 
         offset = offset >> 2;
 
@@ -228,7 +267,7 @@ impl Loongarch64RelocationType {
         inst.set_immediate_l(offset as u32 & 0xFFFF);
         inst.set_immediate_h(((offset as u32) >> 16) & 0x3FF);
 
-        location.write::<u32>(inst.into_bits());
+        location.write_checked::<u32>(inst.into_bits())?;
 
         Ok(())
     }
@@ -240,7 +279,7 @@ impl Loongarch64RelocationType {
         _rela_stack_top: &mut usize,
         _rela_stack: &[i64; RELA_STACK_DEPTH],
     ) -> Result<()> {
-        let inst = location.read::<u32>();
+        let inst = location.read_checked::<u32>()?;
         // Use s32 for a sign-extension deliberately.
         // s32 offset_hi20 = (void *)((v + 0x800) & ~0xfff) -
         //   (void *)((Elf_Addr)location & ~0xfff);
@@ -286,21 +325,60 @@ impl Loongarch64RelocationType {
                 )));
             }
         };
-        location.write::<u32>(new_inst_val);
+        location.write_checked::<u32>(new_inst_val)?;
+        Ok(())
+    }
+
+    /// Absolute address of the symbol, or its thread pointer offset for the
+    /// `TLS_LE` relocations, split over `lu12i.w`/`ori` and extended to 64
+    /// bits by `lu32i.d`/`lu52i.d`, see the LoongArch ELF psABI
+    fn apply_r_larch_abs(&self, location: Ptr, address: u64) -> Result<()> {
+        let inst = location.read_checked::<u32>()?;
+        let new_inst_val = match *self {
+            LaRelTy::R_LARCH_ABS_HI20 | LaRelTy::R_LARCH_TLS_LE_HI20 => {
+                let mut inst = reg1i20_format::from_bits(inst);
+                inst.set_immediate((address >> 12) as u32 & 0xFFFFF);
+                inst.into_bits()
+            }
+            LaRelTy::R_LARCH_ABS_LO12 | LaRelTy::R_LARCH_TLS_LE_LO12 => {
+                let mut inst = reg2i12_format::from_bits(inst);
+                inst.set_immediate(address as u32 & 0xFFF);
+                inst.into_bits()
+            }
+            LaRelTy::R_LARCH_ABS64_LO20 | LaRelTy::R_LARCH_TLS_LE64_LO20 => {
+                let mut inst = reg1i20_format::from_bits(inst);
+                inst.set_immediate((address >> 32) as u32 & 0xFFFFF);
+                inst.into_bits()
+            }
+            LaRelTy::R_LARCH_ABS64_HI12 | LaRelTy::R_LARCH_TLS_LE64_HI12 => {
+                let mut inst = reg2i12_format::from_bits(inst);
+                inst.set_immediate((address >> 52) as u32 & 0xFFF);
+                inst.into_bits()
+            }
+            _ => unreachable!(),
+        };
+        location.write_checked::<u32>(new_inst_val)?;
         Ok(())
     }
 
     /// See <https://elixir.bootlin.com/linux/v6.6/source/arch/loongarch/kernel/module.c#L370>
     fn apply_r_larch_32_pcrel(&self, location: Ptr, address: u64) -> Result<()> {
         let offset = address as i64 - location.0 as i64;
-        location.write::<u32>(offset as u32);
+        if i32::try_from(offset).is_err() {
+            return Err(ModuleErr::RelocationFailed(format!(
+                "offset = {:#x} overflow! dangerous R_LARCH_32_PCREL relocation at PC = {:p}",
+                offset,
+                location.as_ptr::<u32>()
+            )));
+        }
+        location.write_checked::<u32>(offset as u32)?;
         Ok(())
     }
 
     /// See <https://elixir.bootlin.com/linux/v6.6/source/arch/loongarch/kernel/module.c#L379>
     fn apply_r_larch_64_pcrel(&self, location: Ptr, address: u64) -> Result<()> {
         let offset = address as i64 - location.0 as i64;
-        location.write::<u64>(offset as u64);
+        location.write_checked::<u64>(offset as u64)?;
         Ok(())
     }
 
@@ -311,9 +389,7 @@ impl Loongarch64RelocationType {
         rela_stack_top: &mut usize,
         rela_stack: &[i64; RELA_STACK_DEPTH],
     ) -> Result<()> {
-        // TODO: module_emit_got_entry
-        log::error!("apply_r_larch_got_pc is not implemented yet");
-        let got = module_emit_got_entry();
+        let got = module_emit_got_entry(*self)?;
         let new_ty = match self {
             Loongarch64RelocationType::R_LARCH_GOT_PC_HI20 => {
                 Loongarch64RelocationType::R_LARCH_PCALA_LO12
@@ -336,12 +412,7 @@ impl Loongarch64RelocationType {
     ) -> Result<()> {
         let offset = address as i64 - location.0 as i64;
         if offset < -(SZ_128M as i64) || offset >= SZ_128M as i64 {
-            // TODO: module_emit_plt_entry
-            log::error!(
-                "R_LARCH_SOP_PUSH_PLT_PCREL relocation out of range: offset = {}",
-                offset
-            );
-            address = module_emit_plt_entry();
+            address = module_emit_plt_entry(offset)?;
         }
         return self.apply_r_larch_sop_push_pcrel(location, address, rela_stack_top, rela_stack);
     }
@@ -460,7 +531,7 @@ impl Loongarch64RelocationType {
             ))
         };
 
-        let inst = location.read::<u32>();
+        let inst = location.read_checked::<u32>()?;
         match *self {
             LaRelTy::R_LARCH_SOP_POP_32_U_10_12 => {
                 if !unsigned_imm_check(opr1 as u64, 12) {
@@ -469,7 +540,7 @@ impl Loongarch64RelocationType {
                 // (*(uint32_t *) PC) [21 ... 10] = opr [11 ... 0]
                 let mut inst = reg2i12_format::from_bits(inst);
                 inst.set_immediate(opr1 as u32 & 0xFFF);
-                location.write::<u32>(inst.into_bits());
+                location.write_checked::<u32>(inst.into_bits())?;
                 Ok(())
             }
             LaRelTy::R_LARCH_SOP_POP_32_S_10_12 => {
@@ -478,7 +549,7 @@ impl Loongarch64RelocationType {
                 }
                 let mut inst = reg2i12_format::from_bits(inst);
                 inst.set_immediate(opr1 as u32 & 0xFFF);
-                location.write::<u32>(inst.into_bits());
+                location.write_checked::<u32>(inst.into_bits())?;
                 Ok(())
             }
             LaRelTy::R_LARCH_SOP_POP_32_S_10_16 => {
@@ -487,7 +558,7 @@ impl Loongarch64RelocationType {
                 }
                 let mut inst = reg2i16_format::from_bits(inst);
                 inst.set_immediate(opr1 as u32 & 0xFFFF);
-                location.write::<u32>(inst.into_bits());
+                location.write_checked::<u32>(inst.into_bits())?;
                 Ok(())
             }
 
@@ -502,7 +573,7 @@ impl Loongarch64RelocationType {
                 let mut inst = reg1i21_format::from_bits(inst);
                 inst.set_immediate_l(opr1 as u32 & 0xFFFF);
                 inst.set_immediate_h(((opr1 as u32) >> 16) & 0x1F);
-                location.write::<u32>(inst.into_bits());
+                location.write_checked::<u32>(inst.into_bits())?;
                 Ok(())
             }
 
@@ -517,7 +588,7 @@ impl Loongarch64RelocationType {
                 let mut inst = reg0i26_format::from_bits(inst);
                 inst.set_immediate_l(opr1 as u32 & 0xFFFF);
                 inst.set_immediate_h(((opr1 as u32) >> 16) & 0x3FF);
-                location.write::<u32>(inst.into_bits());
+                location.write_checked::<u32>(inst.into_bits())?;
                 Ok(())
             }
 
@@ -525,13 +596,14 @@ impl Loongarch64RelocationType {
                 if !unsigned_imm_check(opr1 as u64, 32) {
                     return Err(overflow());
                 }
-                location.write::<u32>(opr1 as u32);
+                location.write_checked::<u32>(opr1 as u32)?;
                 Ok(())
             }
 
-            _ => {
-                unimplemented!("Relocation type {:?} not implemented yet", self);
-            }
+            _ => Err(ModuleErr::UnsupportedRelocation {
+                arch: "LoongArch",
+                reloc_type: *self as u32,
+            }),
         }
     }
 
@@ -539,27 +611,27 @@ impl Loongarch64RelocationType {
     fn apply_r_larch_add_sub(&self, location: Ptr, address: u64) -> Result<()> {
         match *self {
             LaRelTy::R_LARCH_ADD32 => {
-                let original = location.read::<i32>();
+                let original = location.read_checked::<i32>()?;
                 let result = original.wrapping_add(address as i32);
-                location.write(result);
+                location.write_checked(result)?;
                 Ok(())
             }
             LaRelTy::R_LARCH_ADD64 => {
-                let original = location.read::<i64>();
+                let original = location.read_checked::<i64>()?;
                 let result = original.wrapping_add(address as i64);
-                location.write(result);
+                location.write_checked(result)?;
                 Ok(())
             }
             LaRelTy::R_LARCH_SUB32 => {
-                let original = location.read::<i32>();
+                let original = location.read_checked::<i32>()?;
                 let result = original.wrapping_sub(address as i32);
-                location.write(result);
+                location.write_checked(result)?;
                 Ok(())
             }
             LaRelTy::R_LARCH_SUB64 => {
-                let original = location.read::<i64>();
+                let original = location.read_checked::<i64>()?;
                 let result = original.wrapping_sub(address as i64);
-                location.write(result);
+                location.write_checked(result)?;
                 Ok(())
             }
             _ => {
@@ -577,23 +649,29 @@ impl Loongarch64RelocationType {
     }
 
     fn apply_r_larch_32(&self, location: Ptr, address: u64) -> Result<()> {
-        location.write::<u32>(address as u32);
+        location.write_checked::<u32>(address as u32)?;
         Ok(())
     }
 
     fn apply_r_larch_64(&self, location: Ptr, address: u64) -> Result<()> {
-        location.write::<u64>(address as u64);
+        location.write_checked::<u64>(address as u64)?;
         Ok(())
     }
 
-    pub fn apply_relocation(
+    pub(crate) fn apply_relocation(
         &self,
-        location: u64,
+        location: Ptr,
         address: u64,
         rela_stack_top: &mut usize,
         rela_stack: &mut [i64; RELA_STACK_DEPTH],
     ) -> Result<()> {
-        let location = Ptr(location);
+        if self.is_tls() {
+            log::error!(
+                "{:?} needs a dynamic TLS block, which is not supported",
+                self
+            );
+            return Err(ModuleErr::TlsNotSupported);
+        }
 
         match *self {
             LaRelTy::R_LARCH_B26 => self.apply_r_larch_b26(location, address),
@@ -605,8 +683,12 @@ impl Loongarch64RelocationType {
             }
 
             LaRelTy::R_LARCH_NONE => self.apply_r_larch_none(location, address),
-            LaRelTy::R_LARCH_32 => self.apply_r_larch_32(location, address),
-            LaRelTy::R_LARCH_64 => self.apply_r_larch_64(location, address),
+            LaRelTy::R_LARCH_32 | LaRelTy::R_LARCH_TLS_TPREL32 => {
+                self.apply_r_larch_32(location, address)
+            }
+            LaRelTy::R_LARCH_64 | LaRelTy::R_LARCH_TLS_TPREL64 => {
+                self.apply_r_larch_64(location, address)
+            }
             LaRelTy::R_LARCH_MARK_LA | LaRelTy::R_LARCH_MARK_PCREL => {
                 self.apply_r_larch_none(location, address)
             }
@@ -659,17 +741,97 @@ impl Loongarch64RelocationType {
                 self.apply_r_larch_pcala(location, address, rela_stack_top, rela_stack)
             }
 
+            LaRelTy::R_LARCH_ABS_HI20
+            | LaRelTy::R_LARCH_ABS_LO12
+            | LaRelTy::R_LARCH_ABS64_LO20
+            | LaRelTy::R_LARCH_ABS64_HI12
+            | LaRelTy::R_LARCH_TLS_LE_HI20
+            | LaRelTy::R_LARCH_TLS_LE_LO12
+            | LaRelTy::R_LARCH_TLS_LE64_LO20
+            | LaRelTy::R_LARCH_TLS_LE64_HI12 => self.apply_r_larch_abs(location, address),
+
             LaRelTy::R_LARCH_32_PCREL => self.apply_r_larch_32_pcrel(location, address),
             LaRelTy::R_LARCH_64_PCREL => self.apply_r_larch_64_pcrel(location, address),
-            _ => {
-                unimplemented!("Relocation type {:?} not implemented yet", self);
-            }
+            _ => Err(ModuleErr::UnsupportedRelocation {
+                arch: "LoongArch",
+                reloc_type: *self as u32,
+            }),
         }
     }
 }
 
+/// Known-good encodings checked by [`Loongarch64ArchRelocate::self_test`]
+const SELF_TEST_FIXTURES: &[RelocFixture<LaRelTy>] = &[
+    // b 0
+    RelocFixture {
+        reloc: LaRelTy::R_LARCH_B26,
+        insn: 0x50000000,
+        target: FixtureTarget::PcRel(0x1000),
+        expected: 0x50100000,
+    },
+    RelocFixture {
+        reloc: LaRelTy::R_LARCH_B26,
+        insn: 0x50000000,
+        target: FixtureTarget::PcRel(-4),
+        expected: 0x53ffffff,
+    },
+    // pcalau12i a0, 0
+    RelocFixture {
+        reloc: LaRelTy::R_LARCH_PCALA_HI20,
+        insn: 0x1a000004,
+        target: FixtureTarget::PageRel(0x5abc),
+        expected: 0x1a0000c4,
+    },
+    // addi.d a0, a0, 0
+    RelocFixture {
+        reloc: LaRelTy::R_LARCH_PCALA_LO12,
+        insn: 0x02c00084,
+        target: FixtureTarget::Abs(0x12345abc),
+        expected: 0x02eaf084,
+    },
+    // lu12i.w a0, 0
+    RelocFixture {
+        reloc: LaRelTy::R_LARCH_ABS_HI20,
+        insn: 0x14000004,
+        target: FixtureTarget::Abs(0x12345abc),
+        expected: 0x142468a4,
+    },
+    // ori a0, a0, 0
+    RelocFixture {
+        reloc: LaRelTy::R_LARCH_ABS_LO12,
+        insn: 0x03800084,
+        target: FixtureTarget::Abs(0x12345abc),
+        expected: 0x03aaf084,
+    },
+    // lu32i.d a0, 0
+    RelocFixture {
+        reloc: LaRelTy::R_LARCH_ABS64_LO20,
+        insn: 0x16000004,
+        target: FixtureTarget::Abs(0x0123_4567_89ab_cdef),
+        expected: 0x1668ace4,
+    },
+    // lu52i.d a0, a0, 0
+    RelocFixture {
+        reloc: LaRelTy::R_LARCH_ABS64_HI12,
+        insn: 0x03000084,
+        target: FixtureTarget::Abs(0x0123_4567_89ab_cdef),
+        expected: 0x03004884,
+    },
+];
+
 pub struct Loongarch64ArchRelocate;
 
+impl Loongarch64ArchRelocate {
+    /// Check the relocation handlers against known-good encodings
+    pub fn self_test() -> Result<()> {
+        run_self_test(SELF_TEST_FIXTURES, |reloc, location, address| {
+            let mut rela_stack = [0i64; RELA_STACK_DEPTH];
+            let mut rela_stack_top = 0;
+            reloc.apply_relocation(location, address, &mut rela_stack_top, &mut rela_stack)
+        })
+    }
+}
+
 impl Loongarch64ArchRelocate {
     /// See <https://elixir.bootlin.com/linux/v6.6/source/arch/loongarch/kernel/module.c#L421>
     pub fn apply_relocate_add<H: KernelModuleHelper>(
@@ -678,7 +840,12 @@ impl Loongarch64ArchRelocate {
         sechdrs: &[SectionHeader],
         load_info: &ModuleLoadInfo,
         module: &ModuleOwner<H>,
+        progress: &mut RelocProgress,
     ) -> Result<()> {
+        let section_base = section_header(sechdrs, rel_section.sh_info as usize)?.sh_addr;
+        let bounds = module
+            .section_bounds(section_base)
+            .unwrap_or(section_base..section_base);
         let mut rela_stack = [0i64; RELA_STACK_DEPTH];
         let mut rela_stack_top = 0;
 
@@ -687,8 +854,14 @@ impl Loongarch64ArchRelocate {
             let sym_idx = get_rela_sym_idx(rela.r_info);
 
             // This is where to make the change
-            let location = sechdrs[rel_section.sh_info as usize].sh_addr + rela.r_offset;
-            let (sym, sym_name) = &load_info.syms[sym_idx];
+            let location = section_base + rela.r_offset;
+            let (sym, sym_name) = match load_info.symbol(sym_idx) {
+                Ok(symbol) => symbol,
+                Err(err) => {
+                    progress.fail(progress.context(rela, ""), err)?;
+                    continue;
+                }
+            };
 
             // if (IS_ERR_VALUE(sym->st_value)) {
             //     /* Ignore unresolved weak symbol */
@@ -698,32 +871,139 @@ impl Loongarch64ArchRelocate {
             //     return -ENOENT;
             // }
 
-            let reloc_type = Loongarch64RelocationType::try_from(rel_type).map_err(|_| {
-                ModuleErr::RelocationFailed(format!("Invalid relocation type: {}", rel_type))
-            })?;
-
-            let target_addr = sym.st_value.wrapping_add(rela.r_addend as u64);
+            let Ok(reloc_type) = Loongarch64RelocationType::try_from(rel_type) else {
+                progress.fail(
+                    progress.context(rela, sym_name),
+                    ModuleErr::UnsupportedRelocation {
+                        arch: "LoongArch",
+                        reloc_type: rel_type,
+                    },
+                )?;
+                continue;
+            };
+
+            let mut target_addr = sym.st_value.wrapping_add(rela.r_addend as u64);
+            if reloc_type.is_tprel() {
+                // S is the offset of the variable in the module's TLS block
+                let Some(tls_base) = load_info.tls_base else {
+                    module_log!(
+                        error,
+                        module.name(),
+                        sym = sym_name,
+                        "{:?} needs a TLS block, but the host provides none",
+                        reloc_type
+                    );
+                    progress.fail(progress.context(rela, sym_name), ModuleErr::TlsNotSupported)?;
+                    continue;
+                };
+                target_addr = target_addr.wrapping_add(tls_base);
+            }
             log::trace!(
                 "Applying relocation: type = {:?}, location = {:#x}, target_addr = {:#x}",
                 reloc_type,
                 location,
                 target_addr,
             );
-            let res = reloc_type.apply_relocation(
-                location,
-                target_addr as u64,
-                &mut rela_stack_top,
-                &mut rela_stack,
-            );
+            let res = Ptr::bounded(location, bounds.clone()).and_then(|ptr| {
+                reloc_type.apply_relocation(
+                    ptr,
+                    target_addr as u64,
+                    &mut rela_stack_top,
+                    &mut rela_stack,
+                )
+            });
 
             match res {
-                Err(e) => {
-                    log::error!("[{}]: ({}) {:?}", module.name(), sym_name, e);
-                    return Err(e);
-                }
-                Ok(_) => { /* Successfully applied relocation */ }
+                Err(e) => progress.fail(progress.context(rela, sym_name), e)?,
+                Ok(_) => progress.applied += 1,
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::ModuleLoadInfoBuilder;
+    use goblin::elf::header::EM_LOONGARCH;
+    use goblin::elf::sym::Sym;
+    use goblin::elf64::reloc::Rela;
+
+    fn rela(r_offset: u64, reloc: LaRelTy, r_addend: i64) -> Rela {
+        Rela {
+            r_offset,
+            r_info: (1 << 32) | reloc as u64,
+            r_addend,
+        }
+    }
+
+    #[test]
+    fn tls_le() {
+        // lu12i.w $a0, 0; ori $a0, $a0, 0; then a .word and a .dword
+        let mut data = [0u8; 24];
+        data[..4].copy_from_slice(&0x14000004u32.to_le_bytes());
+        data[4..8].copy_from_slice(&0x03800084u32.to_le_bytes());
+        // The variable is at 0x345 in the module's TLS block
+        let var = Sym {
+            st_value: 0x345,
+            ..Default::default()
+        };
+        let relas = [
+            rela(0, LaRelTy::R_LARCH_TLS_LE_HI20, 0),
+            rela(4, LaRelTy::R_LARCH_TLS_LE_LO12, 0),
+            rela(8, LaRelTy::R_LARCH_TLS_TPREL32, 0),
+            rela(16, LaRelTy::R_LARCH_TLS_TPREL64, 4),
+        ];
+        let builder = ModuleLoadInfoBuilder::new(EM_LOONGARCH)
+            .with_section(".text", &mut data, 24)
+            .with_symbol("var", var);
+        assert!(matches!(
+            builder.apply(1, &relas),
+            Err(ModuleErr::RelocationEntry { ref err, .. })
+                if matches!(**err, ModuleErr::TlsNotSupported)
+        ));
+
+        builder.with_tls_base(0x12000).apply(1, &relas).unwrap();
+        let word = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        assert_eq!(word(0), 0x14000004 | (0x12 << 5));
+        assert_eq!(word(4), 0x03800084 | (0x345 << 10));
+        assert_eq!(word(8), 0x12345);
+        assert_eq!(u64::from_le_bytes(data[16..].try_into().unwrap()), 0x12349);
+    }
+
+    #[test]
+    fn pcrel32() {
+        // S + A - P of a .word at P, relative to P
+        let apply = |offset: i64, addend: i64| {
+            let mut data = [0u8; 8];
+            let place = data.as_ptr() as u64;
+            let var = Sym {
+                st_value: place.wrapping_add((offset - addend) as u64),
+                ..Default::default()
+            };
+            let res = ModuleLoadInfoBuilder::new(EM_LOONGARCH)
+                .with_section(".data", &mut data, 8)
+                .with_symbol("var", var)
+                .apply(1, &[rela(0, LaRelTy::R_LARCH_32_PCREL, addend)]);
+            res.map(|()| data)
+        };
+
+        for (offset, addend) in [(0x1234, 4), (i32::MAX as i64, 0), (i32::MIN as i64, -8)] {
+            let data = apply(offset, addend).unwrap();
+            assert_eq!(data[..4], (offset as i32).to_le_bytes());
+            assert_eq!(data[4..], [0; 4]);
+        }
+        for offset in [i32::MAX as i64 + 1, i32::MIN as i64 - 1] {
+            assert!(
+                matches!(
+                    apply(offset, 0),
+                    Err(ModuleErr::RelocationEntry { ref err, .. })
+                        if matches!(**err, ModuleErr::RelocationFailed(_))
+                ),
+                "accepted an offset of {:#x}",
+                offset
+            );
+        }
+    }
+}
@@ -3,6 +3,11 @@ mod loongarch64;
 mod riscv64;
 mod x86_64;
 
+use alloc::{format, string::String, vec::Vec};
+use core::ops::Range;
+
+use crate::loader::{KernelModuleHelper, ModuleLoadInfo, ModuleOwner};
+
 pub use aarch64::{Aarch64ArchRelocate, Aarch64RelocationType};
 pub use loongarch64::{Loongarch64ArchRelocate, Loongarch64RelocationType};
 pub use riscv64::{Riscv64ArchRelocate, Riscv64RelocationType};
@@ -18,30 +23,372 @@ const fn get_rela_sym_idx(r_info: u64) -> usize {
     (r_info >> 32) as usize
 }
 
+/// Get the section header at `index`, e.g. the target `sh_info` of a
+/// relocation section, failing instead of panicking if it is out of range
+pub(crate) fn section_header(
+    sechdrs: &[goblin::elf::SectionHeader],
+    index: usize,
+) -> crate::Result<&goblin::elf::SectionHeader> {
+    sechdrs.get(index).ok_or_else(|| {
+        log::error!(
+            "Section {} not found, the module has {} sections",
+            index,
+            sechdrs.len()
+        );
+        crate::ModuleErr::SectionNotFound { index }
+    })
+}
+
+/// Apply the entries of the relocation section `rel_section` with the
+/// handlers of the architecture `machine`
+pub(crate) fn apply_relocate_add<H: KernelModuleHelper>(
+    machine: u16,
+    rela_list: &[goblin::elf64::reloc::Rela],
+    rel_section: &goblin::elf::SectionHeader,
+    sechdrs: &[goblin::elf::SectionHeader],
+    load_info: &ModuleLoadInfo,
+    module: &ModuleOwner<H>,
+    progress: &mut RelocProgress,
+) -> crate::Result<()> {
+    match machine {
+        goblin::elf::header::EM_RISCV => Riscv64ArchRelocate::apply_relocate_add(
+            rela_list,
+            rel_section,
+            sechdrs,
+            load_info,
+            module,
+            progress,
+        ),
+        goblin::elf::header::EM_LOONGARCH => Loongarch64ArchRelocate::apply_relocate_add(
+            rela_list,
+            rel_section,
+            sechdrs,
+            load_info,
+            module,
+            progress,
+        ),
+        goblin::elf::header::EM_AARCH64 => Aarch64ArchRelocate::apply_relocate_add(
+            rela_list,
+            rel_section,
+            sechdrs,
+            load_info,
+            module,
+            progress,
+        ),
+        goblin::elf::header::EM_X86_64 => X86_64ArchRelocate::apply_relocate_add(
+            rela_list,
+            rel_section,
+            sechdrs,
+            load_info,
+            module,
+            progress,
+        ),
+        _ => {
+            log::error!("Relocations for machine type {} not supported", machine);
+            Err(crate::ModuleErr::UnsupportedArch)
+        }
+    }
+}
+
+/// Name of the relocation type `rel_type` of the architecture `e_machine`,
+/// `Unknown(n)` if it is not known
+pub(crate) fn rela_type_name(e_machine: u16, rel_type: u32) -> String {
+    let name = match e_machine {
+        goblin::elf::header::EM_X86_64 => {
+            X86_64RelocationType::try_from(rel_type).map(|ty| format!("{ty:?}"))
+        }
+        goblin::elf::header::EM_RISCV => {
+            Riscv64RelocationType::try_from(rel_type).map(|ty| format!("{ty:?}"))
+        }
+        goblin::elf::header::EM_LOONGARCH => {
+            Loongarch64RelocationType::try_from(rel_type).map(|ty| format!("{ty:?}"))
+        }
+        goblin::elf::header::EM_AARCH64 => {
+            Aarch64RelocationType::try_from(rel_type).map(|ty| format!("{ty:?}"))
+        }
+        _ => Err(rel_type),
+    };
+    name.unwrap_or_else(|_| format!("Unknown({})", rel_type))
+}
+
+/// A relocation entry decoded without applying it, see
+/// [`ModuleLoadInfo::relocation`](crate::loader::ModuleLoadInfo::relocation)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelocationEntry {
+    /// Offset of the patched location in the target section
+    pub offset: u64,
+    pub type_code: u32,
+    /// e.g. `R_RISCV_CALL_PLT`, or `Unknown(n)`
+    pub type_name: String,
+    pub sym_idx: usize,
+    /// Demangled name of the symbol, empty for symbol 0
+    pub sym_name: String,
+    pub addend: i64,
+}
+
+impl RelocationEntry {
+    pub(crate) fn decode(
+        e_machine: u16,
+        rela: &goblin::elf64::reloc::Rela,
+        sym_name: String,
+    ) -> Self {
+        let type_code = get_rela_type(rela.r_info);
+        RelocationEntry {
+            offset: rela.r_offset,
+            type_code,
+            type_name: rela_type_name(e_machine, type_code),
+            sym_idx: get_rela_sym_idx(rela.r_info),
+            sym_name,
+            addend: rela.r_addend,
+        }
+    }
+}
+
+/// Where a relocation entry comes from, logged when it fails so the entry
+/// can be found in the disassembly of the module
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RelocationContext<'a> {
+    /// Index of the relocation section
+    pub section: usize,
+    /// `r_offset` of the entry in the section it patches
+    pub offset: u64,
+    /// e.g. `R_RISCV_CALL_PLT`, or `Unknown(n)`
+    pub type_name: String,
+    /// Name of the symbol, empty if it is not known yet
+    pub symbol: &'a str,
+}
+
+impl RelocationContext<'_> {
+    /// Attach the context to `err`
+    fn wrap(self, err: crate::ModuleErr) -> crate::ModuleErr {
+        crate::ModuleErr::RelocationEntry {
+            section: self.section,
+            offset: self.offset,
+            type_name: self.type_name,
+            symbol: self.symbol.into(),
+            err: alloc::boxed::Box::new(err),
+        }
+    }
+}
+
+impl core::fmt::Display for RelocationContext<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "relocation section {}, r_offset {:#x}, {}",
+            self.section, self.offset, self.type_name
+        )?;
+        if !self.symbol.is_empty() {
+            write!(f, " against '{}'", self.symbol)?;
+        }
+        Ok(())
+    }
+}
+
+/// Progress of applying the entries of one relocation section
+pub struct RelocProgress<'a> {
+    /// Number of entries applied successfully
+    pub(crate) applied: usize,
+    /// Index of the relocation section
+    section: usize,
+    /// `e_machine` of the module, to name the relocation types
+    machine: u16,
+    /// Name of the module, the target of the failure logs
+    module: &'a str,
+    /// Failed entries, when failures are collected instead of stopping at
+    /// the first one
+    failures: Option<&'a mut Vec<crate::ModuleErr>>,
+}
+
+impl<'a> RelocProgress<'a> {
+    pub(crate) fn new(
+        section: usize,
+        machine: u16,
+        module: &'a str,
+        failures: Option<&'a mut Vec<crate::ModuleErr>>,
+    ) -> Self {
+        RelocProgress {
+            applied: 0,
+            section,
+            machine,
+            module,
+            failures,
+        }
+    }
+
+    /// Describe the entry `rela` of the section, referencing `symbol`
+    pub(crate) fn context<'s>(
+        &self,
+        rela: &goblin::elf64::reloc::Rela,
+        symbol: &'s str,
+    ) -> RelocationContext<'s> {
+        RelocationContext {
+            section: self.section,
+            offset: rela.r_offset,
+            type_name: rela_type_name(self.machine, get_rela_type(rela.r_info)),
+            symbol,
+        }
+    }
+
+    /// Record a failed entry, logging where it comes from. The error, with
+    /// the context attached, is handed back, stopping the section, unless
+    /// failures are collected.
+    pub(crate) fn fail(
+        &mut self,
+        ctx: RelocationContext,
+        err: crate::ModuleErr,
+    ) -> crate::Result<()> {
+        module_log!(error, self.module, "{}: {:?}", ctx, err);
+        let err = ctx.wrap(err);
+        match &mut self.failures {
+            Some(failures) => {
+                failures.push(err);
+                Ok(())
+            }
+            None => Err(err),
+        }
+    }
+}
+
+/// How the target address of a [`RelocFixture`] is derived from the location
 #[derive(Debug, Clone, Copy)]
-struct Ptr(u64);
+enum FixtureTarget {
+    /// A fixed absolute address
+    Abs(u64),
+    /// `location + offset`
+    PcRel(i64),
+    /// `(location & !0xfff) + offset`
+    PageRel(u64),
+}
+
+/// A canned relocation with its known-good result, used by the self test
+struct RelocFixture<T> {
+    reloc: T,
+    /// The original instruction(s) or data, in memory order
+    insn: u64,
+    target: FixtureTarget,
+    expected: u64,
+}
+
+/// Apply each fixture to a scratch buffer and compare with the expected bytes
+fn run_self_test<T: Copy + core::fmt::Debug>(
+    fixtures: &[RelocFixture<T>],
+    apply: impl Fn(T, Ptr, u64) -> crate::Result<()>,
+) -> crate::Result<()> {
+    for fixture in fixtures {
+        // Run at an odd address, relocations must not assume alignment
+        let mut buf = [0u8; 16];
+        let start = buf.as_mut_ptr() as u64;
+        let location = start + 1;
+        Ptr::new(location).write(fixture.insn);
+        let address = match fixture.target {
+            FixtureTarget::Abs(address) => address,
+            FixtureTarget::PcRel(offset) => location.wrapping_add(offset as u64),
+            FixtureTarget::PageRel(offset) => (location & !0xfff).wrapping_add(offset),
+        };
+        apply(
+            fixture.reloc,
+            Ptr::bounded(location, start..start + buf.len() as u64)?,
+            address,
+        )?;
+        let got = Ptr::new(location).read::<u64>();
+        if got != fixture.expected {
+            log::error!(
+                "Self test of {:?} failed: expected {:#018x}, got {:#018x}",
+                fixture.reloc,
+                fixture.expected,
+                got
+            );
+            return Err(crate::ModuleErr::RelocationFailed(alloc::format!(
+                "Self test of {:?} failed",
+                fixture.reloc
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// A possibly unaligned address, with the `[start, end)` of the memory
+/// [`Ptr::read_checked`] and [`Ptr::write_checked`] may access through it
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Ptr(u64, Option<(u64, u64)>);
 impl Ptr {
+    /// An address [`Ptr::read_checked`] and [`Ptr::write_checked`] access
+    /// unchecked, for memory the loader itself laid out, e.g. stubs
+    pub fn new(addr: u64) -> Ptr {
+        Ptr(addr, None)
+    }
+
+    /// The location of a relocation, which may only patch `bounds`, i.e. the
+    /// section it applies to
+    pub fn bounded(location: u64, bounds: Range<u64>) -> crate::Result<Ptr> {
+        let ptr = Ptr(location, Some((bounds.start, bounds.end)));
+        ptr.check_bounds(1)?;
+        Ok(ptr)
+    }
+
+    fn check_bounds(&self, len: usize) -> crate::Result<()> {
+        let Some((start, end)) = self.1 else {
+            return Ok(());
+        };
+        let in_bounds = self.0 >= start
+            && self
+                .0
+                .checked_add(len as u64)
+                .is_some_and(|write_end| write_end <= end);
+        if !in_bounds {
+            log::error!(
+                "Relocation of {:#x}..{:#x} is outside of its section [{:#x}, {:#x})",
+                self.0,
+                self.0.wrapping_add(len as u64),
+                start,
+                end
+            );
+            return Err(crate::ModuleErr::RelocationOutOfBounds { location: self.0 });
+        }
+        Ok(())
+    }
+
     fn as_ptr<T>(&self) -> *mut T {
         self.0 as *mut T
     }
 
-    /// Writes a value of type T to the pointer location
+    /// Writes a value of type T to the pointer location, failing if it does
+    /// not fit in the bounds of a [`Ptr::bounded`] location
+    pub fn write_checked<T>(&self, value: T) -> crate::Result<()> {
+        self.check_bounds(core::mem::size_of::<T>())?;
+        self.write(value);
+        Ok(())
+    }
+
+    /// Writes a value of type T to the pointer location.
+    ///
+    /// The location may be unaligned, e.g. data in `.eh_frame` or a 32-bit
+    /// instruction next to compressed ones.
     pub fn write<T>(&self, value: T) {
         unsafe {
             let ptr = self.as_ptr::<T>();
-            ptr.write(value);
+            ptr.write_unaligned(value);
         }
     }
 
+    /// Reads a value of type T from the pointer location, failing if it does
+    /// not fit in the bounds of a [`Ptr::bounded`] location
+    pub fn read_checked<T>(&self) -> crate::Result<T> {
+        self.check_bounds(core::mem::size_of::<T>())?;
+        Ok(self.read())
+    }
+
+    /// Reads a value of type T from the possibly unaligned pointer location
     pub fn read<T>(&self) -> T {
         unsafe {
             let ptr = self.as_ptr::<T>();
-            ptr.read()
+            ptr.read_unaligned()
         }
     }
 
     pub fn add(&self, offset: usize) -> Ptr {
-        Ptr(self.0 + offset as u64)
+        Ptr(self.0 + offset as u64, self.1)
     }
 
     pub fn as_slice<T>(&self, len: usize) -> &[T] {
@@ -1,9 +1,13 @@
+use alloc::collections::BTreeMap;
 use alloc::format;
 use alloc::string::ToString;
 use goblin::elf::SectionHeader;
 use int_enum::IntEnum;
 
-use crate::arch::{Ptr, get_rela_sym_idx, get_rela_type};
+use crate::arch::{
+    FixtureTarget, Ptr, RelocFixture, RelocProgress, get_rela_sym_idx, get_rela_type,
+    run_self_test, section_header,
+};
 use crate::loader::{KernelModuleHelper, ModuleLoadInfo, ModuleOwner};
 use crate::{ModuleErr, Result};
 
@@ -121,6 +125,11 @@ pub enum Riscv64RelocationType {
     R_RISCV_SET32 = 56,
 }
 
+/// Whether `offset` fits the `bits`-bit signed immediate of a branch or jump
+const fn riscv_insn_valid_offset(offset: i64, bits: u32) -> bool {
+    -(1i64 << (bits - 1)) <= offset && offset < (1i64 << (bits - 1))
+}
+
 /// The auipc+jalr instruction pair can reach any PC-relative offset
 /// in the range [-2^31 - 2^11, 2^31 - 2^11)
 const fn riscv_insn_valid_32bit_offset(offset: i64) -> bool {
@@ -130,7 +139,175 @@ const fn riscv_insn_valid_32bit_offset(offset: i64) -> bool {
     low <= offset && offset < high
 }
 
+/// Whether the absolute `address` can be built by `lui` and a 12-bit
+/// immediate, i.e. is a sign-extended 32-bit value that does not wrap when
+/// HI20 rounds it up by 0x800
+const fn riscv_abs_hi20_lo12_valid(address: u64) -> bool {
+    let address = address as i64;
+    address == address as i32 as i64 && address <= i32::MAX as i64 - 0x800
+}
+
+/// The error of a HI20/LO12 relocation `reloc` against an `address` that
+/// fails [`riscv_abs_hi20_lo12_valid`]
+fn riscv_abs_out_of_range(reloc: &str, address: u64, location: Ptr) -> ModuleErr {
+    ModuleErr::RelocationFailed(format!(
+        "{}: target {:016x} can not be addressed by lui at PC = {:p}",
+        reloc,
+        address,
+        location.as_ptr::<u32>()
+    ))
+}
+
+/// Size of a call stub, see [`emit_call_stub`]
+const CALL_STUB_SIZE: usize = 24;
+
+/// Bridge a call whose target is out of the 32-bit range of `location`,
+/// e.g. into another allocation of a fragmented module, through a stub
+/// reserved next to the calling section. Returns the stub address.
+fn emit_call_stub<H: KernelModuleHelper>(
+    module: &ModuleOwner<H>,
+    location: u64,
+    target: u64,
+) -> Result<u64> {
+    let too_large = || ModuleErr::FragmentationTooLarge { location, target };
+    let stub = module
+        .alloc_stub(location, CALL_STUB_SIZE)
+        .ok_or_else(too_large)?;
+    if !riscv_insn_valid_32bit_offset(stub as i64 - location as i64) {
+        return Err(too_large());
+    }
+    let stub_ptr = Ptr::new(stub);
+    // auipc t1, 0
+    stub_ptr.write::<u32>(0x00000317);
+    // ld t1, 16(t1)
+    stub_ptr.add(4).write::<u32>(0x01033303);
+    // jr t1
+    stub_ptr.add(8).write::<u32>(0x00030067);
+    // nop, to align the target address
+    stub_ptr.add(12).write::<u32>(0x00000013);
+    stub_ptr.add(16).write::<u64>(target);
+    Ok(stub)
+}
+
+/// A GOT reference to a symbol defined by the module itself does not need a
+/// GOT slot when the symbol is reachable with a direct PC-relative offset.
+///
+/// Only done with the `relax` feature.
+fn can_relax_got_ref(sym: &goblin::elf::sym::Sym, offset: i64) -> bool {
+    let shndx = sym.st_shndx as u32;
+    cfg!(feature = "relax")
+        && shndx != goblin::elf::section_header::SHN_UNDEF
+        && shndx < goblin::elf::section_header::SHN_LORESERVE
+        && riscv_insn_valid_32bit_offset(offset)
+}
+
+/// Rewrite `ld rd, lo12(rs1)` to `addi rd, rs1, lo12`, so the pair computes the
+/// symbol address directly instead of loading it from a GOT slot.
+fn relax_got_load(location: Ptr) -> Result<()> {
+    let inst = location.read_checked::<u32>()?;
+    // opcode = LOAD (0x03), funct3 = 0b011 (ld)
+    if inst & 0x707f != 0x3003 {
+        return Err(ModuleErr::RelocationFailed(format!(
+            "Can not relax GOT access: instruction {:08x} at {:p} is not ld",
+            inst,
+            location.as_ptr::<u32>()
+        )));
+    }
+    // opcode = OP-IMM (0x13), funct3 = 0b000 (addi)
+    location.write_checked((inst & !0x707f) | 0x13)?;
+    Ok(())
+}
+
+/// Get the runtime address of the link-time address `addr`, moved by as much
+/// as the allocated section whose link-time range contains it
+fn rebase_link_addr(sechdrs: &[SectionHeader], link_addrs: &[u64], addr: u64) -> Option<u64> {
+    sechdrs
+        .iter()
+        .zip(link_addrs)
+        .find(|&(shdr, &link_addr)| {
+            shdr.sh_flags & goblin::elf::section_header::SHF_ALLOC as u64 != 0
+                && addr >= link_addr
+                && addr - link_addr < shdr.sh_size
+        })
+        .map(|(shdr, &link_addr)| shdr.sh_addr.wrapping_add(addr - link_addr))
+}
+
+/// Size of a module GOT entry
+const GOT_ENTRY_SIZE: usize = 8;
+
+/// Get a GOT slot holding `value`, next to the section containing
+/// `location`. Relocations of the same section referencing the same value
+/// share one slot.
+///
+/// See <https://elixir.bootlin.com/linux/v6.6/source/arch/riscv/kernel/module-sections.c#L14>
+fn module_emit_got_entry<H: KernelModuleHelper>(
+    module: &ModuleOwner<H>,
+    location: u64,
+    value: u64,
+) -> Result<u64> {
+    module.emit_got_entry(location, value).ok_or_else(|| {
+        ModuleErr::RelocationFailed(format!(
+            "No room left for a GOT entry of {:#x} referenced at {:#x}",
+            value, location
+        ))
+    })
+}
+
+/// Get the address a GOT reference to `sym_name` at `location` must use,
+/// GOT[S] + A as the psABI defines it, `value` being S.
+///
+/// The kernel GOT is preferred so kernel symbols don't get duplicate slots,
+/// otherwise a module GOT entry holding `value` is emitted.
+fn got_entry<H: KernelModuleHelper>(
+    module: &ModuleOwner<H>,
+    location: u64,
+    sym_name: &str,
+    value: u64,
+    addend: i64,
+) -> Result<u64> {
+    let slot = match H::kernel_got_entry(sym_name) {
+        Some(slot) => slot,
+        None => module_emit_got_entry(module, location, value)?,
+    };
+    Ok(slot.wrapping_add(addend as u64))
+}
+
 impl Rv64RelTy {
+    /// Whether the relocation belongs to a TLS model other than local-exec,
+    /// which needs a dynamic TLS block the loader does not provide
+    fn is_tls(&self) -> bool {
+        matches!(
+            self,
+            Rv64RelTy::R_RISCV_TLS_DTPMOD32
+                | Rv64RelTy::R_RISCV_TLS_DTPMOD64
+                | Rv64RelTy::R_RISCV_TLS_DTPREL32
+                | Rv64RelTy::R_RISCV_TLS_DTPREL64
+                | Rv64RelTy::R_RISCV_TLS_GOT_HI20
+                | Rv64RelTy::R_RISCV_TLS_GD_HI20
+                | Rv64RelTy::R_RISCV_TPREL_I
+                | Rv64RelTy::R_RISCV_TPREL_S
+        )
+    }
+
+    /// Whether the relocation takes the thread pointer offset TPREL(S + A)
+    /// instead of an address.
+    ///
+    /// RISC-V uses TLS variant I with no thread control block in front: `tp`
+    /// points at the static TLS blocks, so the module's block starts at the
+    /// offset returned by [`KernelModuleHelper::tls_block_offset`] and
+    /// TPREL(S + A) is that offset plus the offset of the variable inside
+    /// the block.
+    fn is_tprel(&self) -> bool {
+        matches!(
+            self,
+            Rv64RelTy::R_RISCV_TLS_TPREL32
+                | Rv64RelTy::R_RISCV_TLS_TPREL64
+                | Rv64RelTy::R_RISCV_TPREL_HI20
+                | Rv64RelTy::R_RISCV_TPREL_LO12_I
+                | Rv64RelTy::R_RISCV_TPREL_LO12_S
+        )
+    }
+
     fn apply_r_riscv_32_rela(location: Ptr, address: u64) -> Result<()> {
         if address != address as u32 as u64 {
             return Err(ModuleErr::RelocationFailed(format!(
@@ -139,39 +316,68 @@ impl Rv64RelTy {
             )));
         }
         // Write the lower 32 bits to the location
-        location.write(address as u32);
+        location.write_checked(address as u32)?;
         Ok(())
     }
 
     fn apply_r_riscv_64_rela(location: Ptr, address: u64) -> Result<()> {
         // Write the full 64 bits to the location
-        location.write(address);
+        location.write_checked(address)?;
+        Ok(())
+    }
+
+    /// `B + A` of a position-independent module, `address` being A rebased
+    /// onto the section containing it, see [`rebase_link_addr`]
+    fn apply_r_riscv_relative_rela(location: Ptr, address: u64) -> Result<()> {
+        location.write_checked(address)?;
+        Ok(())
+    }
+
+    /// Fill a PLT GOT slot of `.rela.plt` with the resolved symbol address
+    fn apply_r_riscv_jump_slot_rela(location: Ptr, address: u64) -> Result<()> {
+        location.write_checked(address)?;
         Ok(())
     }
 
     fn apply_r_riscv_branch_rela(location: Ptr, address: u64) -> Result<()> {
         let offset = address as i64 - location.0 as i64;
+        // 13-bit immediate, +-4 KiB
+        if !riscv_insn_valid_offset(offset, 13) {
+            return Err(ModuleErr::RelocationFailed(format!(
+                "R_RISCV_BRANCH: target {:016x} is out of the +-4 KiB range from PC = {:p}",
+                address,
+                location.as_ptr::<u32>()
+            )));
+        }
 
         let imm12 = ((offset & 0x1000) << (31 - 12)) as u32;
         let imm11 = ((offset & 0x800) >> (11 - 7)) as u32;
         let imm10_5 = ((offset & 0x7e0) << (30 - 10)) as u32;
         let imm4_1 = ((offset & 0x1e) << (11 - 4)) as u32;
 
-        let original_inst = location.read::<u32>();
-        location.write((original_inst & 0x1fff07f) | imm12 | imm11 | imm10_5 | imm4_1);
+        let original_inst = location.read_checked::<u32>()?;
+        location.write_checked((original_inst & 0x1fff07f) | imm12 | imm11 | imm10_5 | imm4_1)?;
         Ok(())
     }
 
     fn apply_r_riscv_jal_rela(location: Ptr, address: u64) -> Result<()> {
         let offset = address as i64 - location.0 as i64;
+        // 21-bit immediate, +-1 MiB
+        if !riscv_insn_valid_offset(offset, 21) {
+            return Err(ModuleErr::RelocationFailed(format!(
+                "R_RISCV_JAL: target {:016x} is out of the +-1 MiB range from PC = {:p}",
+                address,
+                location.as_ptr::<u32>()
+            )));
+        }
 
         let imm20 = ((offset & 0x100000) << (31 - 20)) as u32;
         let imm19_12 = (offset & 0xff000) as u32;
         let imm11 = ((offset & 0x800) << (20 - 11)) as u32;
         let imm10_1 = ((offset & 0x7fe) << (30 - 10)) as u32;
 
-        let original_inst = location.read::<u32>();
-        location.write((original_inst & 0xFFF) | imm20 | imm19_12 | imm11 | imm10_1);
+        let original_inst = location.read_checked::<u32>()?;
+        location.write_checked((original_inst & 0xFFF) | imm20 | imm19_12 | imm11 | imm10_1)?;
         Ok(())
     }
 
@@ -183,8 +389,9 @@ impl Rv64RelTy {
         let imm4_3 = ((offset & 0x18) << (12 - 5)) as u16;
         let imm2_1 = ((offset & 0x6) << (12 - 10)) as u16;
 
-        let original_inst = location.read::<u16>();
-        location.write((original_inst & 0xe383) | imm8 | imm7_6 | imm5 | imm4_3 | imm2_1);
+        let original_inst = location.read_checked::<u16>()?;
+        location
+            .write_checked((original_inst & 0xe383) | imm8 | imm7_6 | imm5 | imm4_3 | imm2_1)?;
         Ok(())
     }
 
@@ -199,10 +406,41 @@ impl Rv64RelTy {
         let imm4 = ((offset & 0x10) << (12 - 5)) as u16;
         let imm3_1 = ((offset & 0xe) << (12 - 10)) as u16;
 
-        let original_inst = location.read::<u16>();
-        location.write(
+        let original_inst = location.read_checked::<u16>()?;
+        location.write_checked(
             (original_inst & 0xe003) | imm11 | imm10 | imm9_8 | imm7 | imm6 | imm5 | imm4 | imm3_1,
-        );
+        )?;
+        Ok(())
+    }
+
+    /// `c.lui` can not encode a zero immediate, which happens when the high
+    /// part rounds to 0, so the instruction becomes `c.li rd, 0` instead.
+    ///
+    /// Like `lui`, the immediate is sign-extended, so the target must be a
+    /// sign-extended 32-bit address, see [`riscv_abs_hi20_lo12_valid`].
+    fn apply_r_riscv_rvc_lui_rela(location: Ptr, address: u64) -> Result<()> {
+        const MATCH_C_LUI: u16 = 0x6001;
+        const MATCH_C_LI: u16 = 0x4001;
+        // imm[17] at bit 12 and imm[16:12] at bits 6:2
+        const CI_IMM_MASK: u16 = 0x107c;
+
+        let hi20 = (address as i64).wrapping_add(0x800) >> 12;
+        if !riscv_abs_hi20_lo12_valid(address) || !(-32..32).contains(&hi20) {
+            return Err(ModuleErr::RelocationFailed(format!(
+                "R_RISCV_RVC_LUI: target {:016x} does not fit in a c.lui at PC = {:p}",
+                address,
+                location.as_ptr::<u16>()
+            )));
+        }
+        let original_inst = location.read_checked::<u16>()?;
+        if hi20 == 0 {
+            location.write_checked(((original_inst & !MATCH_C_LUI) | MATCH_C_LI) & !CI_IMM_MASK)?;
+            return Ok(());
+        }
+        let imm = hi20 as u16;
+        location.write_checked(
+            (original_inst & !CI_IMM_MASK) | ((imm & 0x20) << 7) | ((imm & 0x1f) << 2),
+        )?;
         Ok(())
     }
 
@@ -216,16 +454,16 @@ impl Rv64RelTy {
             )));
         }
         let hi20 = (offset + 0x800) & 0xfffff000;
-        let original_inst = location.read::<u32>();
-        location.write((original_inst & 0xfff) | (hi20 as u32));
+        let original_inst = location.read_checked::<u32>()?;
+        location.write_checked((original_inst & 0xfff) | (hi20 as u32))?;
         Ok(())
     }
 
     fn apply_r_riscv_pcrel_lo12_i_rela(location: Ptr, address: u64) -> Result<()> {
         // address is the lo12 value to fill. It is calculated before calling this handler.
 
-        let original_inst = location.read::<u32>();
-        location.write((original_inst & 0xfffff) | ((address as u32 & 0xfff) << 20));
+        let original_inst = location.read_checked::<u32>()?;
+        location.write_checked((original_inst & 0xfffff) | ((address as u32 & 0xfff) << 20))?;
         Ok(())
     }
 
@@ -235,54 +473,70 @@ impl Rv64RelTy {
         let imm11_5 = (address as u32 & 0xfe0) << (31 - 11);
         let imm4_0 = (address as u32 & 0x1f) << (11 - 4);
 
-        let original_inst = location.read::<u32>();
-        location.write((original_inst & 0x1fff07f) | imm11_5 | imm4_0);
+        let original_inst = location.read_checked::<u32>()?;
+        location.write_checked((original_inst & 0x1fff07f) | imm11_5 | imm4_0)?;
         Ok(())
     }
 
     /// See <https://elixir.bootlin.com/linux/v6.6/source/arch/riscv/kernel/module.c#L149>
     fn apply_r_riscv_hi20_rela(location: Ptr, address: u64) -> Result<()> {
+        if !riscv_abs_hi20_lo12_valid(address) {
+            return Err(riscv_abs_out_of_range("R_RISCV_HI20", address, location));
+        }
         // Compute HI20 using 32-bit signed arithmetic, matching C implementation
         let address32 = address as i32;
         // Mirror C: ((s32)v + 0x800) & 0xfffff000
         // Do the wrapping add in i32, then mask in u32 to avoid overflowing literal issues.
         let hi20 = ((address32.wrapping_add(0x800)) as u32) & 0xfffff000u32;
-        let original_inst = location.read::<u32>();
-        location.write((original_inst & 0xfff) | hi20);
+        let original_inst = location.read_checked::<u32>()?;
+        location.write_checked((original_inst & 0xfff) | hi20)?;
         Ok(())
     }
 
+    /// The CPU sign-extends the 12-bit immediate, so a `lo12` with bit 11 set
+    /// is negative. HI20 already rounds up by 0x800 to compensate, so `lo12`
+    /// must be derived with the very same wrapping math to keep `hi20 + lo12`
+    /// equal to the target address.
     fn apply_r_riscv_lo12_i_rela(location: Ptr, address: u64) -> Result<()> {
-        // Skip medlow checking because of filtering by HI20 already
-
+        // Checked here too, the LO12 is patched independently of its HI20
+        if !riscv_abs_hi20_lo12_valid(address) {
+            return Err(riscv_abs_out_of_range("R_RISCV_LO12_I", address, location));
+        }
         let address = address as i32;
-        let hi20 = (address + 0x800) & (0xfffff000_u32 as i32);
-        let lo12 = address - hi20;
-        let original_inst = location.read::<u32>();
-        location.write((original_inst & 0xfffff) | ((lo12 as u32 & 0xfff) << 20));
+        let hi20 = address.wrapping_add(0x800) & (0xfffff000_u32 as i32);
+        let lo12 = address.wrapping_sub(hi20);
+        let original_inst = location.read_checked::<u32>()?;
+        location.write_checked((original_inst & 0xfffff) | ((lo12 as u32 & 0xfff) << 20))?;
         Ok(())
     }
 
     fn apply_r_riscv_lo12_s_rela(location: Ptr, address: u64) -> Result<()> {
-        // Skip medlow checking because of filtering by HI20 already
-
+        if !riscv_abs_hi20_lo12_valid(address) {
+            return Err(riscv_abs_out_of_range("R_RISCV_LO12_S", address, location));
+        }
+        // Same wrapping math as LO12_I, so the pair stays consistent with HI20
         let address = address as i32;
-        let hi20 = (address + 0x800) & (0xfffff000_u32 as i32);
-        let lo12 = address - hi20;
+        let hi20 = address.wrapping_add(0x800) & (0xfffff000_u32 as i32);
+        let lo12 = address.wrapping_sub(hi20);
         let imm11_5 = (lo12 as u32 & 0xfe0) << (31 - 11);
         let imm4_0 = (lo12 as u32 & 0x1f) << (11 - 4);
-        let original_inst = location.read::<u32>();
-        location.write((original_inst & 0x1fff07f) | imm11_5 | imm4_0);
+        let original_inst = location.read_checked::<u32>()?;
+        location.write_checked((original_inst & 0x1fff07f) | imm11_5 | imm4_0)?;
         Ok(())
     }
 
     /// See <https://elixir.bootlin.com/linux/v6.6/source/arch/riscv/kernel/module.c#L188>
-    fn apply_r_riscv_got_hi20_rela(_location: Ptr, _address: u64) -> Result<()> {
-        unimplemented!("R_RISCV_GOT_HI20 relocation not implemented yet");
-        // Always emit the got entry
+    ///
+    /// `address` is the GOT slot, see [`got_entry`], which is addressed like a
+    /// PC-relative symbol.
+    fn apply_r_riscv_got_hi20_rela(location: Ptr, address: u64) -> Result<()> {
+        Self::apply_r_riscv_pcrel_hi20_rela(location, address)
     }
 
     /// See <https://elixir.bootlin.com/linux/v6.6/source/arch/riscv/kernel/module.c#L210>
+    ///
+    /// `R_RISCV_CALL` is deprecated and treated exactly like `R_RISCV_CALL_PLT`:
+    /// the loader has no separate PLT, both patch the auipc+jalr pair directly.
     fn apply_r_riscv_call_plt_rela(location: Ptr, address: u64) -> Result<()> {
         let offset = address as i64 - location.0 as i64;
         if !riscv_insn_valid_32bit_offset(offset) {
@@ -295,89 +549,177 @@ impl Rv64RelTy {
         }
         let hi20 = (offset + 0x800) & 0xfffff000;
         let lo12 = (offset - hi20) & 0xfff;
-        let original_auipc = location.read::<u32>();
-        location.write((original_auipc & 0xfff) | (hi20 as u32));
+        // Both instructions must be in bounds before either is patched
         let original_jalr_ptr = location.add(4);
-        let original_jalr = original_jalr_ptr.read::<u32>();
-        original_jalr_ptr.write((original_jalr & 0xfffff) | ((lo12 as u32) << 20));
+        let original_auipc = location.read_checked::<u32>()?;
+        let original_jalr = original_jalr_ptr.read_checked::<u32>()?;
+        location.write_checked((original_auipc & 0xfff) | (hi20 as u32))?;
+        original_jalr_ptr.write_checked((original_jalr & 0xfffff) | ((lo12 as u32) << 20))?;
         Ok(())
     }
 
-    fn apply_r_riscv_call_rela(location: Ptr, address: u64) -> Result<()> {
-        let offset = address as i64 - location.0 as i64;
-        if !riscv_insn_valid_32bit_offset(offset) {
+    fn apply_r_riscv_relax_rela(_location: Ptr, _address: u64) -> Result<()> {
+        Ok(())
+    }
+
+    /// `R_RISCV_TPREL_ADD` only marks the `add rd, rs1, tp` of a TLS LE
+    /// sequence for linker relaxation, nothing needs to be patched.
+    fn apply_r_riscv_tprel_add_rela(location: Ptr, _address: u64) -> Result<()> {
+        const TP: u32 = 4;
+        let insn = location.read_checked::<u16>()? as u32;
+        let is_tp_add = if insn & 0x3 == 0x3 {
+            // add rd, rs1, tp
+            let insn = location.read_checked::<u32>()?;
+            insn & 0xfff0707f == (TP << 20) | 0x33
+        } else {
+            // c.add rd, tp
+            insn & 0xf07f == 0x9002 | (TP << 2)
+        };
+        if !is_tp_add {
             return Err(ModuleErr::RelocationFailed(format!(
-                "R_RISCV_CALL: target {:016x} can not be addressed by the 32-bit offset from PC = {:p}",
-                address,
+                "R_RISCV_TPREL_ADD does not mark a thread pointer add at PC = {:p}",
                 location.as_ptr::<u32>()
             )));
         }
-        let hi20 = (offset + 0x800) & 0xfffff000;
-        let lo12 = (offset - hi20) & 0xfff;
-        let original_auipc = location.read::<u32>();
-        location.write((original_auipc & 0xfff) | (hi20 as u32));
-        let original_jalr_ptr = location.add(4);
-        let original_jalr = original_jalr_ptr.read::<u32>();
-        original_jalr_ptr.write((original_jalr & 0xfffff) | ((lo12 as u32) << 20));
         Ok(())
     }
 
-    fn apply_r_riscv_relax_rela(_location: Ptr, _address: u64) -> Result<()> {
+    /// `location` starts `address` bytes of nop padding, that a relaxing
+    /// linker shrinks so the following instruction lands on the next power of
+    /// two boundary.
+    ///
+    /// Linker relaxation is not done, the padding is kept as is and the code
+    /// stays correct, only possibly less aligned than requested. The boundary
+    /// must still be reachable by dropping padding, otherwise the module was
+    /// laid out with a different alignment than it was assembled for.
+    fn apply_r_riscv_align_rela(location: Ptr, address: u64) -> Result<()> {
+        // The padding is the alignment minus the smallest instruction size
+        let Some(align) = address
+            .checked_add(2)
+            .and_then(u64::checked_next_power_of_two)
+        else {
+            return Err(ModuleErr::RelocationFailed(format!(
+                "R_RISCV_ALIGN of {} bytes at PC = {:p} overflows the alignment",
+                address,
+                location.as_ptr::<u32>()
+            )));
+        };
+        let gap = location
+            .0
+            .checked_next_multiple_of(align)
+            .map(|boundary| boundary - location.0);
+        if !location.0.is_multiple_of(2) || gap.is_none_or(|gap| gap > address) {
+            return Err(ModuleErr::RelocationFailed(format!(
+                "R_RISCV_ALIGN of {} bytes can not reach a {} byte boundary from PC = {:p}",
+                address,
+                align,
+                location.as_ptr::<u32>()
+            )));
+        }
         Ok(())
     }
 
-    fn apply_r_riscv_align_rela(location: Ptr, _address: u64) -> Result<()> {
-        Err(ModuleErr::RelocationFailed(format!(
-            "The unexpected relocation type 'R_RISCV_ALIGN' from PC = {:p}",
-            location.as_ptr::<u32>()
-        )))
-    }
-
     fn apply_r_riscv_add16_rela(location: Ptr, address: u64) -> Result<()> {
-        let value = location.read::<u16>();
-        location.write(value.wrapping_add(address as u16));
+        let value = location.read_checked::<u16>()?;
+        location.write_checked(value.wrapping_add(address as u16))?;
         Ok(())
     }
 
     fn apply_r_riscv_add32_rela(location: Ptr, address: u64) -> Result<()> {
-        let value = location.read::<u32>();
-        location.write(value.wrapping_add(address as u32));
+        let value = location.read_checked::<u32>()?;
+        location.write_checked(value.wrapping_add(address as u32))?;
         Ok(())
     }
 
     fn apply_r_riscv_add64_rela(location: Ptr, address: u64) -> Result<()> {
-        let value = location.read::<u64>();
-        location.write(value.wrapping_add(address));
+        let value = location.read_checked::<u64>()?;
+        location.write_checked(value.wrapping_add(address))?;
         Ok(())
     }
 
     fn apply_r_riscv_sub16_rela(location: Ptr, address: u64) -> Result<()> {
-        let value = location.read::<u16>();
-        location.write(value.wrapping_sub(address as u16));
+        let value = location.read_checked::<u16>()?;
+        location.write_checked(value.wrapping_sub(address as u16))?;
         Ok(())
     }
 
     fn apply_r_riscv_sub32_rela(location: Ptr, address: u64) -> Result<()> {
-        let value = location.read::<u32>();
-        location.write(value.wrapping_sub(address as u32));
+        let value = location.read_checked::<u32>()?;
+        location.write_checked(value.wrapping_sub(address as u32))?;
         Ok(())
     }
 
     fn apply_r_riscv_sub64_rela(location: Ptr, address: u64) -> Result<()> {
-        let value = location.read::<u64>();
-        location.write(value.wrapping_sub(address));
+        let value = location.read_checked::<u64>()?;
+        location.write_checked(value.wrapping_sub(address))?;
+        Ok(())
+    }
+
+    /// Only the low 6 bits of the byte are the field, the upper 2 bits belong
+    /// to the DWARF opcode and are kept.
+    fn apply_r_riscv_sub6_rela(location: Ptr, address: u64) -> Result<()> {
+        let value = location.read_checked::<u8>()?;
+        location.write_checked((value & 0xc0) | (value.wrapping_sub(address as u8) & 0x3f))?;
+        Ok(())
+    }
+
+    /// Like [`Self::apply_r_riscv_sub6_rela`], only the low 6 bits are set
+    fn apply_r_riscv_set6_rela(location: Ptr, address: u64) -> Result<()> {
+        let value = location.read_checked::<u8>()?;
+        location.write_checked((value & 0xc0) | (address as u8 & 0x3f))?;
+        Ok(())
+    }
+
+    fn apply_r_riscv_set8_rela(location: Ptr, address: u64) -> Result<()> {
+        location.write_checked(address as u8)?;
+        Ok(())
+    }
+
+    fn apply_r_riscv_set16_rela(location: Ptr, address: u64) -> Result<()> {
+        location.write_checked(address as u16)?;
         Ok(())
     }
 
-    pub fn apply_relocation(&self, location: u64, address: u64) -> Result<()> {
-        let location = Ptr(location);
+    fn apply_r_riscv_set32_rela(location: Ptr, address: u64) -> Result<()> {
+        location.write_checked(address as u32)?;
+        Ok(())
+    }
+
+    /// Whether a SUB relocation would take the field at `location` below zero,
+    /// i.e. the label difference it completes is negative
+    fn sub_underflows(&self, location: Ptr, address: u64) -> Result<bool> {
+        Ok(match self {
+            Rv64RelTy::R_RISCV_SUB6 => location.read_checked::<u8>()? & 0x3f < address as u8 & 0x3f,
+            Rv64RelTy::R_RISCV_SUB16 => location.read_checked::<u16>()? < address as u16,
+            Rv64RelTy::R_RISCV_SUB32 => location.read_checked::<u32>()? < address as u32,
+            Rv64RelTy::R_RISCV_SUB64 => location.read_checked::<u64>()? < address,
+            _ => false,
+        })
+    }
+
+    /// Patch `location` with `address`, i.e. S + A
+    pub(crate) fn apply_relocation(&self, location: Ptr, address: u64) -> Result<()> {
+        if self.is_tls() {
+            log::error!(
+                "{:?} needs a dynamic TLS block, which is not supported",
+                self
+            );
+            return Err(ModuleErr::TlsNotSupported);
+        }
         match self {
-            Rv64RelTy::R_RISCV_32 => Self::apply_r_riscv_32_rela(location, address),
-            Rv64RelTy::R_RISCV_64 => Self::apply_r_riscv_64_rela(location, address),
+            Rv64RelTy::R_RISCV_32 | Rv64RelTy::R_RISCV_TLS_TPREL32 => {
+                Self::apply_r_riscv_32_rela(location, address)
+            }
+            Rv64RelTy::R_RISCV_64 | Rv64RelTy::R_RISCV_TLS_TPREL64 => {
+                Self::apply_r_riscv_64_rela(location, address)
+            }
+            Rv64RelTy::R_RISCV_RELATIVE => Self::apply_r_riscv_relative_rela(location, address),
+            Rv64RelTy::R_RISCV_JUMP_SLOT => Self::apply_r_riscv_jump_slot_rela(location, address),
             Rv64RelTy::R_RISCV_BRANCH => Self::apply_r_riscv_branch_rela(location, address),
             Rv64RelTy::R_RISCV_JAL => Self::apply_r_riscv_jal_rela(location, address),
             Rv64RelTy::R_RISCV_RVC_BRANCH => Self::apply_r_riscv_rvc_branch_rela(location, address),
             Rv64RelTy::R_RISCV_RVC_JUMP => Self::apply_r_riscv_rvc_jump_rela(location, address),
+            Rv64RelTy::R_RISCV_RVC_LUI => Self::apply_r_riscv_rvc_lui_rela(location, address),
             Rv64RelTy::R_RISCV_PCREL_HI20 => Self::apply_r_riscv_pcrel_hi20_rela(location, address),
             Rv64RelTy::R_RISCV_PCREL_LO12_I => {
                 Self::apply_r_riscv_pcrel_lo12_i_rela(location, address)
@@ -385,29 +727,557 @@ impl Rv64RelTy {
             Rv64RelTy::R_RISCV_PCREL_LO12_S => {
                 Self::apply_r_riscv_pcrel_lo12_s_rela(location, address)
             }
-            Rv64RelTy::R_RISCV_HI20 => Self::apply_r_riscv_hi20_rela(location, address),
-            Rv64RelTy::R_RISCV_LO12_I => Self::apply_r_riscv_lo12_i_rela(location, address),
-            Rv64RelTy::R_RISCV_LO12_S => Self::apply_r_riscv_lo12_s_rela(location, address),
+            // lui/addi/sw of the thread pointer offset, `add rd, rd, tp`
+            // in between
+            Rv64RelTy::R_RISCV_HI20 | Rv64RelTy::R_RISCV_TPREL_HI20 => {
+                Self::apply_r_riscv_hi20_rela(location, address)
+            }
+            Rv64RelTy::R_RISCV_LO12_I | Rv64RelTy::R_RISCV_TPREL_LO12_I => {
+                Self::apply_r_riscv_lo12_i_rela(location, address)
+            }
+            Rv64RelTy::R_RISCV_LO12_S | Rv64RelTy::R_RISCV_TPREL_LO12_S => {
+                Self::apply_r_riscv_lo12_s_rela(location, address)
+            }
             Rv64RelTy::R_RISCV_GOT_HI20 => Self::apply_r_riscv_got_hi20_rela(location, address),
-            Rv64RelTy::R_RISCV_CALL_PLT => Self::apply_r_riscv_call_plt_rela(location, address),
-            Rv64RelTy::R_RISCV_CALL => Self::apply_r_riscv_call_rela(location, address),
+            Rv64RelTy::R_RISCV_CALL_PLT | Rv64RelTy::R_RISCV_CALL => {
+                Self::apply_r_riscv_call_plt_rela(location, address)
+            }
             Rv64RelTy::R_RISCV_RELAX => Self::apply_r_riscv_relax_rela(location, address),
+            Rv64RelTy::R_RISCV_TPREL_ADD => Self::apply_r_riscv_tprel_add_rela(location, address),
             Rv64RelTy::R_RISCV_ALIGN => Self::apply_r_riscv_align_rela(location, address),
             Rv64RelTy::R_RISCV_ADD16 => Self::apply_r_riscv_add16_rela(location, address),
             Rv64RelTy::R_RISCV_ADD32 => Self::apply_r_riscv_add32_rela(location, address),
             Rv64RelTy::R_RISCV_ADD64 => Self::apply_r_riscv_add64_rela(location, address),
+            Rv64RelTy::R_RISCV_SUB6 => Self::apply_r_riscv_sub6_rela(location, address),
             Rv64RelTy::R_RISCV_SUB16 => Self::apply_r_riscv_sub16_rela(location, address),
             Rv64RelTy::R_RISCV_SUB32 => Self::apply_r_riscv_sub32_rela(location, address),
             Rv64RelTy::R_RISCV_SUB64 => Self::apply_r_riscv_sub64_rela(location, address),
-            _ => unimplemented!("RISC-V relocation application not implemented yet"),
+            Rv64RelTy::R_RISCV_SET6 => Self::apply_r_riscv_set6_rela(location, address),
+            Rv64RelTy::R_RISCV_SET8 => Self::apply_r_riscv_set8_rela(location, address),
+            Rv64RelTy::R_RISCV_SET16 => Self::apply_r_riscv_set16_rela(location, address),
+            Rv64RelTy::R_RISCV_SET32 => Self::apply_r_riscv_set32_rela(location, address),
+            _ => Err(ModuleErr::UnsupportedRelocation {
+                arch: "RISC-V",
+                reloc_type: *self as u32,
+            }),
         }
     }
 }
 
 type Rv64RelTy = Riscv64RelocationType;
 
+/// Known-good encodings checked by [`Riscv64ArchRelocate::self_test`]
+const SELF_TEST_FIXTURES: &[RelocFixture<Rv64RelTy>] = &[
+    // auipc ra, 0; jalr ra, 0(ra)
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_CALL_PLT,
+        insn: 0x000080e7_00000097,
+        target: FixtureTarget::PcRel(0x1000_0804),
+        expected: 0x804080e7_10001097,
+    },
+    // beq a0, a1, 0
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_BRANCH,
+        insn: 0x00b50063,
+        target: FixtureTarget::PcRel(-0x800),
+        expected: 0x80b500e3,
+    },
+    // jal ra, 0
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_JAL,
+        insn: 0x000000ef,
+        target: FixtureTarget::PcRel(0x1234),
+        expected: 0x234010ef,
+    },
+    // auipc a0, 0
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_PCREL_HI20,
+        insn: 0x00000517,
+        target: FixtureTarget::PcRel(-0x12345),
+        expected: 0xfffee517,
+    },
+    // lui a0, 0
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_HI20,
+        insn: 0x00000537,
+        target: FixtureTarget::Abs(0x12345abc),
+        expected: 0x12346537,
+    },
+    // addi a0, a0, 0
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_LO12_I,
+        insn: 0x00050513,
+        target: FixtureTarget::Abs(0x12345abc),
+        expected: 0xabc50513,
+    },
+    // sd a1, 0(a0)
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_LO12_S,
+        insn: 0x00b53023,
+        target: FixtureTarget::Abs(0x12345abc),
+        expected: 0xaab53e23,
+    }, // auipc ra, 0; jalr ra, 0(ra), lo12 at the negative end
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_CALL_PLT,
+        insn: 0x000080e7_00000097,
+        target: FixtureTarget::PcRel(-0x800),
+        expected: 0x800080e7_00000097,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_CALL,
+        insn: 0x000080e7_00000097,
+        target: FixtureTarget::PcRel(0x7ff),
+        expected: 0x7ff080e7_00000097,
+    },
+    // beq a0, a1, 0 at the largest forward offset
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_BRANCH,
+        insn: 0x00b50063,
+        target: FixtureTarget::PcRel(0xffe),
+        expected: 0x7eb50fe3,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_BRANCH,
+        insn: 0x00b50063,
+        target: FixtureTarget::PcRel(0x10),
+        expected: 0x00b50863,
+    },
+    // jal ra, 0 at the largest backward offset
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_JAL,
+        insn: 0x000000ef,
+        target: FixtureTarget::PcRel(-0x100000),
+        expected: 0x800000ef,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_JAL,
+        insn: 0x000000ef,
+        target: FixtureTarget::PcRel(-2),
+        expected: 0xfffff0ef,
+    },
+    // c.beqz a0, 0
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_RVC_BRANCH,
+        insn: 0xc101,
+        target: FixtureTarget::PcRel(0x20),
+        expected: 0xc105,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_RVC_BRANCH,
+        insn: 0xc101,
+        target: FixtureTarget::PcRel(-0x100),
+        expected: 0xd101,
+    },
+    // c.j 0
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_RVC_JUMP,
+        insn: 0xa001,
+        target: FixtureTarget::PcRel(0x7fe),
+        expected: 0xaffd,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_RVC_JUMP,
+        insn: 0xa001,
+        target: FixtureTarget::PcRel(-0x800),
+        expected: 0xb001,
+    },
+    // c.lui a0, 0
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_RVC_LUI,
+        insn: 0x6501,
+        target: FixtureTarget::Abs(0x1000),
+        expected: 0x6505,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_RVC_LUI,
+        insn: 0x6501,
+        target: FixtureTarget::Abs(0xffff_ffff_ffff_f000),
+        expected: 0x757d,
+    },
+    // the ends of the 6-bit immediate, 31 and -32
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_RVC_LUI,
+        insn: 0x6501,
+        target: FixtureTarget::Abs(0x1f7ff),
+        expected: 0x657d,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_RVC_LUI,
+        insn: 0x6501,
+        target: FixtureTarget::Abs(0xffff_ffff_fffe_0000),
+        expected: 0x7501,
+    },
+    // the reserved zero immediate turns it into c.li a0, 0
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_RVC_LUI,
+        insn: 0x6501,
+        target: FixtureTarget::Abs(0x7ff),
+        expected: 0x4501,
+    },
+    // auipc a0, 0 rounding up to the next page
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_PCREL_HI20,
+        insn: 0x00000517,
+        target: FixtureTarget::PcRel(0x800),
+        expected: 0x00001517,
+    },
+    // auipc a0, 0 to a GOT slot
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_GOT_HI20,
+        insn: 0x00000517,
+        target: FixtureTarget::PcRel(0x1000),
+        expected: 0x00001517,
+    },
+    // addi a0, a0, 0 with a precomputed lo12
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_PCREL_LO12_I,
+        insn: 0x00050513,
+        target: FixtureTarget::Abs(0x7ff),
+        expected: 0x7ff50513,
+    },
+    // sd a1, 0(a0) with a precomputed lo12
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_PCREL_LO12_S,
+        insn: 0x00b53023,
+        target: FixtureTarget::Abs(0x123),
+        expected: 0x12b531a3,
+    },
+    // lui a0, 0 with the sign bit set
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_HI20,
+        insn: 0x00000537,
+        target: FixtureTarget::Abs(0xffff_ffff_8000_0000),
+        expected: 0x80000537,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_HI20,
+        insn: 0x00000537,
+        target: FixtureTarget::Abs(0xfff),
+        expected: 0x00001537,
+    },
+    // addi a0, a0, 0 with a negative lo12
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_LO12_I,
+        insn: 0x00050513,
+        target: FixtureTarget::Abs(0xfff),
+        expected: 0xfff50513,
+    },
+    // sd a1, 0(a0) with the smallest lo12
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_LO12_S,
+        insn: 0x00b53023,
+        target: FixtureTarget::Abs(0x800),
+        expected: 0x80b53023,
+    },
+    // lui a0, 0; sd a1, 0(a0) addressing 0xffffffff80001abc: lui sign-extends
+    // to 0xffffffff80002000 and the negative lo12 -0x544 steps back from it
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_HI20,
+        insn: 0x00000537,
+        target: FixtureTarget::Abs(0xffffffff80001abc),
+        expected: 0x80002537,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_LO12_S,
+        insn: 0x00b53023,
+        target: FixtureTarget::Abs(0xffffffff80001abc),
+        expected: 0xaab53e23,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_32,
+        insn: 0,
+        target: FixtureTarget::Abs(0xdeadbeef),
+        expected: 0xdeadbeef,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_64,
+        insn: 0,
+        target: FixtureTarget::Abs(0x01234567_89abcdef),
+        expected: 0x01234567_89abcdef,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_JUMP_SLOT,
+        insn: 0,
+        target: FixtureTarget::Abs(0xffffffff_80201000),
+        expected: 0xffffffff_80201000,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_ADD32,
+        insn: 0x10,
+        target: FixtureTarget::Abs(0x20),
+        expected: 0x30,
+    },
+    // ADD accumulates onto the existing value, wrapping within its width
+    // and leaving the following bytes alone
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_ADD32,
+        insn: 0xaaaa_aaaa_ffff_fff0,
+        target: FixtureTarget::Abs(0x1_0000_0020),
+        expected: 0xaaaa_aaaa_0000_0010,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_ADD16,
+        insn: 0xaaaa_aaaa_aaaa_1234,
+        target: FixtureTarget::Abs(0x1111),
+        expected: 0xaaaa_aaaa_aaaa_2345,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_ADD64,
+        insn: 0x8000_0000_0000_0010,
+        target: FixtureTarget::Abs(0x8000_0000_0000_0020),
+        expected: 0x30,
+    },
+    // `.word end - start` is an ADD32 of `end` followed by a SUB32 of `start`
+    // on the same word, the second one must see the first one's result
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_ADD32,
+        insn: 0,
+        target: FixtureTarget::Abs(0x80002010),
+        expected: 0x80002010,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_SUB32,
+        insn: 0x80002010,
+        target: FixtureTarget::Abs(0x80001ff0),
+        expected: 0x20,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_ADD64,
+        insn: 0,
+        target: FixtureTarget::Abs(0xffffffff80002010),
+        expected: 0xffffffff80002010,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_SUB64,
+        insn: 0xffffffff80002010,
+        target: FixtureTarget::Abs(0xffffffff80001ff0),
+        expected: 0x20,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_SUB16,
+        insn: 0x10,
+        target: FixtureTarget::Abs(0x20),
+        expected: 0xfff0,
+    },
+    // the upper two bits are kept
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_SUB6,
+        insn: 0xc1,
+        target: FixtureTarget::Abs(0x2),
+        expected: 0xff,
+    },
+    // the field wraps within its 6 bits
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_SUB6,
+        insn: 0x40,
+        target: FixtureTarget::Abs(0x1),
+        expected: 0x7f,
+    },
+    // only the low 6 bits of the value are used, the opcode bits are kept
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_SET6,
+        insn: 0xc1,
+        target: FixtureTarget::Abs(0x7f),
+        expected: 0xff,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_SET6,
+        insn: 0x3f,
+        target: FixtureTarget::Abs(0x40),
+        expected: 0x00,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_SET8,
+        insn: 0xaaaa_aaaa_aaaa_aaaa,
+        target: FixtureTarget::Abs(0x1234),
+        expected: 0xaaaa_aaaa_aaaa_aa34,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_SET16,
+        insn: 0xaaaa_aaaa_aaaa_aaaa,
+        target: FixtureTarget::Abs(0x1_2345),
+        expected: 0xaaaa_aaaa_aaaa_2345,
+    },
+    RelocFixture {
+        reloc: Rv64RelTy::R_RISCV_SET32,
+        insn: 0xaaaa_aaaa_aaaa_aaaa,
+        target: FixtureTarget::Abs(0xffffffff_80001234),
+        expected: 0xaaaa_aaaa_8000_1234,
+    },
+];
+
 pub struct Riscv64ArchRelocate;
 
+impl Riscv64ArchRelocate {
+    /// Number of bytes to reserve after a section for the call stubs and GOT
+    /// entries its relocations in `rela_list` may need
+    pub fn stub_reservation(rela_list: &[goblin::elf64::reloc::Rela]) -> usize {
+        rela_list
+            .iter()
+            .map(
+                |rela| match Rv64RelTy::try_from(get_rela_type(rela.r_info)) {
+                    Ok(Rv64RelTy::R_RISCV_CALL | Rv64RelTy::R_RISCV_CALL_PLT) => CALL_STUB_SIZE,
+                    Ok(Rv64RelTy::R_RISCV_GOT_HI20) => GOT_ENTRY_SIZE,
+                    _ => 0,
+                },
+            )
+            .sum()
+    }
+
+    /// Check the relocation handlers against known-good encodings
+    pub fn self_test() -> Result<()> {
+        run_self_test(SELF_TEST_FIXTURES, |reloc, location, address| {
+            reloc.apply_relocation(location, address)
+        })?;
+        // 6 bytes of padding to an 8 byte boundary
+        let align = Rv64RelTy::R_RISCV_ALIGN;
+        let buf = [0u64; 2];
+        let base = buf.as_ptr() as u64;
+        if align.apply_relocation(Ptr::new(base + 2), 6).is_err()
+            || align.apply_relocation(Ptr::new(base + 4), 6).is_err()
+            || align.apply_relocation(Ptr::new(base + 3), 6).is_ok()
+        {
+            log::error!("Self test of R_RISCV_ALIGN failed");
+            return Err(ModuleErr::RelocationFailed(
+                "Self test of R_RISCV_ALIGN failed".to_string(),
+            ));
+        }
+        // One past the reach of a branch and a jump, in both directions
+        let mut insn = [0u32; 1];
+        let location = insn.as_mut_ptr() as u64;
+        for (reloc, reach) in [
+            (Rv64RelTy::R_RISCV_BRANCH, 0x1000),
+            (Rv64RelTy::R_RISCV_JAL, 0x100000),
+        ] {
+            if reloc
+                .apply_relocation(Ptr::new(location), location + reach)
+                .is_ok()
+                || reloc
+                    .apply_relocation(Ptr::new(location), location - reach - 2)
+                    .is_ok()
+                || reloc
+                    .apply_relocation(Ptr::new(location), location - reach)
+                    .is_err()
+            {
+                log::error!("Self test of the {:?} range check failed", reloc);
+                return Err(ModuleErr::RelocationFailed(format!(
+                    "Self test of the {:?} range check failed",
+                    reloc
+                )));
+            }
+        }
+        // One past the ends of the c.lui immediate, after rounding
+        let mut insn = [0x6501u16; 1];
+        let location = insn.as_mut_ptr() as u64;
+        let lui = Rv64RelTy::R_RISCV_RVC_LUI;
+        if lui.apply_relocation(Ptr::new(location), 0x1f800).is_ok()
+            || lui
+                .apply_relocation(Ptr::new(location), 0xffff_ffff_fffd_f7ff)
+                .is_ok()
+        {
+            log::error!("Self test of the {:?} range check failed", lui);
+            return Err(ModuleErr::RelocationFailed(format!(
+                "Self test of the {:?} range check failed",
+                lui
+            )));
+        }
+        // B + A, already rebased, at an odd address
+        let mut slot = [0u64; 2];
+        let location = slot.as_mut_ptr() as u64 + 1;
+        Rv64RelTy::R_RISCV_RELATIVE.apply_relocation(Ptr::new(location), 0xffffffff80001234)?;
+        if Ptr::new(location).read::<u64>() != 0xffffffff80001234 {
+            log::error!("Self test of R_RISCV_RELATIVE failed");
+            return Err(ModuleErr::RelocationFailed(
+                "Self test of R_RISCV_RELATIVE failed".to_string(),
+            ));
+        }
+        // A 64-bit write 4 bytes before the end of the section
+        let start = slot.as_ptr() as u64;
+        let location = Ptr::bounded(start + 12, start..start + 16)?;
+        if !matches!(
+            Rv64RelTy::R_RISCV_64.apply_relocation(location, 0x1234),
+            Err(ModuleErr::RelocationOutOfBounds { .. })
+        ) || Ptr::bounded(start + 16, start..start + 16).is_ok()
+        {
+            log::error!("Self test of the relocation bounds check failed");
+            return Err(ModuleErr::RelocationFailed(
+                "Self test of the relocation bounds check failed".to_string(),
+            ));
+        }
+        // auipc a0, 0x12345; addi a0, a0, -0x123 and
+        // auipc a0, 0x12345; sd a1, -0x123(a0)
+        let pairs = [[0x12345517u64, 0xedd50513], [0x12345517, 0xecb53ea3]];
+        for (idx, pair) in pairs.iter().enumerate() {
+            let store = idx == 1;
+            let base = pair.as_ptr() as u64;
+            let target = base + 0x12345000 - 0x123;
+            let reloc = if store {
+                Rv64RelTy::R_RISCV_PCREL_LO12_S
+            } else {
+                Rv64RelTy::R_RISCV_PCREL_LO12_I
+            };
+            // A pair whose HI20 was patched against another base is caught
+            if check_pcrel_pair(reloc, base, base + 8, target).is_err()
+                || check_pcrel_pair(reloc, base, base + 8, target + 0x1000).is_ok()
+            {
+                log::error!("Self test of the HI20/LO12 pair check failed");
+                return Err(ModuleErr::RelocationFailed(
+                    "Self test of the HI20/LO12 pair check failed".to_string(),
+                ));
+            }
+        }
+        #[cfg(feature = "relax")]
+        {
+            // ld a0, 16(a0) -> addi a0, a0, 16
+            let mut insn = [0x01053503u32];
+            relax_got_load(Ptr::new(insn.as_mut_ptr() as u64))?;
+            if insn[0] != 0x01050513 {
+                log::error!("Self test of GOT relaxation failed: got {:#010x}", insn[0]);
+                return Err(ModuleErr::RelocationFailed(
+                    "Self test of GOT relaxation failed".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Effective address computed by the patched `auipc` at `hi20_loc` and the
+/// I-type (or S-type with `store`) instruction at `lo12_loc` using it
+fn pcrel_pair_target(hi20_loc: u64, lo12_loc: u64, store: bool) -> u64 {
+    let hi = Ptr::new(hi20_loc).read::<u32>() & 0xfffff000;
+    let lo = Ptr::new(lo12_loc).read::<u32>();
+    let lo = if store {
+        ((lo as i32 >> 25) << 5) | ((lo >> 7) & 0x1f) as i32
+    } else {
+        lo as i32 >> 20
+    };
+    hi20_loc
+        .wrapping_add(hi as i32 as i64 as u64)
+        .wrapping_add(lo as i64 as u64)
+}
+
+/// Check the patched HI20/LO12 pair reaches `target`, catching e.g. a HI20
+/// resolved against another base than its LO12
+fn check_pcrel_pair(reloc: Rv64RelTy, hi20_loc: u64, lo12_loc: u64, target: u64) -> Result<()> {
+    let got = pcrel_pair_target(hi20_loc, lo12_loc, reloc == Rv64RelTy::R_RISCV_PCREL_LO12_S);
+    if got != target {
+        log::error!(
+            "{:?} at {:#x} with HI20 at {:#x} computes {:#x}, expected {:#x}",
+            reloc,
+            lo12_loc,
+            hi20_loc,
+            got,
+            target
+        );
+        return Err(ModuleErr::RelocationFailed(format!(
+            "HI20/LO12 pair at {:#x} computes {:#x} instead of {:#x}",
+            lo12_loc, got, target
+        )));
+    }
+    Ok(())
+}
+
 #[allow(unused_assignments)]
 impl Riscv64ArchRelocate {
     /// See <https://elixir.bootlin.com/linux/v6.6/source/arch/riscv/kernel/module.c#L313>
@@ -417,88 +1287,966 @@ impl Riscv64ArchRelocate {
         sechdrs: &[SectionHeader],
         load_info: &ModuleLoadInfo,
         module: &ModuleOwner<H>,
+        progress: &mut RelocProgress,
     ) -> Result<()> {
+        // PCREL/GOT HI20 relocations by the location they patch, so each
+        // LO12 finds its pair without scanning the section again
+        let section_base = section_header(sechdrs, rel_section.sh_info as usize)?.sh_addr;
+        let bounds = module
+            .section_bounds(section_base)
+            .unwrap_or(section_base..section_base);
+        let mut hi20_relas = BTreeMap::new();
+        for rela in rela_list {
+            if let Ok(ty @ (Rv64RelTy::R_RISCV_PCREL_HI20 | Rv64RelTy::R_RISCV_GOT_HI20)) =
+                Rv64RelTy::try_from(get_rela_type(rela.r_info))
+            {
+                hi20_relas
+                    .entry(section_base + rela.r_offset)
+                    .or_insert((ty, rela));
+            }
+        }
         for rela in rela_list {
             let rel_type = get_rela_type(rela.r_info);
             let sym_idx = get_rela_sym_idx(rela.r_info);
+            let (sym, sym_name) = match load_info.symbol(sym_idx) {
+                Ok(symbol) => symbol,
+                Err(err) => {
+                    progress.fail(progress.context(rela, ""), err)?;
+                    continue;
+                }
+            };
 
             // This is where to make the change
-            let location = sechdrs[rel_section.sh_info as usize].sh_addr + rela.r_offset;
-
-            let reloc_type = Riscv64RelocationType::try_from(rel_type).map_err(|_| {
-                ModuleErr::RelocationFailed(format!("Invalid relocation type: {}", rel_type))
-            })?;
+            let location = section_base + rela.r_offset;
+            let ptr = match Ptr::bounded(location, bounds.clone()) {
+                Ok(ptr) => ptr,
+                Err(err) => {
+                    progress.fail(progress.context(rela, sym_name), err)?;
+                    continue;
+                }
+            };
 
-            let (sym, sym_name) = &load_info.syms[sym_idx];
+            let Ok(reloc_type) = Riscv64RelocationType::try_from(rel_type) else {
+                progress.fail(
+                    progress.context(rela, sym_name),
+                    ModuleErr::UnsupportedRelocation {
+                        arch: "RISC-V",
+                        reloc_type: rel_type,
+                    },
+                )?;
+                continue;
+            };
 
             let mut target_addr = sym.st_value.wrapping_add(rela.r_addend as u64);
+            if reloc_type == Rv64RelTy::R_RISCV_RELATIVE {
+                // A is a link-time address of the module
+                let Some(addr) = rebase_link_addr(sechdrs, &load_info.link_addrs, target_addr)
+                else {
+                    progress.fail(
+                        progress.context(rela, sym_name),
+                        ModuleErr::RelocationFailed(format!(
+                            "R_RISCV_RELATIVE of {:#x} is not inside any section",
+                            target_addr
+                        )),
+                    )?;
+                    continue;
+                };
+                target_addr = addr;
+            }
+            if reloc_type.is_tprel() {
+                // S is the offset of the variable in the module's TLS block
+                let Some(tls_base) = load_info.tls_base else {
+                    module_log!(
+                        error,
+                        module.name(),
+                        sym = sym_name,
+                        "{:?} needs a TLS block, but the host provides none",
+                        reloc_type
+                    );
+                    progress.fail(progress.context(rela, sym_name), ModuleErr::TlsNotSupported)?;
+                    continue;
+                };
+                target_addr = target_addr.wrapping_add(tls_base);
+            }
+
+            // Local GOT references are relaxed to direct PC-relative addressing.
+            let mut reloc_type = reloc_type;
+            if reloc_type == Rv64RelTy::R_RISCV_GOT_HI20
+                && can_relax_got_ref(sym, target_addr as i64 - location as i64)
+            {
+                module_log!(
+                    debug,
+                    module.name(),
+                    sym = sym_name,
+                    "relax R_RISCV_GOT_HI20 to R_RISCV_PCREL_HI20"
+                );
+                reloc_type = Rv64RelTy::R_RISCV_PCREL_HI20;
+            } else if reloc_type == Rv64RelTy::R_RISCV_GOT_HI20 {
+                // Address the GOT slot instead of the symbol itself
+                target_addr =
+                    match got_entry(module, location, sym_name, sym.st_value, rela.r_addend) {
+                        Ok(slot) => slot,
+                        Err(err) => {
+                            progress.fail(progress.context(rela, sym_name), err)?;
+                            continue;
+                        }
+                    };
+                reloc_type = Rv64RelTy::R_RISCV_PCREL_HI20;
+            }
+            if matches!(
+                reloc_type,
+                Rv64RelTy::R_RISCV_CALL | Rv64RelTy::R_RISCV_CALL_PLT
+            ) && !riscv_insn_valid_32bit_offset(target_addr as i64 - location as i64)
+            {
+                module_log!(
+                    debug,
+                    module.name(),
+                    sym = sym_name,
+                    "call target {:#x} out of range, going through a stub",
+                    target_addr
+                );
+                target_addr = match emit_call_stub(module, location, target_addr) {
+                    Ok(stub) => stub,
+                    Err(err) => {
+                        progress.fail(progress.context(rela, sym_name), err)?;
+                        continue;
+                    }
+                };
+            }
+            let mut relax_load = false;
+            // HI20 location and target of the pair, checked once the LO12
+            // is patched
+            #[cfg(feature = "reloc-verify")]
+            let mut pcrel_pair = None;
 
             if reloc_type == Rv64RelTy::R_RISCV_PCREL_LO12_I
                 || reloc_type == Rv64RelTy::R_RISCV_PCREL_LO12_S
             {
-                // PC-relative relocation
-                let mut find = false;
-                for inner_rela in rela_list {
-                    let hi20_loc =
-                        sechdrs[rel_section.sh_info as usize].sh_addr + inner_rela.r_offset;
-                    let hi20_type = get_rela_type(inner_rela.r_info);
-                    let hi20_type = Rv64RelTy::try_from(hi20_type).map_err(|_| {
-                        ModuleErr::RelocationFailed(format!(
-                            "Invalid relocation type: {}",
-                            hi20_type
-                        ))
-                    })?;
-
-                    // Find the corresponding HI20 relocation entry
-                    if hi20_loc == sym.st_value
-                        && (hi20_type == Rv64RelTy::R_RISCV_PCREL_HI20
-                            || hi20_type == Rv64RelTy::R_RISCV_GOT_HI20)
+                // PC-relative relocation, the symbol is the HI20 location
+                let Some(&(hi20_type, inner_rela)) = hi20_relas.get(&sym.st_value) else {
+                    // Show every HI20 that was considered, to tell e.g. a wrong
+                    // section base from a really missing HI20
+                    #[cfg(feature = "reloc-trace")]
                     {
-                        let (hi20_sym, _) = load_info.syms[get_rela_sym_idx(inner_rela.r_info)];
-
-                        let hi20_sym_val = hi20_sym.st_value as i64 + inner_rela.r_addend;
-                        // Calculate lo12
-                        let offset = hi20_sym_val - hi20_loc as i64;
-
-                        // if (IS_ENABLED(CONFIG_MODULE_SECTIONS)
-                        //     && hi20_type == R_RISCV_GOT_HI20) {
-                        //     offset = module_emit_got_entry(me, hi20_sym_val);
-                        //     offset = offset - hi20_loc;
-                        // }
-
-                        if hi20_type == Rv64RelTy::R_RISCV_GOT_HI20 {
-                            unimplemented!("GOT handling not implemented yet");
+                        module_log!(
+                            trace,
+                            module.name(),
+                            sym = sym_name,
+                            "LO12 at {:#x} looks for a HI20 at {:#x}",
+                            location,
+                            sym.st_value
+                        );
+                        for inner_rela in rela_list {
+                            if matches!(
+                                Rv64RelTy::try_from(get_rela_type(inner_rela.r_info)),
+                                Ok(Rv64RelTy::R_RISCV_PCREL_HI20 | Rv64RelTy::R_RISCV_GOT_HI20)
+                            ) {
+                                module_log!(
+                                    trace,
+                                    module.name(),
+                                    "  candidate HI20 at {:#x} (section base {:#x} + offset {:#x})",
+                                    section_base + inner_rela.r_offset,
+                                    section_base,
+                                    inner_rela.r_offset
+                                );
+                            }
+                        }
+                    }
+                    module_log!(
+                        error,
+                        module.name(),
+                        sym = sym_name,
+                        "Can not find HI20 relocation information for LO12 relocation at {:#x}",
+                        location
+                    );
+                    progress.fail(
+                        progress.context(rela, sym_name),
+                        ModuleErr::OrphanLo12 {
+                            location,
+                            symbol_index: sym_idx,
+                        },
+                    )?;
+                    continue;
+                };
+                let hi20_loc = sym.st_value;
+                let (hi20_sym, hi20_sym_name) =
+                    match load_info.symbol(get_rela_sym_idx(inner_rela.r_info)) {
+                        Ok(symbol) => symbol,
+                        Err(err) => {
+                            progress.fail(progress.context(rela, sym_name), err)?;
+                            continue;
                         }
+                    };
+
+                let hi20_sym_val = hi20_sym.st_value as i64 + inner_rela.r_addend;
+                // Calculate lo12
+                let mut offset = hi20_sym_val - hi20_loc as i64;
 
-                        let hi_20 = (offset + 0x800) & 0xfffff000;
-                        let lo_12 = offset - hi_20;
+                // if (IS_ENABLED(CONFIG_MODULE_SECTIONS)
+                //     && hi20_type == R_RISCV_GOT_HI20) {
+                //     offset = module_emit_got_entry(me, hi20_sym_val);
+                //     offset = offset - hi20_loc;
+                // }
 
-                        // update target_addr
-                        target_addr = lo_12 as u64;
-                        find = true;
-                        break;
+                if hi20_type == Rv64RelTy::R_RISCV_GOT_HI20 {
+                    if can_relax_got_ref(hi20_sym, offset) {
+                        // The HI20 was relaxed to PCREL, so the GOT load must
+                        // become an address computation as well.
+                        relax_load = reloc_type == Rv64RelTy::R_RISCV_PCREL_LO12_I;
+                    } else {
+                        // Same slot as the HI20 got, entries are shared
+                        let slot = match got_entry(
+                            module,
+                            hi20_loc,
+                            hi20_sym_name,
+                            hi20_sym.st_value,
+                            inner_rela.r_addend,
+                        ) {
+                            Ok(slot) => slot,
+                            Err(err) => {
+                                progress.fail(progress.context(rela, sym_name), err)?;
+                                continue;
+                            }
+                        };
+                        offset = slot as i64 - hi20_loc as i64;
                     }
                 }
-                if !find {
-                    log::error!(
-                        "[{}]: ({}) Can not find HI20 relocation information for LO12 relocation",
-                        module.name(),
-                        sym_name
-                    );
-                    return Err(ModuleErr::RelocationFailed(
-                        "Missing HI20 relocation for LO12".to_string(),
-                    ));
+
+                let hi_20 = (offset + 0x800) & 0xfffff000;
+                let lo_12 = offset - hi_20;
+                // A HI20 after the LO12 is not patched yet
+                #[cfg(feature = "reloc-verify")]
+                if core::ptr::from_ref(inner_rela) < core::ptr::from_ref(rela) {
+                    pcrel_pair = Some((hi20_loc, hi20_loc.wrapping_add(offset as u64)));
                 }
+
+                // update target_addr
+                target_addr = lo_12 as u64;
+            }
+            let underflows = if load_info.strict {
+                reloc_type.sub_underflows(ptr, target_addr)
+            } else {
+                Ok(false)
+            };
+            let mut res = match underflows {
+                Ok(true) => Err(ModuleErr::RelocationFailed(format!(
+                    "{:?} of '{}' at {:#x} underflows",
+                    reloc_type, sym_name, location
+                ))),
+                Ok(false) => reloc_type.apply_relocation(ptr, target_addr),
+                Err(err) => Err(err),
+            };
+            if res.is_ok() && relax_load {
+                res = relax_got_load(ptr);
+            }
+            #[cfg(feature = "reloc-verify")]
+            if let (Ok(()), Some((hi20_loc, target))) = (&res, pcrel_pair) {
+                res = check_pcrel_pair(reloc_type, hi20_loc, location, target);
             }
-            let res = reloc_type.apply_relocation(location, target_addr);
             match res {
-                Err(e) => {
-                    log::error!("[{}]: ({}) {:?}", module.name(), sym_name, e);
-                    return Err(e);
-                }
-                Ok(_) => { /* Successfully applied relocation */ }
+                Err(e) => progress.fail(progress.context(rela, sym_name), e)?,
+                Ok(_) => progress.applied += 1,
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::ModuleLoadInfoBuilder;
+    use goblin::elf::header::EM_RISCV;
+    use goblin::elf::sym::Sym;
+    use goblin::elf64::reloc::Rela;
+
+    fn rela(r_offset: u64, sym: usize, reloc: Rv64RelTy, r_addend: i64) -> Rela {
+        Rela {
+            r_offset,
+            r_info: ((sym as u64) << 32) | reloc as u64,
+            r_addend,
+        }
+    }
+
+    /// Patch `insn` at a scratch location with `reloc` against `address`
+    fn patch(reloc: Rv64RelTy, insn: u32, address: u64) -> Result<u32> {
+        let mut buf = [insn];
+        let location = buf.as_mut_ptr() as u64;
+        reloc.apply_relocation(Ptr::new(location), address)?;
+        Ok(buf[0])
+    }
+
+    /// Address computed by `lui rd, hi` followed by the I-type (or S-type
+    /// with `store`) `lo` instruction, both sign-extending like the CPU
+    fn lui_pair_target(hi: u32, lo: u32, store: bool) -> u64 {
+        let lo = if store {
+            ((lo as i32 >> 25) << 5) | ((lo >> 7) & 0x1f) as i32
+        } else {
+            lo as i32 >> 20
+        };
+        ((hi & 0xfffff000) as i32 as i64 + lo as i64) as u64
+    }
+
+    #[test]
+    fn golden_fixtures() {
+        for fixture in SELF_TEST_FIXTURES {
+            run_self_test(
+                core::slice::from_ref(fixture),
+                |reloc, location, address| reloc.apply_relocation(location, address),
+            )
+            .unwrap_or_else(|e| panic!("{:?}: {:?}", fixture.reloc, e));
+        }
+        // Every handler that patches the location has a golden case,
+        // R_RISCV_RELATIVE and R_RISCV_ALIGN are checked by self_test itself
+        for reloc in [
+            Rv64RelTy::R_RISCV_32,
+            Rv64RelTy::R_RISCV_64,
+            Rv64RelTy::R_RISCV_JUMP_SLOT,
+            Rv64RelTy::R_RISCV_BRANCH,
+            Rv64RelTy::R_RISCV_JAL,
+            Rv64RelTy::R_RISCV_RVC_BRANCH,
+            Rv64RelTy::R_RISCV_RVC_JUMP,
+            Rv64RelTy::R_RISCV_RVC_LUI,
+            Rv64RelTy::R_RISCV_PCREL_HI20,
+            Rv64RelTy::R_RISCV_PCREL_LO12_I,
+            Rv64RelTy::R_RISCV_PCREL_LO12_S,
+            Rv64RelTy::R_RISCV_HI20,
+            Rv64RelTy::R_RISCV_LO12_I,
+            Rv64RelTy::R_RISCV_LO12_S,
+            Rv64RelTy::R_RISCV_GOT_HI20,
+            Rv64RelTy::R_RISCV_CALL_PLT,
+            Rv64RelTy::R_RISCV_CALL,
+            Rv64RelTy::R_RISCV_ADD16,
+            Rv64RelTy::R_RISCV_ADD32,
+            Rv64RelTy::R_RISCV_ADD64,
+            Rv64RelTy::R_RISCV_SUB6,
+            Rv64RelTy::R_RISCV_SUB16,
+            Rv64RelTy::R_RISCV_SUB32,
+            Rv64RelTy::R_RISCV_SUB64,
+            Rv64RelTy::R_RISCV_SET6,
+            Rv64RelTy::R_RISCV_SET8,
+            Rv64RelTy::R_RISCV_SET16,
+            Rv64RelTy::R_RISCV_SET32,
+        ] {
+            assert!(
+                SELF_TEST_FIXTURES.iter().any(|f| f.reloc == reloc),
+                "no golden case for {:?}",
+                reloc
+            );
+        }
+        Riscv64ArchRelocate::self_test().unwrap();
+    }
+
+    #[test]
+    fn pc_relative_encodings() {
+        // (reloc, instruction, offset from it, expected encoding)
+        for (reloc, insn, offset, expected) in [
+            // beq a0, a1
+            (
+                Rv64RelTy::R_RISCV_BRANCH,
+                0x00b50063u32,
+                0x100i64,
+                0x10b50063,
+            ),
+            (Rv64RelTy::R_RISCV_BRANCH, 0x00b50063, -4, 0xfeb50ee3),
+            // jal ra
+            (Rv64RelTy::R_RISCV_JAL, 0x000000ef, 0x800, 0x001000ef),
+            (Rv64RelTy::R_RISCV_JAL, 0x000000ef, -8, 0xff9ff0ef),
+            // c.beqz a0
+            (Rv64RelTy::R_RISCV_RVC_BRANCH, 0xc101, 0x10, 0xc901),
+            (Rv64RelTy::R_RISCV_RVC_BRANCH, 0xc101, -2, 0xdd7d),
+            // c.j
+            (Rv64RelTy::R_RISCV_RVC_JUMP, 0xa001, 0x20, 0xa005),
+            (Rv64RelTy::R_RISCV_RVC_JUMP, 0xa001, -2, 0xbffd),
+            // auipc a0
+            (
+                Rv64RelTy::R_RISCV_PCREL_HI20,
+                0x00000517,
+                0x12345800,
+                0x12346517,
+            ),
+            (
+                Rv64RelTy::R_RISCV_PCREL_HI20,
+                0x00000517,
+                -0x1000,
+                0xfffff517,
+            ),
+        ] {
+            let mut buf = [insn, 0];
+            let location = buf.as_mut_ptr() as u64;
+            reloc
+                .apply_relocation(Ptr::new(location), location.wrapping_add(offset as u64))
+                .unwrap();
+            assert_eq!(
+                buf,
+                [expected, 0],
+                "{:?} of {:#x}: got {:#010x}",
+                reloc,
+                offset,
+                buf[0]
+            );
+        }
+
+        // auipc ra, 0; jalr ra, 0(ra), lo12 is -1
+        for reloc in [Rv64RelTy::R_RISCV_CALL, Rv64RelTy::R_RISCV_CALL_PLT] {
+            let mut buf = [0x00000097u32, 0x000080e7];
+            let location = buf.as_mut_ptr() as u64;
+            reloc
+                .apply_relocation(Ptr::new(location), location + 0x12345fff)
+                .unwrap();
+            assert_eq!(buf, [0x12346097, 0xfff080e7], "{:?}", reloc);
+        }
+    }
+
+    #[test]
+    fn hi20_lo12_negative_lo12() {
+        // Low 12 bits >= 0x800, so lo12 is negative and HI20 has to carry
+        for address in [0x12345abc, 0x800, 0xfff, 0x7fffe800, 0xffffffff80001abc] {
+            let hi = patch(Rv64RelTy::R_RISCV_HI20, 0x00000537, address).unwrap();
+            // addi a0, a0, 0
+            let lo_i = patch(Rv64RelTy::R_RISCV_LO12_I, 0x00050513, address).unwrap();
+            // sd a1, 0(a0)
+            let lo_s = patch(Rv64RelTy::R_RISCV_LO12_S, 0x00b53023, address).unwrap();
+            assert!(
+                lo_i as i32 >> 20 < 0,
+                "lo12 of {:#x} is not negative",
+                address
+            );
+            assert_eq!(lui_pair_target(hi, lo_i, false), address);
+            assert_eq!(lui_pair_target(hi, lo_s, true), address);
+        }
+    }
+
+    #[test]
+    fn tprel_local_exec() {
+        // lui a0, 0; add a0, a0, tp; addi a0, a0, 0; then a .quad
+        let mut text = [0u8; 24];
+        text[..4].copy_from_slice(&0x00000537u32.to_le_bytes());
+        text[4..8].copy_from_slice(&0x00450533u32.to_le_bytes());
+        text[8..12].copy_from_slice(&0x00050513u32.to_le_bytes());
+        // The variable is at 0x10 in the module's TLS block
+        let var = Sym {
+            st_value: 0x10,
+            ..Default::default()
+        };
+        let relas = [
+            rela(0, 1, Rv64RelTy::R_RISCV_TPREL_HI20, 0),
+            rela(4, 1, Rv64RelTy::R_RISCV_TPREL_ADD, 0),
+            rela(8, 1, Rv64RelTy::R_RISCV_TPREL_LO12_I, 0),
+            rela(16, 1, Rv64RelTy::R_RISCV_TLS_TPREL64, 8),
+        ];
+        let builder = ModuleLoadInfoBuilder::new(EM_RISCV)
+            .with_section(".text", &mut text, 24)
+            .with_symbol("var", var);
+        assert!(matches!(
+            builder.apply(1, &relas),
+            Err(ModuleErr::RelocationEntry { ref err, .. })
+                if matches!(**err, ModuleErr::TlsNotSupported)
+        ));
+
+        // tp + 0x7f0 + 0x10 needs lo12 = -0x800
+        builder.with_tls_base(0x7f0).apply(1, &relas).unwrap();
+        let word = |i: usize| u32::from_le_bytes(text[i..i + 4].try_into().unwrap());
+        assert_eq!(word(0), 0x00001537);
+        assert_eq!(word(4), 0x00450533);
+        assert_eq!(word(8), 0x80050513);
+        assert_eq!(u64::from_le_bytes(text[16..].try_into().unwrap()), 0x808);
+    }
+
+    #[test]
+    fn add_sub_label_difference() {
+        // A .word and a .quad of `end - start + 4`, the assembler leaves 4
+        let mut data = [0u8; 16];
+        data[..4].copy_from_slice(&4u32.to_le_bytes());
+        data[8..].copy_from_slice(&4u64.to_le_bytes());
+        let label = |st_value| Sym {
+            st_value,
+            ..Default::default()
+        };
+        let builder = ModuleLoadInfoBuilder::new(EM_RISCV)
+            .with_section(".eh_frame", &mut data, 16)
+            .with_symbol("start", label(0xffff_ffc0_0000_1234))
+            .with_symbol("end", label(0xffff_ffc0_0000_1334));
+        builder
+            .apply(
+                1,
+                &[
+                    rela(0, 2, Rv64RelTy::R_RISCV_ADD32, 0),
+                    rela(0, 1, Rv64RelTy::R_RISCV_SUB32, 0),
+                    rela(8, 2, Rv64RelTy::R_RISCV_ADD64, 8),
+                    rela(8, 1, Rv64RelTy::R_RISCV_SUB64, 0),
+                ],
+            )
+            .unwrap();
+        drop(builder);
+        assert_eq!(u32::from_le_bytes(data[..4].try_into().unwrap()), 0x104);
+        assert_eq!(u64::from_le_bytes(data[8..].try_into().unwrap()), 0x10c);
+    }
+
+    #[test]
+    fn align_overflow() {
+        // nop
+        assert!(patch(Rv64RelTy::R_RISCV_ALIGN, 0x00000013, 2).is_ok());
+        for padding in [u64::MAX, u64::MAX - 1, 1 << 63] {
+            assert!(
+                patch(Rv64RelTy::R_RISCV_ALIGN, 0x00000013, padding).is_err(),
+                "accepted {:#x} bytes of padding",
+                padding
+            );
+        }
+    }
+
+    #[test]
+    fn relative_rebased_by_section() {
+        // .data linked at 0x10000 and .rodata at 0x20000, loaded elsewhere
+        let mut data = [0u8; 16];
+        let mut rodata = [0u8; 16];
+        let (data_addr, rodata_addr) = (data.as_ptr() as u64, rodata.as_ptr() as u64);
+        let builder = ModuleLoadInfoBuilder::new(EM_RISCV)
+            .with_section(".data", &mut data, 16)
+            .with_section(".rodata", &mut rodata, 16)
+            .with_link_addr(1, 0x10000)
+            .with_link_addr(2, 0x20000);
+        builder
+            .apply(
+                1,
+                &[
+                    rela(0, 0, Rv64RelTy::R_RISCV_RELATIVE, 0x20008),
+                    rela(8, 0, Rv64RelTy::R_RISCV_RELATIVE, 0x10004),
+                ],
+            )
+            .unwrap();
+        // Past the end of .rodata, in no section
+        assert!(matches!(
+            builder.apply(1, &[rela(0, 0, Rv64RelTy::R_RISCV_RELATIVE, 0x20010)]),
+            Err(ModuleErr::RelocationEntry { ref err, .. })
+                if matches!(**err, ModuleErr::RelocationFailed(_))
+        ));
+        drop(builder);
+        let word = |i: usize| u64::from_le_bytes(data[i..i + 8].try_into().unwrap());
+        assert_eq!(word(0), rodata_addr + 8);
+        assert_eq!(word(8), data_addr + 4);
+    }
+
+    #[test]
+    fn straddling_section_end() {
+        let mut text = [0u8; 16];
+        // auipc ra, 0; jalr ra, 0(ra) with the jalr past the end
+        text[12..].copy_from_slice(&0x00000097u32.to_le_bytes());
+        let target = Sym {
+            st_value: text.as_ptr() as u64 + 0x1000,
+            ..Default::default()
+        };
+        for strict in [false, true] {
+            let builder = ModuleLoadInfoBuilder::new(EM_RISCV)
+                .with_section(".text", &mut text, 16)
+                .with_symbol("target", target)
+                .with_strict_relocations(strict);
+            for relocation in [
+                rela(14, 1, Rv64RelTy::R_RISCV_ADD32, 0),
+                rela(14, 1, Rv64RelTy::R_RISCV_SUB32, 0),
+                rela(12, 1, Rv64RelTy::R_RISCV_CALL_PLT, 0),
+            ] {
+                let res = builder.apply(1, &[relocation]);
+                assert!(
+                    matches!(
+                        res,
+                        Err(ModuleErr::RelocationEntry { ref err, .. })
+                            if matches!(**err, ModuleErr::RelocationOutOfBounds { .. })
+                    ),
+                    "{:?}",
+                    res
+                );
+            }
+        }
+        // Nothing was patched, not even the auipc
+        assert_eq!(text[..12], [0; 12]);
+        assert_eq!(text[12..], 0x00000097u32.to_le_bytes());
+    }
+
+    #[test]
+    fn far_call_stub() {
+        // auipc ra, 0; jalr ra, 0(ra), then room for a call stub
+        let mut text = [0u8; 48];
+        text[..4].copy_from_slice(&0x00000097u32.to_le_bytes());
+        text[4..8].copy_from_slice(&0x000080e7u32.to_le_bytes());
+        let base = text.as_ptr() as u64;
+        // In another allocation, out of the ±2GB of the call
+        let far = base.wrapping_add(1 << 32);
+        let target = Sym {
+            st_value: far,
+            ..Default::default()
+        };
+        let call = [rela(0, 1, Rv64RelTy::R_RISCV_CALL_PLT, 0)];
+        ModuleLoadInfoBuilder::new(EM_RISCV)
+            .with_section(".text", &mut text, 16)
+            .with_symbol("far", target)
+            .apply(1, &call)
+            .unwrap();
+        // The call goes to the stub right after the section, which jumps on
+        assert_eq!(pcrel_pair_target(base, base + 4, false), base + 16);
+        let word = |i: usize| u32::from_le_bytes(text[i..i + 4].try_into().unwrap());
+        assert_eq!(
+            [word(16), word(20), word(24), word(28)],
+            [0x00000317, 0x01033303, 0x00030067, 0x00000013]
+        );
+        assert_eq!(u64::from_le_bytes(text[32..40].try_into().unwrap()), far);
+
+        // Without room for a stub
+        let mut text = [0u8; 16];
+        text[..4].copy_from_slice(&0x00000097u32.to_le_bytes());
+        text[4..8].copy_from_slice(&0x000080e7u32.to_le_bytes());
+        let far = (text.as_ptr() as u64).wrapping_add(1 << 32);
+        let target = Sym {
+            st_value: far,
+            ..Default::default()
+        };
+        let res = ModuleLoadInfoBuilder::new(EM_RISCV)
+            .with_section(".text", &mut text, 16)
+            .with_symbol("far", target)
+            .apply(1, &call);
+        assert!(
+            matches!(
+                res,
+                Err(ModuleErr::RelocationEntry { ref err, .. })
+                    if matches!(**err, ModuleErr::FragmentationTooLarge { target, .. } if target == far)
+            ),
+            "{:?}",
+            res
+        );
+    }
+
+    #[test]
+    fn got_entries_shared() {
+        // Two auipc a0, 0; ld a0, 0(a0) loading the same kernel symbol, then
+        // room for two GOT entries
+        let mut text = [0u8; 32];
+        for pair in text[..16].chunks_mut(8) {
+            pair[..4].copy_from_slice(&0x00000517u32.to_le_bytes());
+            pair[4..].copy_from_slice(&0x00053503u32.to_le_bytes());
+        }
+        let base = text.as_ptr() as u64;
+        let kernel_sym = Sym {
+            st_value: 0xffff_ffff_8000_1000,
+            ..Default::default()
+        };
+        let label = |offset| Sym {
+            st_value: base + offset,
+            ..Default::default()
+        };
+        let builder = ModuleLoadInfoBuilder::new(EM_RISCV)
+            .with_section(".text", &mut text, 16)
+            .with_symbol("kernel_sym", kernel_sym)
+            .with_symbol(".L0", label(0))
+            .with_symbol(".L1", label(8));
+        builder
+            .apply(
+                1,
+                &[
+                    rela(0, 1, Rv64RelTy::R_RISCV_GOT_HI20, 0),
+                    rela(4, 2, Rv64RelTy::R_RISCV_PCREL_LO12_I, 0),
+                    // GOT[S] + A, the addend applies to the slot address
+                    rela(8, 1, Rv64RelTy::R_RISCV_GOT_HI20, 8),
+                    rela(12, 3, Rv64RelTy::R_RISCV_PCREL_LO12_I, 0),
+                ],
+            )
+            .unwrap();
+        drop(builder);
+
+        let slot = pcrel_pair_target(base, base + 4, false);
+        assert_eq!(slot, base + 16);
+        assert_eq!(pcrel_pair_target(base + 8, base + 12, false), slot + 8);
+        assert_eq!(
+            u64::from_le_bytes(text[16..24].try_into().unwrap()),
+            0xffff_ffff_8000_1000
+        );
+        // The second entry is unused
+        assert_eq!(text[24..], [0; 8]);
+    }
+
+    #[test]
+    fn pcrel_hi20_lo12_pairing() {
+        // auipc a0, 0; sd a1, 0(a0); auipc a1, 0; addi a1, a1, 0
+        let mut text = [0u8; 16];
+        text[..4].copy_from_slice(&0x00000517u32.to_le_bytes());
+        text[4..8].copy_from_slice(&0x00b53023u32.to_le_bytes());
+        text[8..12].copy_from_slice(&0x00000597u32.to_le_bytes());
+        text[12..].copy_from_slice(&0x00058593u32.to_le_bytes());
+        let base = text.as_ptr() as u64;
+        // Low 12 bits of the distance >= 0x800, so each HI20 has to carry
+        let var = base + 0x1abc;
+        let label = |st_value| Sym {
+            st_value,
+            ..Default::default()
+        };
+        let builder = ModuleLoadInfoBuilder::new(EM_RISCV)
+            .with_section(".text", &mut text, 16)
+            .with_symbol("var", label(var))
+            .with_symbol(".L0", label(base))
+            .with_symbol(".L1", label(base + 8));
+        builder
+            .apply(
+                1,
+                &[
+                    // Each LO12 finds its HI20 by location, whatever the order
+                    rela(4, 2, Rv64RelTy::R_RISCV_PCREL_LO12_S, 0),
+                    rela(12, 3, Rv64RelTy::R_RISCV_PCREL_LO12_I, 0),
+                    rela(0, 1, Rv64RelTy::R_RISCV_PCREL_HI20, 0),
+                    // The addend is the HI20's, the LO12 one stays 0
+                    rela(8, 1, Rv64RelTy::R_RISCV_PCREL_HI20, 0x10),
+                ],
+            )
+            .unwrap();
+        drop(builder);
+        assert_eq!(pcrel_pair_target(base, base + 4, true), var);
+        assert_eq!(pcrel_pair_target(base + 8, base + 12, false), var + 0x10);
+    }
+
+    #[test]
+    fn got_emission() {
+        // auipc a0, 0; ld a0, 0(a0) for two kernel symbols, then room for
+        // two GOT entries
+        let mut text = [0u8; 32];
+        for pair in text[..16].chunks_mut(8) {
+            pair[..4].copy_from_slice(&0x00000517u32.to_le_bytes());
+            pair[4..].copy_from_slice(&0x00053503u32.to_le_bytes());
+        }
+        let base = text.as_ptr() as u64;
+        let relas = [
+            rela(0, 1, Rv64RelTy::R_RISCV_GOT_HI20, 0),
+            rela(4, 3, Rv64RelTy::R_RISCV_PCREL_LO12_I, 0),
+            rela(8, 2, Rv64RelTy::R_RISCV_GOT_HI20, 0),
+            rela(12, 4, Rv64RelTy::R_RISCV_PCREL_LO12_I, 0),
+        ];
+        fn symbols(builder: ModuleLoadInfoBuilder<'_>, base: u64) -> ModuleLoadInfoBuilder<'_> {
+            let label = |st_value| Sym {
+                st_value,
+                ..Default::default()
+            };
+            builder
+                .with_symbol("kernel_a", label(0xffff_ffff_8000_1000))
+                .with_symbol("kernel_b", label(0xffff_ffff_8000_2000))
+                .with_symbol(".L0", label(base))
+                .with_symbol(".L1", label(base + 8))
+        }
+
+        // The whole buffer is .text, no room for a GOT
+        let mut code = text;
+        let code_base = code.as_ptr() as u64;
+        let builder = symbols(
+            ModuleLoadInfoBuilder::new(EM_RISCV).with_section(".text", &mut code, 32),
+            code_base,
+        );
+        assert!(matches!(
+            builder.apply(1, &relas),
+            Err(ModuleErr::RelocationEntry { ref err, .. })
+                if matches!(**err, ModuleErr::RelocationFailed(_))
+        ));
+
+        let builder = symbols(
+            ModuleLoadInfoBuilder::new(EM_RISCV).with_section(".text", &mut text, 16),
+            base,
+        );
+        builder.apply(1, &relas).unwrap();
+        drop(builder);
+        // One entry per symbol, in order, each holding the symbol address
+        assert_eq!(pcrel_pair_target(base, base + 4, false), base + 16);
+        assert_eq!(pcrel_pair_target(base + 8, base + 12, false), base + 24);
+        let entry = |i: usize| u64::from_le_bytes(text[i..i + 8].try_into().unwrap());
+        assert_eq!(entry(16), 0xffff_ffff_8000_1000);
+        assert_eq!(entry(24), 0xffff_ffff_8000_2000);
+    }
+
+    #[test]
+    fn rvc_lui_sign_extension() {
+        // c.lui a0, 0, in the low half of the scratch word
+        let insn = 0x6501;
+        for address in [0xffff_ffff_fffe_0000, 0xffff_ffff_ffff_f7ff] {
+            assert!(patch(Rv64RelTy::R_RISCV_RVC_LUI, insn, address).is_ok());
+        }
+        // The 32-bit truncation of these fits, but c.lui sign-extends
+        for address in [
+            0xfffe_0000,
+            0xffff_f000,
+            0x1_0000_1000,
+            0x7fff_ffff_ffff_f800,
+        ] {
+            assert!(
+                patch(Rv64RelTy::R_RISCV_RVC_LUI, insn, address).is_err(),
+                "accepted {:#x}",
+                address
+            );
+        }
+    }
+
+    #[cfg(feature = "relax")]
+    #[test]
+    fn relax_local_got_ref() {
+        // auipc a0, 0; ld a0, 0(a0); auipc a0, 0; sd a1, 0(a0), then room
+        // for a GOT entry
+        let mut text = [0u8; 24];
+        text[..4].copy_from_slice(&0x00000517u32.to_le_bytes());
+        text[4..8].copy_from_slice(&0x00053503u32.to_le_bytes());
+        text[8..12].copy_from_slice(&0x00000517u32.to_le_bytes());
+        text[12..16].copy_from_slice(&0x00b53023u32.to_le_bytes());
+        let base = text.as_ptr() as u64;
+        // Defined by the module, in .text
+        let local = |st_value| Sym {
+            st_value,
+            st_shndx: 1,
+            ..Default::default()
+        };
+        let builder = ModuleLoadInfoBuilder::new(EM_RISCV)
+            .with_section(".text", &mut text, 16)
+            .with_symbol("var", local(base + 0x1abc))
+            .with_symbol(".L0", local(base))
+            .with_symbol(".L1", local(base + 8));
+        builder
+            .apply(
+                1,
+                &[
+                    rela(0, 1, Rv64RelTy::R_RISCV_GOT_HI20, 0),
+                    rela(4, 2, Rv64RelTy::R_RISCV_PCREL_LO12_I, 0),
+                    rela(8, 1, Rv64RelTy::R_RISCV_GOT_HI20, 0),
+                    rela(12, 3, Rv64RelTy::R_RISCV_PCREL_LO12_S, 0),
+                ],
+            )
+            .unwrap();
+        drop(builder);
+        // The auipc addresses the symbol itself, not a GOT slot
+        assert_eq!(pcrel_pair_target(base, base + 4, false), base + 0x1abc);
+        assert_eq!(pcrel_pair_target(base + 8, base + 12, true), base + 0x1abc);
+        // The ld became addi a0, a0, -0x544
+        assert_eq!(text[4..8], (0x00050513u32 | 0xabc << 20).to_le_bytes());
+        // The sd is not a GOT load, only its offset -0x54c is patched
+        assert_eq!(
+            text[12..16],
+            (0x00b53023u32 | 0x55 << 25 | 0x14 << 7).to_le_bytes()
+        );
+        assert_eq!(text[16..], [0; 8]);
+    }
+
+    #[cfg(feature = "reloc-verify")]
+    #[test]
+    fn verify_pcrel_pair() {
+        // auipc a0, 2; addi a0, a0, -0x544; auipc a0, 2; sd a1, -0x54c(a0)
+        let mut text = [0u8; 16];
+        text[..4].copy_from_slice(&0x00002517u32.to_le_bytes());
+        text[4..8].copy_from_slice(&(0x00050513u32 | 0xabc << 20).to_le_bytes());
+        text[8..12].copy_from_slice(&0x00002517u32.to_le_bytes());
+        text[12..16].copy_from_slice(&(0x00b53023u32 | 0x55 << 25 | 0x14 << 7).to_le_bytes());
+        let base = text.as_ptr() as u64;
+        let target = base + 0x1abc;
+        let is_flagged = |res: Result<()>| matches!(res, Err(ModuleErr::RelocationFailed(_)));
+
+        check_pcrel_pair(Rv64RelTy::R_RISCV_PCREL_LO12_I, base, base + 4, target).unwrap();
+        check_pcrel_pair(Rv64RelTy::R_RISCV_PCREL_LO12_S, base + 8, base + 12, target).unwrap();
+        // The store immediate read as an I-type one
+        assert!(is_flagged(check_pcrel_pair(
+            Rv64RelTy::R_RISCV_PCREL_LO12_I,
+            base + 8,
+            base + 12,
+            target
+        )));
+
+        // auipc a0, 1
+        text[..4].copy_from_slice(&0x00001517u32.to_le_bytes());
+        assert!(is_flagged(check_pcrel_pair(
+            Rv64RelTy::R_RISCV_PCREL_LO12_I,
+            base,
+            base + 4,
+            target
+        )));
+    }
+
+    #[cfg(feature = "reloc-trace")]
+    #[test]
+    fn trace_orphan_lo12() {
+        use crate::testing::capture_logs;
+
+        // auipc a0, 0; addi a0, a0, 0
+        let mut text = [0u8; 16];
+        text[..4].copy_from_slice(&0x00000517u32.to_le_bytes());
+        text[4..8].copy_from_slice(&0x00050513u32.to_le_bytes());
+        let base = text.as_ptr() as u64;
+        let at = |st_value| Sym {
+            st_value,
+            ..Default::default()
+        };
+        // The label of the LO12 is off by 8 from its auipc
+        let builder = ModuleLoadInfoBuilder::new(EM_RISCV)
+            .with_section(".text", &mut text, 16)
+            .with_symbol("var", at(base + 0x100))
+            .with_symbol(".L0", at(base + 8));
+        let (res, logs) = capture_logs(|| {
+            builder.apply(
+                1,
+                &[
+                    rela(0, 1, Rv64RelTy::R_RISCV_PCREL_HI20, 0),
+                    rela(4, 2, Rv64RelTy::R_RISCV_PCREL_LO12_I, 0),
+                ],
+            )
+        });
+        assert!(matches!(
+            res,
+            Err(ModuleErr::RelocationEntry { ref err, .. })
+                if matches!(**err, ModuleErr::OrphanLo12 { .. })
+        ));
+        let traces: alloc::vec::Vec<_> = logs
+            .iter()
+            .filter(|(level, ..)| *level == log::Level::Trace)
+            .map(|(_, _, message)| message.as_str())
+            .collect();
+        let looks_for = alloc::format!(
+            "LO12 at {:#x} looks for a HI20 at {:#x}",
+            base + 4,
+            base + 8
+        );
+        let candidate = alloc::format!(
+            "candidate HI20 at {:#x} (section base {:#x} + offset 0x0)",
+            base,
+            base
+        );
+        assert!(
+            traces.iter().any(|message| message.contains(&looks_for)),
+            "{:?}",
+            traces
+        );
+        assert!(
+            traces.iter().any(|message| message.contains(&candidate)),
+            "{:?}",
+            traces
+        );
+    }
+
+    #[test]
+    fn hi20_lo12_out_of_range() {
+        // Rounding HI20 up wraps to 0x80000000, which lui sign-extends
+        for address in [
+            0x7ffff9d5,
+            0x7ffff800,
+            0x80000000,
+            0x1_0000_0000,
+            0xffff_fffe_ffff_f000,
+        ] {
+            for (reloc, insn) in [
+                (Rv64RelTy::R_RISCV_HI20, 0x00000537),
+                (Rv64RelTy::R_RISCV_LO12_I, 0x00050513),
+                (Rv64RelTy::R_RISCV_LO12_S, 0x00b53023),
+            ] {
+                assert!(
+                    patch(reloc, insn, address).is_err(),
+                    "{:?} accepted {:#x}",
+                    reloc,
+                    address
+                );
+            }
+        }
+        // The ends of the reach
+        for address in [0x7ffff7ff, 0xffff_ffff_8000_0000] {
+            let hi = patch(Rv64RelTy::R_RISCV_HI20, 0x00000537, address).unwrap();
+            let lo = patch(Rv64RelTy::R_RISCV_LO12_I, 0x00050513, address).unwrap();
+            assert_eq!(lui_pair_target(hi, lo, false), address);
+        }
+    }
+}
@@ -2,7 +2,10 @@ use alloc::format;
 use goblin::elf::SectionHeader;
 use int_enum::IntEnum;
 
-use crate::arch::{Ptr, get_rela_sym_idx, get_rela_type};
+use crate::arch::{
+    FixtureTarget, Ptr, RelocFixture, RelocProgress, get_rela_sym_idx, get_rela_type,
+    run_self_test, section_header,
+};
 use crate::loader::{KernelModuleHelper, ModuleLoadInfo, ModuleOwner};
 use crate::{ModuleErr, Result};
 
@@ -45,14 +48,36 @@ pub enum X86_64RelocationType {
     R_X86_64_PC8 = 15,
     /// Place relative 64-bit signed
     R_X86_64_PC64 = 24,
+    /// Relaxable 32 bit signed pc relative offset to GOT
+    R_X86_64_GOTPCRELX = 41,
+    /// Relaxable 32 bit signed pc relative offset to GOT, with REX prefix
+    R_X86_64_REX_GOTPCRELX = 42,
 }
 
 type X64RelTy = X86_64RelocationType;
 
 impl X86_64RelocationType {
-    fn apply_relocation(&self, location: u64, mut target_addr: u64) -> Result<()> {
+    /// Whether the relocation addresses a GOT slot holding the symbol
+    fn is_got_pcrel(&self) -> bool {
+        matches!(
+            self,
+            X64RelTy::R_X86_64_GOTPCREL
+                | X64RelTy::R_X86_64_GOTPCRELX
+                | X64RelTy::R_X86_64_REX_GOTPCRELX
+        )
+    }
+
+    /// Number of bytes written at the location
+    fn size(&self) -> u64 {
+        match self {
+            X64RelTy::R_X86_64_64 | X64RelTy::R_X86_64_PC64 => 8,
+            X64RelTy::R_X86_64_NONE => 0,
+            _ => 4,
+        }
+    }
+
+    fn apply_relocation(&self, location: Ptr, mut target_addr: u64) -> Result<()> {
         let size;
-        let location = Ptr(location);
         let overflow = || {
             log::error!(
                 "overflow in relocation type {:?}, target address {:#x}",
@@ -85,7 +110,11 @@ impl X86_64RelocationType {
                 }
                 size = 4;
             }
-            X64RelTy::R_X86_64_PC32 | X64RelTy::R_X86_64_PLT32 => {
+            X64RelTy::R_X86_64_PC32
+            | X64RelTy::R_X86_64_PLT32
+            | X64RelTy::R_X86_64_GOTPCREL
+            | X64RelTy::R_X86_64_GOTPCRELX
+            | X64RelTy::R_X86_64_REX_GOTPCRELX => {
                 target_addr = target_addr.wrapping_sub(location.0);
                 size = 4;
             }
@@ -94,10 +123,10 @@ impl X86_64RelocationType {
                 size = 8;
             }
             _ => {
-                return Err(ModuleErr::RelocationFailed(format!(
-                    "Unsupported relocation type: {:?}",
-                    self
-                )));
+                return Err(ModuleErr::UnsupportedRelocation {
+                    arch: "x86-64",
+                    reloc_type: *self as u32,
+                });
             }
         }
         // if (memcmp(loc, &zero, size))
@@ -115,8 +144,8 @@ impl X86_64RelocationType {
         } else {
             // Write the relocated value
             match size {
-                4 => location.write::<u32>(target_addr as u32),
-                8 => location.write::<u64>(target_addr as u64),
+                4 => location.write_checked::<u32>(target_addr as u32)?,
+                8 => location.write_checked::<u64>(target_addr as u64)?,
                 _ => unreachable!(),
             }
         }
@@ -124,8 +153,73 @@ impl X86_64RelocationType {
     }
 }
 
+/// Known-good encodings checked by [`X86_64ArchRelocate::self_test`]
+const SELF_TEST_FIXTURES: &[RelocFixture<X64RelTy>] = &[
+    RelocFixture {
+        reloc: X64RelTy::R_X86_64_PC32,
+        insn: 0,
+        target: FixtureTarget::PcRel(-0x10),
+        expected: 0xfffffff0,
+    },
+    RelocFixture {
+        reloc: X64RelTy::R_X86_64_64,
+        insn: 0,
+        target: FixtureTarget::Abs(0xffffffff80001234),
+        expected: 0xffffffff80001234,
+    },
+    RelocFixture {
+        reloc: X64RelTy::R_X86_64_32S,
+        insn: 0,
+        target: FixtureTarget::Abs(0xffffffff80001234),
+        expected: 0x80001234,
+    },
+    // The ends of the unsigned and signed 32-bit ranges
+    RelocFixture {
+        reloc: X64RelTy::R_X86_64_32,
+        insn: 0,
+        target: FixtureTarget::Abs(0xffffffff),
+        expected: 0xffffffff,
+    },
+    RelocFixture {
+        reloc: X64RelTy::R_X86_64_32S,
+        insn: 0,
+        target: FixtureTarget::Abs(0x7fffffff),
+        expected: 0x7fffffff,
+    },
+    RelocFixture {
+        reloc: X64RelTy::R_X86_64_32S,
+        insn: 0,
+        target: FixtureTarget::Abs(0xffffffff80000000),
+        expected: 0x80000000,
+    },
+];
+
 pub struct X86_64ArchRelocate;
 
+/// Size of a module GOT entry
+const GOT_ENTRY_SIZE: usize = 8;
+
+impl X86_64ArchRelocate {
+    /// Number of bytes to reserve after a section for the GOT entries its
+    /// relocations in `rela_list` may need
+    pub fn stub_reservation(rela_list: &[goblin::elf64::reloc::Rela]) -> usize {
+        rela_list
+            .iter()
+            .filter(|rela| {
+                X64RelTy::try_from(get_rela_type(rela.r_info)).is_ok_and(|ty| ty.is_got_pcrel())
+            })
+            .count()
+            * GOT_ENTRY_SIZE
+    }
+
+    /// Check the relocation handlers against known-good encodings
+    pub fn self_test() -> Result<()> {
+        run_self_test(SELF_TEST_FIXTURES, |reloc, location, address| {
+            reloc.apply_relocation(location, address)
+        })
+    }
+}
+
 #[allow(unused_assignments)]
 impl X86_64ArchRelocate {
     /// See https://elixir.bootlin.com/linux/v6.6/source/arch/x86/kernel/module.c#L252
@@ -135,38 +229,165 @@ impl X86_64ArchRelocate {
         sechdrs: &[SectionHeader],
         load_info: &ModuleLoadInfo,
         module: &ModuleOwner<H>,
+        progress: &mut RelocProgress,
     ) -> Result<()> {
+        let to_section = section_header(sechdrs, rel_section.sh_info as usize)?;
+        let bounds = module
+            .section_bounds(to_section.sh_addr)
+            .unwrap_or(to_section.sh_addr..to_section.sh_addr);
         for rela in rela_list {
             let rel_type = get_rela_type(rela.r_info);
             let sym_idx = get_rela_sym_idx(rela.r_info);
 
             // This is where to make the change
-            let location = sechdrs[rel_section.sh_info as usize].sh_addr + rela.r_offset;
-            let (sym, sym_name) = &load_info.syms[sym_idx];
+            let location = to_section.sh_addr + rela.r_offset;
+            let (sym, sym_name) = match load_info.symbol(sym_idx) {
+                Ok(symbol) => symbol,
+                Err(err) => {
+                    progress.fail(progress.context(rela, ""), err)?;
+                    continue;
+                }
+            };
+
+            let Ok(reloc_type) = X86_64RelocationType::try_from(rel_type) else {
+                progress.fail(
+                    progress.context(rela, sym_name),
+                    ModuleErr::UnsupportedRelocation {
+                        arch: "x86-64",
+                        reloc_type: rel_type,
+                    },
+                )?;
+                continue;
+            };
 
-            let reloc_type = X86_64RelocationType::try_from(rel_type).map_err(|_| {
-                ModuleErr::RelocationFailed(format!("Invalid relocation type: {}", rel_type))
-            })?;
+            if rela
+                .r_offset
+                .checked_add(reloc_type.size())
+                .is_none_or(|end| end > to_section.sh_size)
+            {
+                module_log!(
+                    error,
+                    module.name(),
+                    sym = sym_name,
+                    "{:?} at offset {:#x} is outside of its section",
+                    reloc_type,
+                    rela.r_offset
+                );
+                progress.fail(
+                    progress.context(rela, sym_name),
+                    ModuleErr::RelocationFailed(format!(
+                        "{:?} at offset {:#x} exceeds the section size {:#x}",
+                        reloc_type, rela.r_offset, to_section.sh_size
+                    )),
+                )?;
+                continue;
+            }
 
-            let target_addr = sym.st_value.wrapping_add(rela.r_addend as u64);
+            let mut target_addr = sym.st_value.wrapping_add(rela.r_addend as u64);
+            if reloc_type.is_got_pcrel() {
+                // G + GOT + A - P, the kernel GOT is preferred so kernel
+                // symbols don't get duplicate slots
+                let Some(slot) = H::kernel_got_entry(sym_name)
+                    .or_else(|| module.emit_got_entry(location, sym.st_value))
+                else {
+                    progress.fail(
+                        progress.context(rela, sym_name),
+                        ModuleErr::RelocationFailed(format!(
+                            "No room left for a GOT entry of '{}' referenced at {:#x}",
+                            sym_name, location
+                        )),
+                    )?;
+                    continue;
+                };
+                target_addr = slot.wrapping_add(rela.r_addend as u64);
+            }
 
-            log::info!(
-                "[{}]: Applying relocation {:?} at location {:#x} with target addr {:#x}",
+            module_log!(
+                info,
                 module.name(),
+                "Applying relocation {:?} at location {:#x} with target addr {:#x}",
                 reloc_type,
                 location,
                 target_addr
             );
 
-            let res = reloc_type.apply_relocation(location, target_addr);
+            let res = Ptr::bounded(location, bounds.clone())
+                .and_then(|ptr| reloc_type.apply_relocation(ptr, target_addr));
             match res {
-                Err(e) => {
-                    log::error!("[{}]: '{}' {:?}", module.name(), sym_name, e);
-                    return Err(e);
-                }
-                Ok(_) => { /* Successfully applied relocation */ }
+                Err(e) => progress.fail(progress.context(rela, sym_name), e)?,
+                Ok(_) => progress.applied += 1,
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::ModuleLoadInfoBuilder;
+    use goblin::elf::header::EM_X86_64;
+    use goblin::elf::sym::Sym;
+    use goblin::elf64::reloc::Rela;
+
+    /// Apply `reloc` at `r_offset` of a zeroed 16 byte `.data` against a
+    /// symbol at `value`
+    fn apply(reloc: X64RelTy, r_offset: u64, value: u64) -> (Result<()>, [u8; 16]) {
+        let mut data = [0u8; 16];
+        let sym = Sym {
+            st_value: value,
+            ..Default::default()
+        };
+        let rela = Rela {
+            r_offset,
+            r_info: (1 << 32) | reloc as u64,
+            r_addend: 0,
+        };
+        let res = ModuleLoadInfoBuilder::new(EM_X86_64)
+            .with_section(".data", &mut data, 16)
+            .with_symbol("var", sym)
+            .apply(1, &[rela]);
+        (res, data)
+    }
+
+    fn is_failed(res: &Result<()>) -> bool {
+        matches!(
+            res,
+            Err(ModuleErr::RelocationEntry { err, .. })
+                if matches!(**err, ModuleErr::RelocationFailed(_))
+        )
+    }
+
+    #[test]
+    fn abs32_range() {
+        let (res, data) = apply(X64RelTy::R_X86_64_32, 0, 0xffff_ffff);
+        res.unwrap();
+        assert_eq!(data[..4], 0xffff_ffffu32.to_le_bytes());
+        let (res, _) = apply(X64RelTy::R_X86_64_32, 0, 0x1_0000_0000);
+        assert!(is_failed(&res));
+
+        // Sign extended, e.g. the top 2GB of a -mcmodel=kernel address space
+        for value in [0x7fff_ffff, 0xffff_ffff_8000_0000] {
+            let (res, data) = apply(X64RelTy::R_X86_64_32S, 0, value);
+            res.unwrap();
+            assert_eq!(data[..4], (value as u32).to_le_bytes());
+        }
+        for value in [0x8000_0000, 0xffff_ffff_7fff_ffff] {
+            let (res, _) = apply(X64RelTy::R_X86_64_32S, 0, value);
+            assert!(is_failed(&res), "accepted {:#x}", value);
+        }
+    }
+
+    #[test]
+    fn abs64_past_section_end() {
+        let (res, data) = apply(X64RelTy::R_X86_64_64, 8, 0x1234_5678_9abc_def0);
+        res.unwrap();
+        assert_eq!(data[8..], 0x1234_5678_9abc_def0u64.to_le_bytes());
+        // Straddling the end, and past it
+        for r_offset in [12, 16] {
+            let (res, data) = apply(X64RelTy::R_X86_64_64, r_offset, 0x1234);
+            assert!(is_failed(&res), "accepted offset {}", r_offset);
+            assert_eq!(data, [0; 16]);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use alloc::vec::Vec;
+
+use crate::{ModuleErr, Result};
+
+/// Compression formats recognized by their magic bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    /// Detect the compression format of `data` from its magic bytes
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(&[0x1f, 0x8b]) {
+            Some(Compression::Gzip)
+        } else if data.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Compression::Xz)
+        } else if data.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+}
+
+/// Decompression supplied by the caller, so the loader does not depend on
+/// any particular codec
+pub trait Decompressor {
+    /// Decompress `input` of the given `format`, appending the result to `out`
+    ///
+    /// Any error, e.g. for a corrupt stream, is reported by
+    /// [`decompress_module`] as [`ModuleErr::DecompressionFailed`].
+    fn decompress(&self, format: Compression, input: &[u8], out: &mut Vec<u8>) -> Result<()>;
+}
+
+/// Get the module ELF from `data`, decompressing it into `buf` with
+/// `decompressor` if a compression magic is detected
+pub fn decompress_module<'a>(
+    data: &'a [u8],
+    decompressor: &dyn Decompressor,
+    buf: &'a mut Vec<u8>,
+) -> Result<&'a [u8]> {
+    if data.starts_with(&goblin::elf::header::ELFMAG[..]) {
+        return Ok(data);
+    }
+    let Some(format) = Compression::detect(data) else {
+        log::error!("Module is neither an ELF file nor compressed");
+        return Err(ModuleErr::InvalidElf);
+    };
+    log::info!("Decompressing {:?} module ({} bytes)", format, data.len());
+    buf.clear();
+    if let Err(e) = decompressor.decompress(format, data, buf) {
+        log::error!("Failed to decompress {:?} module: {:?}", format, e);
+        return Err(ModuleErr::DecompressionFailed);
+    }
+    if !buf.starts_with(&goblin::elf::header::ELFMAG[..]) {
+        log::error!("Decompressed {:?} module is not an ELF file", format);
+        return Err(ModuleErr::DecompressionFailed);
+    }
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Stands in for a codec: "decompresses" by dropping the magic bytes, and
+    /// fails like a real codec would on a truncated stream
+    struct StripMagic;
+
+    impl Decompressor for StripMagic {
+        fn decompress(&self, _format: Compression, input: &[u8], out: &mut Vec<u8>) -> Result<()> {
+            match input.get(2..) {
+                Some(rest) if !rest.is_empty() => {
+                    out.extend_from_slice(rest);
+                    Ok(())
+                }
+                _ => Err(ModuleErr::InvalidElf),
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt_stream() {
+        let mut buf = Vec::new();
+        let ok = [0x1f, 0x8b, 0x7f, b'E', b'L', b'F', 2];
+        let elf = decompress_module(&ok, &StripMagic, &mut buf).unwrap();
+        assert_eq!(elf, &ok[2..]);
+
+        let truncated = [0x1f, 0x8b];
+        let err = decompress_module(&truncated, &StripMagic, &mut buf).unwrap_err();
+        assert!(matches!(err, ModuleErr::DecompressionFailed));
+
+        let garbage = [0x1f, 0x8b, 0xde, 0xad];
+        let err = decompress_module(&garbage, &StripMagic, &mut buf).unwrap_err();
+        assert!(matches!(err, ModuleErr::DecompressionFailed));
+    }
+}
@@ -1,12 +1,32 @@
 #![no_std]
 
+/// Log a message on behalf of a loaded module.
+///
+/// Every line is prefixed with `[module]: ` (and `(symbol) ` when given), and
+/// the module name is used as the log target so a host can filter per module.
+macro_rules! module_log {
+    ($lvl:ident, $module:expr, sym = $sym:expr, $($arg:tt)+) => {
+        log::$lvl!(target: $module, "[{}]: ({}) {}", $module, $sym, format_args!($($arg)+))
+    };
+    ($lvl:ident, $module:expr, $($arg:tt)+) => {
+        log::$lvl!(target: $module, "[{}]: {}", $module, format_args!($($arg)+))
+    };
+}
+
 mod arch;
+mod decompress;
 pub mod loader;
 mod module;
 mod parser;
+mod session;
+#[cfg(test)]
+mod testing;
 
-use alloc::string::String;
+use alloc::{boxed::Box, string::String};
+pub use decompress::{Compression, Decompressor, decompress_module};
+pub use module::{ModuleInfo, parse_linux_modinfo};
 pub use parser::ElfParser;
+pub use session::{LoadSession, ModuleRecord};
 extern crate alloc;
 
 type Result<T> = core::result::Result<T, ModuleErr>;
@@ -20,6 +40,150 @@ pub enum ModuleErr {
     MemoryAllocationFailed,
     UnsupportedFeature,
     UndefinedSymbol,
+    DuplicateSymbolDefinition(String),
+    /// Relocating a section failed after `applied` of its entries were patched
+    PartialRelocation {
+        applied: usize,
+        err: Box<ModuleErr>,
+    },
+    /// A replacement function body does not fit in the original function
+    PatchTooLarge {
+        size: usize,
+        capacity: usize,
+    },
+    /// A call from `location` can not reach `target`, not even through a
+    /// stub, because the module allocations are too far apart
+    FragmentationTooLarge {
+        location: u64,
+        target: u64,
+    },
+    /// The module uses `symbol` from `namespace` without importing it
+    NamespaceViolation {
+        symbol: String,
+        namespace: String,
+    },
+    /// The module uses thread-local storage, but no TLS block is available
+    TlsNotSupported,
+    /// A parameter value does not have the size its declared type requires
+    ParamTypeMismatch {
+        name: String,
+        expected: usize,
+        got: usize,
+    },
+    /// The caller supplied [`Decompressor`] failed
+    DecompressionFailed,
+    /// The module has more relocations than [`LoadOptions::max_relocations`]
+    ///
+    /// [`LoadOptions::max_relocations`]: crate::loader::LoadOptions::max_relocations
+    TooManyRelocations {
+        count: usize,
+        limit: usize,
+    },
+    /// The module has more sections than [`ParseLimits::max_sections`]
+    ///
+    /// [`ParseLimits::max_sections`]: crate::loader::ParseLimits::max_sections
+    TooManySections {
+        count: usize,
+        limit: usize,
+    },
+    /// The module has more symbols than [`ParseLimits::max_symbols`]
+    ///
+    /// [`ParseLimits::max_symbols`]: crate::loader::ParseLimits::max_symbols
+    TooManySymbols {
+        count: usize,
+        limit: usize,
+    },
+    /// Every relocation that failed, with
+    /// [`LoadOptions::collect_relocation_failures`]
+    ///
+    /// [`LoadOptions::collect_relocation_failures`]: crate::loader::LoadOptions::collect_relocation_failures
+    MultipleRelocationFailures(alloc::vec::Vec<ModuleErr>),
+    /// `name` is already exported by the module `owner`
+    DuplicateSymbol {
+        name: String,
+        owner: String,
+    },
+    /// The module metadata is incomplete or corrupted
+    InvalidModuleInfo(&'static str),
+    /// The module uses a relocation type the loader can not apply
+    UnsupportedRelocation {
+        arch: &'static str,
+        reloc_type: u32,
+    },
+    /// The PC-relative LO12 relocation at `location` has no HI20 relocation
+    /// at the address of its symbol `symbol_index`
+    OrphanLo12 {
+        location: u64,
+        symbol_index: usize,
+    },
+    /// The module was built against another version of `symbol`: its
+    /// `__versions` section records the CRC `expected`, the kernel has `found`
+    VersionMismatch {
+        symbol: String,
+        expected: u32,
+        found: u32,
+    },
+    /// The module was built for another kernel: its `vermagic` is `found`,
+    /// or missing, instead of the `expected` [`KernelModuleHelper::vermagic`]
+    VermagicMismatch {
+        expected: String,
+        found: Option<String>,
+    },
+    /// A section header index, e.g. the `sh_info` of a relocation section,
+    /// is out of range
+    SectionNotFound {
+        index: usize,
+    },
+    /// The relocation section `section` has entries of `entsize` bytes
+    /// instead of the size of `Elf64_Rela`
+    BadRelaEntsize {
+        section: usize,
+        entsize: u64,
+    },
+    /// A relocation would patch memory at `location`, outside of the section
+    /// it applies to
+    RelocationOutOfBounds {
+        location: u64,
+    },
+    /// The module is big-endian, or the host is, which the relocation
+    /// handlers do not support
+    UnsupportedEndianness,
+    /// The relocation section `section` is `SHT_REL`, whose entries take
+    /// their addend from the patched location, only `SHT_RELA` is supported
+    UnsupportedRelocationSection {
+        section: usize,
+    },
+    /// A relocation entry references the symbol `index` of a symbol table
+    /// with only `table_len` entries
+    SymbolIndexOutOfRange {
+        index: usize,
+        table_len: usize,
+    },
+    /// The module is built for the format version `found` instead of the
+    /// `expected` [`supported_format_version`]
+    FormatVersionMismatch {
+        expected: u16,
+        found: String,
+    },
+    /// The entry at `r_offset` `offset` of the relocation section `section`,
+    /// of type `type_name` against `symbol`, failed with `err`
+    RelocationEntry {
+        section: usize,
+        offset: u64,
+        type_name: String,
+        symbol: String,
+        err: Box<ModuleErr>,
+    },
+    /// The undefined global symbol is provided neither by the kernel nor by
+    /// another module
+    UnresolvedSymbol(String),
+    /// The init function of the module returned this nonzero code
+    InitFailed(i32),
+    /// The module `name` can not be unloaded while `users` depend on it
+    ModuleInUse {
+        name: String,
+        users: alloc::vec::Vec<String>,
+    },
 }
 
 impl core::fmt::Display for ModuleErr {
@@ -32,8 +196,317 @@ impl core::fmt::Display for ModuleErr {
             ModuleErr::MemoryAllocationFailed => write!(f, "Memory allocation failed"),
             ModuleErr::UnsupportedFeature => write!(f, "Unsupported feature encountered"),
             ModuleErr::UndefinedSymbol => write!(f, "Undefined symbol encountered"),
+            ModuleErr::DuplicateSymbolDefinition(name) => {
+                write!(f, "Symbol '{}' is defined more than once", name)
+            }
+            ModuleErr::PartialRelocation { applied, err } => {
+                write!(
+                    f,
+                    "{} ({} relocations applied before failure)",
+                    err, applied
+                )
+            }
+            ModuleErr::PatchTooLarge { size, capacity } => {
+                write!(
+                    f,
+                    "Patch of {} bytes does not fit in a {} byte function",
+                    size, capacity
+                )
+            }
+            ModuleErr::FragmentationTooLarge { location, target } => {
+                write!(
+                    f,
+                    "Call from {:#x} can not reach {:#x} across the module allocations",
+                    location, target
+                )
+            }
+            ModuleErr::NamespaceViolation { symbol, namespace } => {
+                write!(
+                    f,
+                    "Symbol '{}' is in namespace {}, which the module does not import",
+                    symbol, namespace
+                )
+            }
+            ModuleErr::TlsNotSupported => write!(f, "Thread-local storage is not supported"),
+            ModuleErr::ParamTypeMismatch {
+                name,
+                expected,
+                got,
+            } => {
+                write!(
+                    f,
+                    "Parameter '{}' takes {} bytes, got {}",
+                    name, expected, got
+                )
+            }
+            ModuleErr::DecompressionFailed => write!(f, "Module decompression failed"),
+            ModuleErr::TooManyRelocations { count, limit } => {
+                write!(
+                    f,
+                    "Module has {} relocations, exceeding the limit of {}",
+                    count, limit
+                )
+            }
+            ModuleErr::TooManySections { count, limit } => {
+                write!(
+                    f,
+                    "Module has {} sections, exceeding the limit of {}",
+                    count, limit
+                )
+            }
+            ModuleErr::TooManySymbols { count, limit } => {
+                write!(
+                    f,
+                    "Module has {} symbols, exceeding the limit of {}",
+                    count, limit
+                )
+            }
+            ModuleErr::MultipleRelocationFailures(failures) => {
+                write!(f, "{} relocations failed", failures.len())?;
+                for err in failures {
+                    write!(f, "\n  {}", err)?;
+                }
+                Ok(())
+            }
+            ModuleErr::DuplicateSymbol { name, owner } => {
+                write!(f, "Symbol '{}' is already exported by {}", name, owner)
+            }
+            ModuleErr::InvalidModuleInfo(reason) => write!(f, "Invalid module info: {}", reason),
+            ModuleErr::UnsupportedRelocation { arch, reloc_type } => {
+                write!(f, "Unsupported {} relocation type {}", arch, reloc_type)
+            }
+            ModuleErr::OrphanLo12 {
+                location,
+                symbol_index,
+            } => {
+                write!(
+                    f,
+                    "LO12 relocation at {:#x} has no matching HI20 (symbol {})",
+                    location, symbol_index
+                )
+            }
+            ModuleErr::VersionMismatch {
+                symbol,
+                expected,
+                found,
+            } => {
+                write!(
+                    f,
+                    "Module disagrees about version of symbol '{}': expected CRC {:#010x}, kernel has {:#010x}",
+                    symbol, expected, found
+                )
+            }
+            ModuleErr::VermagicMismatch { expected, found } => match found {
+                Some(found) => write!(
+                    f,
+                    "Module version magic '{}' should be '{}'",
+                    found, expected
+                ),
+                None => write!(f, "Module has no version magic, expected '{}'", expected),
+            },
+            ModuleErr::SectionNotFound { index } => {
+                write!(f, "Section {} not found", index)
+            }
+            ModuleErr::BadRelaEntsize { section, entsize } => {
+                write!(
+                    f,
+                    "Relocation section {} has {} byte entries, expected 24",
+                    section, entsize
+                )
+            }
+            ModuleErr::RelocationOutOfBounds { location } => {
+                write!(f, "Relocation at {:#x} is outside of its section", location)
+            }
+            ModuleErr::UnsupportedEndianness => write!(f, "Unsupported endianness"),
+            ModuleErr::UnsupportedRelocationSection { section } => {
+                write!(
+                    f,
+                    "Relocation section {} is SHT_REL, only SHT_RELA is supported",
+                    section
+                )
+            }
+            ModuleErr::SymbolIndexOutOfRange { index, table_len } => {
+                write!(
+                    f,
+                    "Symbol index {} is out of range of a table of {} symbols",
+                    index, table_len
+                )
+            }
+            ModuleErr::FormatVersionMismatch { expected, found } => {
+                write!(
+                    f,
+                    "Module format version {} is not the supported {}",
+                    found, expected
+                )
+            }
+            ModuleErr::RelocationEntry {
+                section,
+                offset,
+                type_name,
+                symbol,
+                err,
+            } => {
+                write!(
+                    f,
+                    "{} (relocation section {}, r_offset {:#x}, {}",
+                    err, section, offset, type_name
+                )?;
+                if !symbol.is_empty() {
+                    write!(f, " against '{}'", symbol)?;
+                }
+                write!(f, ")")
+            }
+            ModuleErr::UnresolvedSymbol(name) => write!(f, "Unresolved symbol '{}'", name),
+            ModuleErr::InitFailed(code) => write!(f, "Module init failed with {}", code),
+            ModuleErr::ModuleInUse { name, users } => {
+                write!(f, "Module {} is in use by {}", name, users.join(", "))
+            }
         }
     }
 }
 
 impl core::error::Error for ModuleErr {}
+
+/// Architecture a module is built for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModuleArch {
+    Riscv64,
+    X86_64,
+    Aarch64,
+    LoongArch64,
+}
+
+impl ModuleArch {
+    /// Map an ELF `e_machine` to the architecture, if it is supported
+    pub const fn from_machine(e_machine: u16) -> Option<Self> {
+        match e_machine {
+            goblin::elf::header::EM_RISCV => Some(ModuleArch::Riscv64),
+            goblin::elf::header::EM_X86_64 => Some(ModuleArch::X86_64),
+            goblin::elf::header::EM_AARCH64 => Some(ModuleArch::Aarch64),
+            goblin::elf::header::EM_LOONGARCH => Some(ModuleArch::LoongArch64),
+            _ => None,
+        }
+    }
+}
+
+/// Get the architecture the module ELF in `elf_data` is built for, reading
+/// only its header
+pub fn module_arch(elf_data: &[u8]) -> Result<ModuleArch> {
+    let header = goblin::elf::Elf::parse_header(elf_data).map_err(|_| ModuleErr::InvalidElf)?;
+    ModuleArch::from_machine(header.e_machine).ok_or_else(|| {
+        log::error!("Unsupported machine type: {}", header.e_machine);
+        ModuleErr::UnsupportedArch
+    })
+}
+
+/// Get the names of the symbols the module ELF in `elf_data` needs from the
+/// kernel or other modules, without allocating or relocating anything, see
+/// [`ParsedModule::undefined_symbols`].
+///
+/// A caller can check them before loading, to reject a module early with
+/// the precise symbol that is missing.
+///
+/// [`ParsedModule::undefined_symbols`]: crate::loader::ParsedModule::undefined_symbols
+pub fn collect_undefined_symbols(elf_data: &[u8]) -> Result<alloc::vec::Vec<String>> {
+    Ok(loader::ParsedModule::parse(elf_data)?.undefined_symbols())
+}
+
+/// Get the module format version this loader understands, see
+/// [`kmod::FORMAT_VERSION`]
+pub const fn supported_format_version() -> u16 {
+    kmod::FORMAT_VERSION
+}
+
+/// Run the relocation handlers of the host architecture against built-in
+/// fixtures and check the results match known-good encodings.
+///
+/// This catches a miscompiled or mis-ported loader before it touches a real
+/// module.
+pub fn self_test() -> Result<()> {
+    if cfg!(target_arch = "riscv64") {
+        self_test_arch(ModuleArch::Riscv64)
+    } else if cfg!(target_arch = "loongarch64") {
+        self_test_arch(ModuleArch::LoongArch64)
+    } else if cfg!(target_arch = "aarch64") {
+        self_test_arch(ModuleArch::Aarch64)
+    } else if cfg!(target_arch = "x86_64") {
+        self_test_arch(ModuleArch::X86_64)
+    } else {
+        Ok(())
+    }
+}
+
+/// Run the relocation self test of `arch`, see [`self_test`].
+///
+/// The handlers only pack bits into a scratch buffer, so every architecture
+/// can be checked on any host, e.g. the RISC-V encodings on an x86_64 build
+/// machine.
+pub fn self_test_arch(arch: ModuleArch) -> Result<()> {
+    match arch {
+        ModuleArch::Riscv64 => arch::Riscv64ArchRelocate::self_test(),
+        ModuleArch::X86_64 => arch::X86_64ArchRelocate::self_test(),
+        ModuleArch::Aarch64 => arch::Aarch64ArchRelocate::self_test(),
+        ModuleArch::LoongArch64 => arch::Loongarch64ArchRelocate::self_test(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ElfBuilder, capture_logs};
+    use goblin::elf::header::{EM_AARCH64, EM_ARM, EM_LOONGARCH, EM_RISCV, EM_X86_64};
+
+    #[test]
+    fn relocation_self_tests() {
+        self_test().unwrap();
+        for arch in [
+            ModuleArch::Riscv64,
+            ModuleArch::X86_64,
+            ModuleArch::Aarch64,
+            ModuleArch::LoongArch64,
+        ] {
+            if let Err(err) = self_test_arch(arch) {
+                panic!("{:?} self test failed: {}", arch, err);
+            }
+        }
+    }
+
+    #[test]
+    fn module_arch_by_machine() {
+        for (machine, arch) in [
+            (EM_RISCV, ModuleArch::Riscv64),
+            (EM_X86_64, ModuleArch::X86_64),
+            (EM_AARCH64, ModuleArch::Aarch64),
+            (EM_LOONGARCH, ModuleArch::LoongArch64),
+        ] {
+            let elf = ElfBuilder::new(machine).build();
+            assert_eq!(module_arch(&elf).unwrap(), arch);
+        }
+        let elf = ElfBuilder::new(EM_ARM).build();
+        assert!(matches!(module_arch(&elf), Err(ModuleErr::UnsupportedArch)));
+        assert!(matches!(
+            module_arch(b"\x7fELF"),
+            Err(ModuleErr::InvalidElf)
+        ));
+    }
+
+    #[test]
+    fn module_log_format() {
+        let ((), logs) = capture_logs(|| {
+            module_log!(warn, "hello", "loaded at {:#x}", 0x1000);
+            module_log!(error, "hello", sym = "printk", "unresolved");
+        });
+        let logs: alloc::vec::Vec<_> = logs
+            .iter()
+            .map(|(level, target, message)| (*level, target.as_str(), message.as_str()))
+            .collect();
+        // The module is the target and prefixes the message, then the symbol
+        assert_eq!(
+            logs,
+            [
+                (log::Level::Warn, "hello", "[hello]: loaded at 0x1000"),
+                (log::Level::Error, "hello", "[hello]: (printk) unresolved"),
+            ]
+        );
+    }
+}
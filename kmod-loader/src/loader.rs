@@ -1,15 +1,17 @@
-use crate::{ModuleErr, Result, module::ModuleInfo};
+pub use crate::arch::RelocationEntry;
+use crate::{LoadSession, ModuleErr, Result, arch::RelocProgress, module::ModuleInfo};
 
 use alloc::{
     boxed::Box,
+    collections::{BTreeMap, BTreeSet},
     format,
     string::{String, ToString},
     vec::Vec,
 };
 use bitflags::bitflags;
-use core::{ffi::CStr, fmt::Display};
+use core::{fmt::Display, ops::Range};
 use goblin::elf::{Elf, SectionHeader};
-use kmod::Module;
+use kmod::{ExitCall, InitCall, KernelParam, KernelSymbol, Module, ModuleParam};
 
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -20,6 +22,33 @@ bitflags! {
     }
 }
 
+bitflags! {
+    /// Kernel taints caused by loading a module
+    ///
+    /// See <https://elixir.bootlin.com/linux/v6.6/source/include/linux/panic.h#L55>
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct Taint: u32 {
+        /// The module has no GPL compatible license
+        const PROPRIETARY_MODULE = 1 << 0;
+        /// The module was loaded despite a symbol version or version magic
+        /// mismatch
+        const FORCED_MODULE = 1 << 1;
+    }
+}
+
+/// See <https://elixir.bootlin.com/linux/v6.6/source/include/linux/license.h#L5>
+fn license_is_gpl_compatible(license: &str) -> bool {
+    matches!(
+        license,
+        "GPL"
+            | "GPL v2"
+            | "GPL and additional rights"
+            | "Dual BSD/GPL"
+            | "Dual MIT/GPL"
+            | "Dual MPL/GPL"
+    )
+}
+
 impl Display for SectionPerm {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let mut perms = String::new();
@@ -61,37 +90,589 @@ pub trait SectionMemOps {
     fn change_perms(&mut self, perms: SectionPerm) -> bool;
 }
 
+/// Identifies a memory pool that module sections can be allocated from.
+///
+/// `MemoryPool(0)` is the default pool.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryPool(pub usize);
+
+/// Decide which memory pool a section is allocated from, given the section
+/// name, its ELF flags and its size.
+pub type SectionPlacer = fn(name: &str, sh_flags: u64, size: usize) -> MemoryPool;
+
+/// The default placer puts every section in the default pool
+fn default_section_placer(_name: &str, _sh_flags: u64, _size: usize) -> MemoryPool {
+    MemoryPool::default()
+}
+
+const PAGE_SIZE: usize = 4096;
+/// Size of the huge pages requested by [`LoadOptions::huge_pages`]
+pub const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+/// Hints describing how a section allocation should be backed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocHint {
+    /// Memory pool chosen by the [`SectionPlacer`]
+    pub pool: MemoryPool,
+    /// Required alignment of the allocation
+    pub align: usize,
+    /// Back the allocation with huge pages if possible
+    pub huge_page: bool,
+}
+
+/// An entry of the `.altinstructions` section.
+///
+/// The offsets are relative to the address of the field holding them, the
+/// replacement bytes usually live in `.altinstr_replacement`.
+/// See <https://elixir.bootlin.com/linux/v6.6/source/arch/x86/include/asm/alternative.h#L67>
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct AltInstr {
+    /// Offset to the original instructions
+    pub instr_offset: i32,
+    /// Offset to the replacement instructions
+    pub repl_offset: i32,
+    /// CPU feature bit, checked with [`KernelModuleHelper::cpu_has`]
+    pub feature: u32,
+    /// Length of the original instructions
+    pub instrlen: u8,
+    /// Length of the replacement instructions, at most `instrlen`
+    pub replacementlen: u8,
+}
+
+/// A module init function
+pub type InitFn = unsafe extern "C" fn() -> core::ffi::c_int;
+
+/// Exit function of a module
+pub type ExitFn = unsafe extern "C" fn();
+
+/// Called by [`ModuleOwner::call_init`] instead of the module's init
+/// function, which it receives so it can call through
+pub type InitOverride = fn(original: Option<InitFn>) -> i32;
+
+/// Options controlling how a module is loaded
+#[derive(Debug, Clone, Default)]
+pub struct LoadOptions {
+    /// Back the text sections with huge pages to reduce TLB pressure.
+    ///
+    /// This is only a hint, see [`AllocHint::huge_page`]: the default
+    /// [`KernelModuleHelper::alloc`] ignores it, so the helper must override
+    /// it to honour the request. A text section that does not start on a
+    /// [`HUGE_PAGE_SIZE`] boundary is reported as
+    /// [`LoadWarning::HugePagesIgnored`].
+    pub huge_pages: bool,
+    /// Reject modules with more relocations than this, across all sections.
+    /// `None` means no limit.
+    pub max_relocations: Option<usize>,
+    /// Keep applying relocations after a failure and report every failed
+    /// entry at once as [`ModuleErr::MultipleRelocationFailures`].
+    ///
+    /// Meant for validating a module, it is never returned loaded when a
+    /// relocation failed.
+    pub collect_relocation_failures: bool,
+    /// Run this instead of the module's init function, e.g. to instrument it
+    pub init_override: Option<InitOverride>,
+    /// Fail on relocations whose arithmetic wraps, e.g. a RISC-V label
+    /// difference that would be negative, instead of silently wrapping
+    pub strict_relocations: bool,
+    /// Let [`LoadSession::register_export`] shadow a symbol another module
+    /// already exports, instead of failing with [`ModuleErr::DuplicateSymbol`]
+    ///
+    /// [`LoadSession::register_export`]: crate::LoadSession::register_export
+    pub allow_symbol_override: bool,
+    /// Load the module even if the CRC of a symbol in its `__versions`
+    /// section differs from [`KernelModuleHelper::symbol_crc`], tainting the
+    /// kernel, instead of failing with [`ModuleErr::VersionMismatch`]
+    pub force_modversions: bool,
+    /// Load the module even if its `vermagic` differs from
+    /// [`KernelModuleHelper::vermagic`], tainting the kernel, instead of
+    /// failing with [`ModuleErr::VermagicMismatch`]
+    pub force_vermagic: bool,
+}
+
+/// A non-fatal condition found while loading a module
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadWarning {
+    /// `.modinfo` declares this many modules, only the first one is loaded
+    ExtraModules(usize),
+    /// The module declares no license and taints the kernel
+    MissingLicense,
+    /// The module license is not GPL compatible and taints the kernel
+    ProprietaryLicense(String),
+    /// A weak symbol could not be resolved and is left at 0
+    UnresolvedWeakSymbol(String),
+    /// The CRC of a symbol differs from the kernel's, see
+    /// [`LoadOptions::force_modversions`]
+    ForcedVersion(String),
+    /// The module version magic, if any, differs from the kernel's, see
+    /// [`LoadOptions::force_vermagic`]
+    ForcedVermagic(Option<String>),
+    /// [`LoadOptions::huge_pages`] was requested, but the text section is
+    /// not allocated on a huge page
+    HugePagesIgnored(String),
+}
+
+impl Display for LoadWarning {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            LoadWarning::ExtraModules(count) => {
+                write!(f, "Found {} modules, only the first one is loaded", count)
+            }
+            LoadWarning::MissingLicense => write!(f, "Module license not specified"),
+            LoadWarning::ProprietaryLicense(license) => {
+                write!(f, "Module license '{}' is not GPL compatible", license)
+            }
+            LoadWarning::UnresolvedWeakSymbol(name) => {
+                write!(f, "Unresolved weak symbol '{}'", name)
+            }
+            LoadWarning::ForcedVersion(name) => {
+                write!(f, "Forced load despite the version of symbol '{}'", name)
+            }
+            LoadWarning::ForcedVermagic(Some(vermagic)) => {
+                write!(f, "Forced load despite the version magic '{}'", vermagic)
+            }
+            LoadWarning::ForcedVermagic(None) => {
+                write!(f, "Forced load despite the missing version magic")
+            }
+            LoadWarning::HugePagesIgnored(section) => {
+                write!(f, "Section '{}' is not backed by huge pages", section)
+            }
+        }
+    }
+}
+
 /// Trait for kernel module helper functions
 pub trait KernelModuleHelper {
+    /// Host bookkeeping attached to each loaded module, see
+    /// [`ModuleOwner::userdata`]
+    type UserData;
     /// Allocate virtual memory for module section
     fn vmalloc(size: usize) -> Box<dyn SectionMemOps>;
-    /// Resolve symbol name to address
+    /// Allocate virtual memory for module section, honoring the given hint
+    fn alloc(size: usize, _hint: AllocHint) -> Box<dyn SectionMemOps> {
+        // Default implementation ignores the hint
+        Self::vmalloc(size)
+    }
+    /// Allocate memory for an executable (`SHF_EXECINSTR`) section, e.g.
+    /// from a dedicated code region on hosts that keep code and data apart.
+    ///
+    /// The section is freed by dropping the returned memory, which should
+    /// hand it back to the pool it came from.
+    fn alloc_exec(size: usize, hint: AllocHint) -> Box<dyn SectionMemOps> {
+        // Default implementation uses the same allocator for all sections
+        Self::alloc(size, hint)
+    }
+    /// Allocate memory for a non-executable section, see
+    /// [`KernelModuleHelper::alloc_exec`]
+    fn alloc_data(size: usize, hint: AllocHint) -> Box<dyn SectionMemOps> {
+        // Default implementation uses the same allocator for all sections
+        Self::alloc(size, hint)
+    }
+    /// Resolve symbol name to address.
+    ///
+    /// Loaders should prefer [`KernelModuleHelper::resolve_symbols`], which
+    /// resolves a whole module at once.
     fn resolve_symbol(name: &str) -> Option<usize>;
+    /// Resolve a batch of symbol names, writing the address of `names[i]` to
+    /// `out[i]`.
+    ///
+    /// The loader resolves all undefined symbols of a module with one call, a
+    /// kernel with a slow lookup, e.g. a scan of kallsyms, should override
+    /// this to resolve the batch under one lock or in one pass.
+    fn resolve_symbols(names: &[&str], out: &mut [Option<usize>]) {
+        // Default implementation resolves the symbols one at a time
+        for (name, addr) in names.iter().zip(out.iter_mut()) {
+            *addr = Self::resolve_symbol(name);
+        }
+    }
+    /// Get the namespace the kernel symbol `name` is exported in, if any.
+    ///
+    /// Modules must import a namespace to use its symbols.
+    fn symbol_namespace(_name: &str) -> Option<String> {
+        // Default implementation exports everything without a namespace
+        None
+    }
+    /// Get the address of the kernel GOT slot holding the symbol `name`.
+    ///
+    /// GOT references to kernel symbols use this slot when available instead
+    /// of a per-module GOT entry.
+    fn kernel_got_entry(_name: &str) -> Option<u64> {
+        // Default implementation has no kernel GOT
+        None
+    }
+    /// Reserve `size` bytes aligned to `align` in the static TLS area for the
+    /// module's thread-local variables, and return the offset of the block
+    /// from the thread pointer.
+    ///
+    /// The host is responsible for copying the module's `.tdata` into the
+    /// block of every thread and zeroing the rest.
+    fn tls_block_offset(_size: usize, _align: usize) -> Option<u64> {
+        // Default implementation has no static TLS area for modules
+        None
+    }
+    /// Check whether the CPU has the feature `feature`, as numbered by the
+    /// [`AltInstr`] entries of the module's `.altinstructions` section
+    fn cpu_has(_feature: u32) -> bool {
+        // Default implementation reports no optional features
+        false
+    }
+    /// Set the attributes of the `len` bytes of module memory at `vaddr`, for
+    /// hosts managing module mappings by address rather than through
+    /// [`SectionMemOps::change_perms`]. Memory is always readable.
+    ///
+    /// Returns whether the attributes could be applied.
+    fn set_memory_attributes(_vaddr: usize, _len: usize, _exec: bool, _write: bool) -> bool {
+        // Default implementation relies on SectionMemOps::change_perms
+        true
+    }
+    /// Get the version magic of the kernel, checked against the `vermagic`
+    /// entry of the module's `.modinfo`
+    fn vermagic() -> Option<&'static str> {
+        // Default implementation does not check the version magic
+        None
+    }
+    /// Get the CRC of the kernel symbol `name`, checked against the module's
+    /// `__versions` section
+    fn symbol_crc(_name: &str) -> Option<u32> {
+        // Default implementation has no symbol versions
+        None
+    }
     /// Flush CPU cache for the given memory region
     fn flsuh_cache(_addr: usize, _size: usize) {
         // Default implementation does nothing
     }
 }
 
+/// Hash of a symbol name used by [`ModuleLoader::with_symbol_hashes`],
+/// 64-bit FNV-1a over the demangled name
+pub const fn symbol_hash(name: &str) -> u64 {
+    let bytes = name.as_bytes();
+    let mut hash = 0xcbf29ce484222325u64;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+        i += 1;
+    }
+    hash
+}
+
+/// Sanity limits checked before the ELF tables are allocated, so a malformed
+/// module can not make the parser allocate huge vectors
+#[derive(Debug, Clone, Copy)]
+pub struct ParseLimits {
+    /// Maximum number of section headers
+    pub max_sections: usize,
+    /// Maximum number of entries across the symbol tables
+    pub max_symbols: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits {
+            max_sections: 1 << 15,
+            max_symbols: 1 << 20,
+        }
+    }
+}
+
+impl ParseLimits {
+    /// Check the section and symbol counts declared by `elf_data` without
+    /// parsing anything else
+    fn check(&self, elf_data: &[u8]) -> Result<()> {
+        use goblin::elf::section_header::{SHT_DYNSYM, SHT_SYMTAB};
+        let header = Elf::parse_header(elf_data).map_err(|_| ModuleErr::InvalidElf)?;
+        let ctx = goblin::container::Ctx::new(
+            header.container().map_err(|_| ModuleErr::InvalidElf)?,
+            header.endianness().map_err(|_| ModuleErr::InvalidElf)?,
+        );
+        let shoff = header.e_shoff as usize;
+        // With more than SHN_LORESERVE sections e_shnum is 0 and the real
+        // count is in the sh_size of the first section header
+        let count = if header.e_shnum == 0 && shoff != 0 {
+            SectionHeader::parse(elf_data, shoff, 1, ctx)
+                .map_err(|_| ModuleErr::InvalidElf)?
+                .first()
+                .map_or(0, |shdr| shdr.sh_size as usize)
+        } else {
+            header.e_shnum as usize
+        };
+        if count > self.max_sections {
+            log::error!(
+                "Module declares {} sections, more than the limit of {}",
+                count,
+                self.max_sections
+            );
+            return Err(ModuleErr::TooManySections {
+                count,
+                limit: self.max_sections,
+            });
+        }
+        if count == 0 {
+            return Ok(());
+        }
+
+        let count = SectionHeader::parse(elf_data, shoff, count, ctx)
+            .map_err(|_| ModuleErr::InvalidElf)?
+            .iter()
+            .filter(|shdr| shdr.sh_type == SHT_SYMTAB || shdr.sh_type == SHT_DYNSYM)
+            .map(|shdr| shdr.sh_size.checked_div(shdr.sh_entsize).unwrap_or(0) as usize)
+            .fold(0usize, usize::saturating_add);
+        if count > self.max_symbols {
+            log::error!(
+                "Module declares {} symbols, more than the limit of {}",
+                count,
+                self.max_symbols
+            );
+            return Err(ModuleErr::TooManySymbols {
+                count,
+                limit: self.max_symbols,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Demangled names of the undefined symbols of `elf` that are not weak, i.e.
+/// the ones the module can not be loaded without
+fn required_symbols<'e>(elf: &'e Elf) -> impl Iterator<Item = String> + 'e {
+    elf.syms
+        .iter()
+        .skip(1)
+        .filter(|sym| {
+            sym.st_shndx == goblin::elf::section_header::SHN_UNDEF as usize
+                && sym.st_bind() != goblin::elf::sym::STB_WEAK
+        })
+        .map(|sym| {
+            let raw_name = elf.strtab.get_at(sym.st_name).unwrap_or("<unknown>");
+            format!("{:#}", rustc_demangle::demangle(raw_name))
+        })
+}
+
+/// Fail on the first `SHT_REL` section that patches an allocated section.
+///
+/// Like the 64-bit ports of Linux, only `SHT_RELA` is supported, and a REL
+/// entry must not be misread as a RELA one.
+///
+/// See <https://elixir.bootlin.com/linux/v6.6/source/include/linux/moduleloader.h>
+fn reject_rel_sections(elf: &Elf) -> Result<()> {
+    for (idx, shdr) in elf.section_headers.iter().enumerate() {
+        if shdr.sh_type != goblin::elf::section_header::SHT_REL {
+            continue;
+        }
+        let to_section = crate::arch::section_header(&elf.section_headers, shdr.sh_info as usize)?;
+        // Don't bother with non-allocated sections, like apply_relocations
+        if to_section.sh_flags & goblin::elf::section_header::SHF_ALLOC as u64 == 0 {
+            continue;
+        }
+        log::error!(
+            "Relocation section {} ('{}') is SHT_REL, REL relocations are unsupported",
+            idx,
+            elf.shdr_strtab.get_at(shdr.sh_name).unwrap_or("<unknown>")
+        );
+        return Err(ModuleErr::UnsupportedRelocationSection { section: idx });
+    }
+    Ok(())
+}
+
+/// A module ELF parsed once, so it can be inspected, validated and then
+/// loaded without parsing the bytes again.
+pub struct ParsedModule<'a> {
+    elf: Elf<'a>,
+    elf_data: &'a [u8],
+    /// Indices of the `SHT_RELA` sections
+    rela_sections: Vec<usize>,
+}
+
+impl<'a> ParsedModule<'a> {
+    /// Parse the module ELF borrowed from `elf_data`
+    pub fn parse(elf_data: &'a [u8]) -> Result<Self> {
+        Self::parse_with_limits(elf_data, ParseLimits::default())
+    }
+
+    /// Parse the module ELF borrowed from `elf_data`, rejecting it before
+    /// any table is allocated if it exceeds `limits`
+    pub fn parse_with_limits(elf_data: &'a [u8], limits: ParseLimits) -> Result<Self> {
+        // The relocation handlers read and patch the module memory in native
+        // byte order and pack the immediates of little-endian encodings, so
+        // reject anything else before the tables are read
+        // TODO: byte swap the fields to support big-endian modules
+        let ei_data = elf_data.get(goblin::elf::header::EI_DATA).copied();
+        if ei_data == Some(goblin::elf::header::ELFDATA2MSB) || cfg!(target_endian = "big") {
+            log::error!(
+                "Only little-endian modules on little-endian hosts are supported (EI_DATA = {:?})",
+                ei_data
+            );
+            return Err(ModuleErr::UnsupportedEndianness);
+        }
+        limits.check(elf_data)?;
+        let elf = Elf::parse(elf_data).map_err(|_| ModuleErr::InvalidElf)?;
+        if !elf.is_64 {
+            return Err(ModuleErr::UnsupportedArch);
+        }
+        let rela_sections = elf
+            .section_headers
+            .iter()
+            .enumerate()
+            .filter(|(_, shdr)| shdr.sh_type == goblin::elf::section_header::SHT_RELA)
+            .map(|(idx, _)| idx)
+            .collect();
+        Ok(ParsedModule {
+            elf,
+            elf_data,
+            rela_sections,
+        })
+    }
+
+    /// Get the section headers of the module
+    pub fn section_headers(&self) -> &[SectionHeader] {
+        &self.elf.section_headers
+    }
+
+    /// Get the symbol table of the module
+    pub fn syms(&self) -> &goblin::elf::Symtab<'a> {
+        &self.elf.syms
+    }
+
+    /// Get the indices of the relocation sections
+    pub fn rela_sections(&self) -> &[usize] {
+        &self.rela_sections
+    }
+
+    /// Get the demangled names of the symbols the module needs from the
+    /// kernel or other modules, sorted and without duplicates.
+    ///
+    /// Weak undefined symbols are left out, the module loads without them.
+    pub fn undefined_symbols(&self) -> Vec<String> {
+        let names: BTreeSet<String> = required_symbols(&self.elf).collect();
+        names.into_iter().collect()
+    }
+
+    /// Check that the module targets a supported architecture and that its
+    /// `.modinfo` and relocation sections are well-formed
+    pub fn validate(&self) -> Result<()> {
+        match self.elf.header.e_machine {
+            goblin::elf::header::EM_RISCV
+            | goblin::elf::header::EM_LOONGARCH
+            | goblin::elf::header::EM_AARCH64
+            | goblin::elf::header::EM_X86_64 => {}
+            machine => {
+                log::error!("Unsupported machine type: {}", machine);
+                return Err(ModuleErr::UnsupportedArch);
+            }
+        }
+        let has_modinfo = self
+            .elf
+            .section_headers
+            .iter()
+            .any(|shdr| self.elf.shdr_strtab.get_at(shdr.sh_name) == Some(".modinfo"));
+        if !has_modinfo {
+            log::error!("Section '.modinfo' not found");
+            return Err(ModuleErr::InvalidElf);
+        }
+        reject_rel_sections(&self.elf)?;
+        for &idx in &self.rela_sections {
+            let shdr = &self.elf.section_headers[idx];
+            // Size of Elf64_Rela
+            if shdr.sh_entsize != 24 {
+                log::error!(
+                    "Relocation section {} has {} byte entries, expected 24",
+                    idx,
+                    shdr.sh_entsize
+                );
+                return Err(ModuleErr::BadRelaEntsize {
+                    section: idx,
+                    entsize: shdr.sh_entsize,
+                });
+            }
+            let end = shdr.sh_offset.checked_add(shdr.sh_size);
+            if end.is_none_or(|end| end > self.elf_data.len() as u64) {
+                log::error!("Malformed relocation section {}", idx);
+                return Err(ModuleErr::InvalidElf);
+            }
+        }
+        Ok(())
+    }
+}
+
 pub struct ModuleLoader<'a, H: KernelModuleHelper> {
     elf: Elf<'a>,
     elf_data: &'a [u8],
+    rela_sections: Vec<usize>,
+    /// Address each section was linked at, before it is allocated
+    link_addrs: Vec<u64>,
     module_name: Option<&'a str>,
+    placer: SectionPlacer,
+    options: LoadOptions,
+    userdata: Option<H::UserData>,
+    warnings: core::cell::RefCell<Vec<LoadWarning>>,
+    /// Symbol namespaces declared by the module's `import_ns` entries
+    imported_namespaces: Vec<String>,
+    /// Offset of each `SHF_TLS` section in the module's TLS block
+    tls_offsets: Vec<Option<u64>>,
+    /// Thread pointer offset of the TLS block, see
+    /// [`KernelModuleHelper::tls_block_offset`]
+    tls_base: Option<u64>,
+    /// Addresses of the undefined symbols, replacing
+    /// [`KernelModuleHelper::resolve_symbol`]
+    symbol_map: Option<&'a [(&'a str, u64)]>,
+    /// Memory the sections are carved from instead of calling
+    /// [`KernelModuleHelper::alloc`], see
+    /// [`ModuleLoader::load_with_symbols_into`]
+    region: Option<&'static mut [u8]>,
+    /// `(symbol_hash(name), name, address)` entries sorted by hash, consulted
+    /// before [`KernelModuleHelper::resolve_symbol`]
+    symbol_hashes: Option<&'a [(u64, &'a str, u64)]>,
+    /// Modules whose exports resolve symbols the kernel does not define
+    session: Option<&'a LoadSession>,
+    /// Modules of the session whose exports resolved a symbol
+    used_modules: core::cell::RefCell<BTreeSet<String>>,
+    /// `(symbol, crc)` pairs of the `__versions` section
+    versions: Vec<(String, u32)>,
     __helper: core::marker::PhantomData<H>,
 }
 
 struct SectionPages {
     name: String,
     addr: Box<dyn SectionMemOps>,
+    /// Page aligned size of the allocation
     size: usize,
+    /// Size of the section itself
+    sh_size: usize,
     perms: SectionPerm,
+    pool: MemoryPool,
+    /// Offset and size of the area reserved for relocation stubs
+    stub_offset: usize,
+    stub_size: usize,
+    /// Bytes of the stub area handed out so far
+    stubs_used: core::cell::Cell<usize>,
 }
 
 pub struct ModuleOwner<H: KernelModuleHelper> {
     module_info: ModuleInfo,
     pages: Vec<SectionPages>,
     name: String,
+    /// Address and size of each function defined by the module
+    functions: BTreeMap<String, (usize, usize)>,
     module: Module,
+    /// Value returned by the init function, if it has run
+    init_result: Option<i32>,
+    userdata: Option<H::UserData>,
+    taints: Taint,
+    /// The init function succeeded and the exit function has not run yet
+    live: bool,
+    /// GOT entries emitted in the stub areas, keyed by the index of the
+    /// section page and the value of the entry
+    got_entries: core::cell::RefCell<BTreeMap<(usize, u64), u64>>,
+    init_override: Option<InitOverride>,
+    /// `#[init_fn(priority = N)]` functions, in ascending priority
+    init_calls: Vec<(u32, InitFn)>,
+    /// `#[exit_fn(priority = N)]` functions, in ascending priority
+    exit_calls: Vec<(u32, ExitFn)>,
+    /// Address ranges of the `.text.init` sections, until they are discarded
+    init_text: Vec<Range<u64>>,
+    /// Modules whose exports the module uses
+    used_modules: Vec<String>,
     _helper: core::marker::PhantomData<H>,
 }
 
@@ -101,25 +682,505 @@ impl<H: KernelModuleHelper> ModuleOwner<H> {
         &self.name
     }
 
-    /// Call the module's init function
+    /// Get the `.modinfo` metadata of the module
+    pub fn module_info(&self) -> &ModuleInfo {
+        &self.module_info
+    }
+
+    /// Get the names of the modules whose exports resolved a symbol of this
+    /// module, see [`ModuleLoader::with_session`]
+    pub fn used_modules(&self) -> impl Iterator<Item = &str> {
+        self.used_modules.iter().map(String::as_str)
+    }
+
+    /// Get the lowest address of the module's section allocations
+    pub fn base_address(&self) -> Option<u64> {
+        self.pages
+            .iter()
+            .map(|page| page.addr.as_ptr() as u64)
+            .min()
+    }
+
+    /// Take `size` bytes from the stub area of the section containing
+    /// `location`, returning their address
+    pub(crate) fn alloc_stub(&self, location: u64, size: usize) -> Option<u64> {
+        let page = &self.pages[self.page_index(location)?];
+        let used = page.stubs_used.get();
+        if used + size > page.stub_size {
+            return None;
+        }
+        page.stubs_used.set(used + size);
+        Some(page.addr.as_ptr() as u64 + (page.stub_offset + used) as u64)
+    }
+
+    /// Get a GOT entry holding `value` in the stub area of the section
+    /// containing `location`, reusing the section's entry for `value` if it
+    /// already has one
+    pub(crate) fn emit_got_entry(&self, location: u64, value: u64) -> Option<u64> {
+        let key = (self.page_index(location)?, value);
+        if let Some(&slot) = self.got_entries.borrow().get(&key) {
+            return Some(slot);
+        }
+        let slot = self.alloc_stub(location, core::mem::size_of::<u64>())?;
+        unsafe { (slot as *mut u64).write(value) };
+        self.got_entries.borrow_mut().insert(key, slot);
+        Some(slot)
+    }
+
+    /// Get the index of the section page containing `location`
+    fn page_index(&self, location: u64) -> Option<usize> {
+        self.pages.iter().position(|page| {
+            let start = page.addr.as_ptr() as u64;
+            (start..start + page.sh_size as u64).contains(&location)
+        })
+    }
+
+    /// Get the `[start, end)` of the section allocated at `addr`, the only
+    /// memory its relocations may patch
+    pub(crate) fn section_bounds(&self, addr: u64) -> Option<Range<u64>> {
+        let page = &self.pages[self.page_index(addr)?];
+        let start = page.addr.as_ptr() as u64;
+        Some(start..start + page.sh_size as u64)
+    }
+
+    /// Get the in-memory, relocated contents of the section `name`
+    ///
+    /// # Safety
+    ///
+    /// This reads live module memory. The caller must ensure the section is
+    /// still mapped readable and is not modified while the slice is in use.
+    pub unsafe fn section_bytes(&self, name: &str) -> Option<&[u8]> {
+        let page = self.pages.iter().find(|page| page.name == name)?;
+        Some(unsafe { core::slice::from_raw_parts(page.addr.as_ptr(), page.sh_size) })
+    }
+
+    /// Overwrite the code of the function `name` in place with `new_code`.
+    ///
+    /// The replacement must not be larger than the original function. The
+    /// containing section is made writable for the copy, then its permissions
+    /// are restored and the instruction cache is flushed.
+    ///
+    /// # Safety
+    ///
+    /// No CPU may be executing the function while it is patched, and
+    /// `new_code` must be valid position-independent code for the target.
+    pub unsafe fn patch_function(&mut self, name: &str, new_code: &[u8]) -> Result<()> {
+        let &(addr, capacity) = self.functions.get(name).ok_or_else(|| {
+            log::error!("Function '{}' not found in module {}", name, self.name);
+            ModuleErr::UndefinedSymbol
+        })?;
+        if new_code.len() > capacity {
+            return Err(ModuleErr::PatchTooLarge {
+                size: new_code.len(),
+                capacity,
+            });
+        }
+        let page = self
+            .pages
+            .iter_mut()
+            .find(|page| {
+                let start = page.addr.as_ptr() as usize;
+                (start..start + page.size).contains(&addr)
+            })
+            .ok_or(ModuleErr::InvalidOperation)?;
+
+        if !page.addr.change_perms(page.perms | SectionPerm::WRITE) {
+            log::error!("Failed to make section '{}' writable", page.name);
+            return Err(ModuleErr::InvalidOperation);
+        }
+        unsafe {
+            core::ptr::copy_nonoverlapping(new_code.as_ptr(), addr as *mut u8, new_code.len());
+        }
+        if !page.addr.change_perms(page.perms) {
+            log::error!(
+                "Failed to restore permissions of section '{}' to {}",
+                page.name,
+                page.perms
+            );
+            return Err(ModuleErr::InvalidOperation);
+        }
+        H::flsuh_cache(addr, new_code.len());
+        Ok(())
+    }
+
+    /// Get the parameters the module declares with `#[module_param]`
+    ///
+    /// # Safety
+    ///
+    /// The module's `.modparam` section must be relocated.
+    pub unsafe fn module_params(&self) -> &[ModuleParam] {
+        let Some(params) = (unsafe { self.section_bytes(".modparam") }) else {
+            return &[];
+        };
+        unsafe {
+            core::slice::from_raw_parts(
+                params.as_ptr() as *const ModuleParam,
+                params.len() / core::mem::size_of::<ModuleParam>(),
+            )
+        }
+    }
+
+    /// Get the symbols the module exports with `#[export_symbol]`
+    ///
+    /// # Safety
+    ///
+    /// The module's `.ksymtab` section must be relocated.
+    pub unsafe fn exported_symbols(&self) -> &[KernelSymbol] {
+        let Some(symbols) = (unsafe { self.section_bytes(".ksymtab") }) else {
+            return &[];
+        };
+        unsafe {
+            core::slice::from_raw_parts(
+                symbols.as_ptr() as *const KernelSymbol,
+                symbols.len() / core::mem::size_of::<KernelSymbol>(),
+            )
+        }
+    }
+
+    /// Set the parameter `name` to `value`, given in the native byte order of
+    /// its declared type.
+    ///
+    /// Parameters declared with `#[module_param]` are looked up first, then
+    /// the Linux `__param` ones. Fixed-size types must be given exactly their
+    /// size, `string` parameters at most their maximum length without the NUL
+    /// terminator.
+    ///
+    /// # Safety
+    ///
+    /// The module's `.modparam` and `__param` sections must be relocated, and
+    /// the parameter must not be accessed concurrently.
+    pub unsafe fn set_param(&mut self, name: &str, value: &[u8]) -> Result<()> {
+        let mismatch = |expected| ModuleErr::ParamTypeMismatch {
+            name: name.to_string(),
+            expected,
+            got: value.len(),
+        };
+
+        let module_params = unsafe { self.module_params() };
+        if let Some(param) = module_params.iter().find(|p| p.name() == Some(name)) {
+            let ty = param.ty().ok_or_else(|| {
+                log::error!("Parameter '{}' has an unknown type", name);
+                ModuleErr::UnsupportedFeature
+            })?;
+            if value.len() != ty.size() {
+                return Err(mismatch(ty.size()));
+            }
+            if ty == kmod::ModuleParamType::Bool && value[0] > 1 {
+                log::error!("Parameter '{}' is a bool, got {}", name, value[0]);
+                return Err(ModuleErr::InvalidOperation);
+            }
+            unsafe {
+                core::ptr::copy_nonoverlapping(
+                    value.as_ptr(),
+                    param.value() as *mut u8,
+                    value.len(),
+                );
+            }
+            return Ok(());
+        }
+
+        let ty = self.module_info.param_type(name).ok_or_else(|| {
+            log::error!("Parameter '{}' not found in module {}", name, self.name);
+            ModuleErr::UndefinedSymbol
+        })?;
+
+        let params = unsafe { self.section_bytes("__param") }.ok_or(ModuleErr::UndefinedSymbol)?;
+        let params = unsafe {
+            core::slice::from_raw_parts(
+                params.as_ptr() as *const KernelParam,
+                params.len() / core::mem::size_of::<KernelParam>(),
+            )
+        };
+        let param = params
+            .iter()
+            .find(|param| unsafe { param.name() }.to_bytes() == name.as_bytes())
+            .ok_or(ModuleErr::UndefinedSymbol)?;
+
+        let size = match ty {
+            "byte" | "bool" | "invbool" => 1,
+            "short" | "ushort" => 2,
+            "int" | "uint" | "hexint" => 4,
+            "long" | "ulong" | "ullong" => 8,
+            "string" => {
+                let (maxlen, buf) = unsafe { param.string() };
+                // Leave room for the NUL terminator
+                let capacity = maxlen.saturating_sub(1);
+                if value.len() > capacity {
+                    return Err(mismatch(capacity));
+                }
+                unsafe {
+                    core::ptr::copy_nonoverlapping(value.as_ptr(), buf as *mut u8, value.len());
+                    *buf.add(value.len()) = 0;
+                }
+                return Ok(());
+            }
+            _ => {
+                log::error!("Parameter '{}' has unsupported type '{}'", name, ty);
+                return Err(ModuleErr::UnsupportedFeature);
+            }
+        };
+        if value.len() != size {
+            return Err(mismatch(size));
+        }
+        unsafe {
+            core::ptr::copy_nonoverlapping(value.as_ptr(), param.arg() as *mut u8, size);
+        }
+        Ok(())
+    }
+
+    /// Get the kernel taints caused by the module
+    pub fn taints(&self) -> Taint {
+        self.taints
+    }
+
+    /// Get the host data attached to the module
+    pub fn userdata(&self) -> Option<&H::UserData> {
+        self.userdata.as_ref()
+    }
+
+    /// Get the host data attached to the module, mutably
+    pub fn userdata_mut(&mut self) -> Option<&mut H::UserData> {
+        self.userdata.as_mut()
+    }
+
+    /// Attach `userdata` to the module, returning the previous one
+    pub fn set_userdata(&mut self, userdata: H::UserData) -> Option<H::UserData> {
+        self.userdata.replace(userdata)
+    }
+
+    /// Get the value returned by the module's init function, or `None` if
+    /// [`ModuleOwner::call_init`] has not been called yet
+    pub fn init_result(&self) -> Option<i32> {
+        self.init_result
+    }
+
+    /// Give every section its final permissions: code becomes read-only and
+    /// executable, data non-executable, and relro data read-only.
+    ///
+    /// The permissions come from the `SHF_EXECINSTR` and `SHF_WRITE` flags of
+    /// each section, and are applied with [`SectionMemOps::change_perms`] and
+    /// [`KernelModuleHelper::set_memory_attributes`]. The loader calls this
+    /// once relocations are applied, before the init function can run.
+    pub fn finalize_permissions(&mut self) -> Result<()> {
+        for page in &mut self.pages {
+            let addr = page.addr.as_ptr() as usize;
+            if !page.addr.change_perms(page.perms)
+                || !H::set_memory_attributes(
+                    addr,
+                    page.size,
+                    page.perms.contains(SectionPerm::EXECUTE),
+                    page.perms.contains(SectionPerm::WRITE),
+                )
+            {
+                log::error!(
+                    "Failed to change permissions of section '{}' to {}",
+                    page.name,
+                    page.perms
+                );
+                return Err(ModuleErr::InvalidOperation);
+            }
+            H::flsuh_cache(addr, page.size);
+        }
+        Ok(())
+    }
+
+    /// Call the module's init function, or the [`LoadOptions::init_override`]
+    /// given when loading it, then its `#[init_fn(priority = N)]` functions
+    /// in ascending priority.
+    ///
+    /// The first nonzero return stops the init functions and is returned.
+    /// The exit functions of the init functions that succeeded are then
+    /// called in reverse, undoing the phases that succeeded.
     pub fn call_init(&mut self) -> Result<i32> {
-        if let Some(init_fn) = self.module.take_init_fn() {
-            let result = unsafe { init_fn() };
-            Ok(result)
-        } else {
+        if self.init_result.is_some() {
             log::warn!("The init function can only be called once.");
-            Err(ModuleErr::InvalidOperation)
+            return Err(ModuleErr::InvalidOperation);
+        }
+        let init_fn = self.module.take_init_fn();
+        let init_calls = core::mem::take(&mut self.init_calls);
+        let mut result = match (self.init_override, init_fn) {
+            (Some(init_override), init_fn) => init_override(init_fn),
+            (None, Some(init_fn)) => unsafe { init_fn() },
+            // Only prioritized init functions
+            (None, None) if !init_calls.is_empty() => 0,
+            (None, None) => {
+                log::warn!("The module has no init function.");
+                return Err(ModuleErr::InvalidOperation);
+            }
+        };
+        if result == 0 {
+            let mut succeeded = Vec::new();
+            for &(priority, init_fn) in &init_calls {
+                result = unsafe { init_fn() };
+                if result != 0 {
+                    module_log!(
+                        error,
+                        self.name.as_str(),
+                        "init function of priority {} failed with {}",
+                        priority,
+                        result
+                    );
+                    self.unwind_init(&succeeded);
+                    break;
+                }
+                succeeded.push(priority);
+            }
         }
+        self.init_result = Some(result);
+        self.live = result == 0;
+        Ok(result)
     }
 
-    /// Call the module's exit function
-    pub fn call_exit(&mut self) {
+    /// Call the exit functions of the init functions of `succeeded` in
+    /// reverse. Init and exit functions of the same priority pair up in link
+    /// order, so a priority only gets as many exit calls as inits succeeded.
+    fn unwind_init(&mut self, succeeded: &[u32]) {
+        let exit_calls = core::mem::take(&mut self.exit_calls);
+        let mut remaining: BTreeMap<u32, usize> = BTreeMap::new();
+        for &priority in succeeded {
+            *remaining.entry(priority).or_default() += 1;
+        }
+        let undo: Vec<ExitFn> = exit_calls
+            .iter()
+            .filter_map(|&(priority, exit_fn)| {
+                let count = remaining.get_mut(&priority).filter(|count| **count > 0)?;
+                *count -= 1;
+                Some(exit_fn)
+            })
+            .collect();
+        for exit_fn in undo.into_iter().rev() {
+            unsafe { exit_fn() };
+        }
+        // The plain init function runs as priority 0
         if let Some(exit_fn) = self.module.take_exit_fn() {
+            unsafe { exit_fn() };
+        }
+    }
+
+    /// Free the `.text.init` and `.text.init.N` sections holding the
+    /// `#[init_fn]` code once the init functions have run, like Linux frees
+    /// the `__init` sections after loading. The `.text.exit` code stays until
+    /// the module is dropped.
+    ///
+    /// The section allocations are dropped, returning their memory to the
+    /// host. Returns the number of bytes freed, 0 if there is no init section.
+    pub fn discard_init(&mut self) -> Result<usize> {
+        if self.init_result.is_none() {
+            log::warn!("The init section can only be discarded after init has run.");
+            return Err(ModuleErr::InvalidOperation);
+        }
+        let init_text = core::mem::take(&mut self.init_text);
+        if init_text.is_empty() {
+            return Ok(0);
+        }
+        let in_init_text = |addr: u64| init_text.iter().any(|range| range.contains(&addr));
+        let mut freed = 0;
+        self.pages.retain(|page| {
+            let discard = init_text
+                .iter()
+                .any(|range| page.addr.as_ptr() as u64 == range.start);
+            if discard {
+                freed += page.size;
+            }
+            !discard
+        });
+        self.functions
+            .retain(|_, &mut (addr, _)| !in_init_text(addr as u64));
+        // GOT entries are keyed by page index, they are only shared while
+        // relocating anyway
+        self.got_entries.get_mut().clear();
+        module_log!(
+            debug,
+            self.name.as_str(),
+            "discarded {} bytes of init code",
+            freed
+        );
+        Ok(freed)
+    }
+
+    /// Call the module's `#[exit_fn(priority = N)]` functions in descending
+    /// priority, then its exit function
+    pub fn call_exit(&mut self) {
+        let exit_calls = core::mem::take(&mut self.exit_calls);
+        for &(_, exit_fn) in exit_calls.iter().rev() {
             unsafe {
                 exit_fn();
             }
-        } else {
-            log::warn!("The exit function can only be called once.");
+        }
+        match self.module.take_exit_fn() {
+            Some(exit_fn) => {
+                unsafe {
+                    exit_fn();
+                }
+                self.live = false;
+            }
+            None if !exit_calls.is_empty() => self.live = false,
+            // Nothing to clean up
+            None if self.live => {
+                log::debug!("Module {} has no exit function", self.name);
+                self.live = false;
+            }
+            None => log::warn!("The exit function can only be called once."),
+        }
+    }
+
+    /// Start the module like `insmod`: call its init function, then register
+    /// its exports and record it in `session`.
+    ///
+    /// Like Linux, a nonzero init return fails the load: the module is not
+    /// registered, its memory is freed and [`ModuleErr::InitFailed`] carries
+    /// the code. If its exports can not be registered, the exit function is
+    /// called before the module is freed.
+    pub fn init_module(mut self, session: &mut LoadSession, options: &LoadOptions) -> Result<Self> {
+        let code = self.call_init()?;
+        if code != 0 {
+            log::error!("Module {} init failed with {}", self.name, code);
+            // Dropping the owner frees the section allocations
+            return Err(ModuleErr::InitFailed(code));
+        }
+        // The module is loaded, so its `.ksymtab` is relocated
+        if let Err(err) = unsafe { session.register_module_exports(&self, options) } {
+            self.call_exit();
+            return Err(err);
+        }
+        session.record(&self);
+        Ok(self)
+    }
+
+    /// Unload the module like `rmmod`: call its exit function, forget it and
+    /// its exports in `session`, then free its memory.
+    ///
+    /// While modules recorded in `session` depend on it, see
+    /// [`LoadSession::refcount`], nothing is done and the module is handed
+    /// back with [`ModuleErr::ModuleInUse`].
+    pub fn unload(
+        mut self,
+        session: &mut LoadSession,
+    ) -> core::result::Result<(), (Box<Self>, ModuleErr)> {
+        let users: Vec<String> = session.dependents(&self.name).map(String::from).collect();
+        if !users.is_empty() {
+            log::error!("Module {} is in use by {}", self.name, users.join(", "));
+            let name = self.name.clone();
+            return Err((Box::new(self), ModuleErr::ModuleInUse { name, users }));
+        }
+        if self.live {
+            self.call_exit();
+        }
+        session.remove(&self.name);
+        // Dropping the owner frees the section allocations
+        Ok(())
+    }
+}
+
+impl<H: KernelModuleHelper> Drop for ModuleOwner<H> {
+    fn drop(&mut self) {
+        // The section memory is freed with the pages, anything the module
+        // registered during init would be left pointing at it
+        if self.live {
+            log::warn!(
+                "Module {} dropped without calling its exit function",
+                self.name
+            );
         }
     }
 }
@@ -132,40 +1193,757 @@ const fn align_up(addr: usize, align: usize) -> usize {
 //     addr & !(align - 1)
 // }
 
-pub struct ModuleLoadInfo {
+/// Whether the section is read-only after relocation, e.g. a const array of
+/// pointers
+fn is_relro_section(name: &str) -> bool {
+    name == ".data.rel.ro" || name.starts_with(".data.rel.ro.")
+}
+
+pub struct ModuleLoadInfo<'a> {
     pub(crate) syms: Vec<(goblin::elf::sym::Sym, String)>,
+    /// `e_machine` of the module
+    pub(crate) machine: u16,
+    /// Entries of the relocation sections indexing `syms`, by section index
+    pub(crate) relocations: BTreeMap<usize, &'a [goblin::elf64::reloc::Rela]>,
+    /// Thread pointer offset of the module's TLS block, `STT_TLS` symbols
+    /// hold offsets into this block
+    pub(crate) tls_base: Option<u64>,
+    /// See [`LoadOptions::strict_relocations`]
+    pub(crate) strict: bool,
+    /// See [`ModuleLoader::relocation_groups`]
+    pub(crate) relocation_groups: Vec<Vec<usize>>,
+    /// Address ranges of the `.text.init` and `.text.init.N` sections
+    pub(crate) init_text: Vec<Range<u64>>,
+    /// `(symbol, crc)` pairs of the `__versions` section
+    pub(crate) versions: Vec<(String, u32)>,
+    /// Link-time `sh_addr` of each section, by section index
+    pub(crate) link_addrs: Vec<u64>,
+}
+
+impl ModuleLoadInfo<'_> {
+    /// Get the relocation sections partitioned into groups that can be
+    /// applied independently of each other
+    pub fn relocation_groups(&self) -> &[Vec<usize>] {
+        &self.relocation_groups
+    }
+
+    /// Get the `(symbol, crc)` pairs the module was built against, from its
+    /// `__versions` section
+    pub fn versions(&self) -> &[(String, u32)] {
+        &self.versions
+    }
+
+    /// Get the address ranges of the `.text.init` and `.text.init.N`
+    /// sections holding the `#[init_fn]` code, freed by
+    /// [`ModuleOwner::discard_init`]
+    pub fn init_text(&self) -> &[Range<u64>] {
+        &self.init_text
+    }
+
+    /// Get the symbol `index` of the table the relocation entries index,
+    /// failing instead of panicking on an index from a corrupted `r_info`
+    pub(crate) fn symbol(&self, index: usize) -> Result<&(goblin::elf::sym::Sym, String)> {
+        self.syms.get(index).ok_or_else(|| {
+            log::error!(
+                "Symbol index {} out of range, the symbol table has {} entries",
+                index,
+                self.syms.len()
+            );
+            ModuleErr::SymbolIndexOutOfRange {
+                index,
+                table_len: self.syms.len(),
+            }
+        })
+    }
+
+    /// Decode the entry `index` of the relocation section `section`, without
+    /// applying it
+    pub fn relocation(&self, section: usize, index: usize) -> Option<RelocationEntry> {
+        let rela = self.relocations.get(&section)?.get(index)?;
+        let sym_idx = (rela.r_info >> 32) as usize;
+        let sym_name = self.syms.get(sym_idx).map(|(_, name)| name.clone())?;
+        Some(RelocationEntry::decode(self.machine, rela, sym_name))
+    }
+}
+
+/// Host helper of the modules built by [`ModuleLoadInfoBuilder`], which
+/// neither allocates nor resolves anything. Its allocations are null, so
+/// loading a module with it fails with [`ModuleErr::MemoryAllocationFailed`].
+#[cfg(any(test, feature = "test-utils"))]
+pub struct NoHelper;
+
+#[cfg(any(test, feature = "test-utils"))]
+impl KernelModuleHelper for NoHelper {
+    type UserData = ();
+
+    fn vmalloc(_size: usize) -> Box<dyn SectionMemOps> {
+        // ModuleLoadInfoBuilder sections are given by the caller
+        Box::new(BorrowedSection(core::ptr::null_mut()))
+    }
+
+    fn resolve_symbol(_name: &str) -> Option<usize> {
+        None
+    }
+}
+
+/// Section memory borrowed from the caller, of
+/// [`ModuleLoader::load_with_symbols_into`] or `ModuleLoadInfoBuilder`. Its
+/// permissions are the caller's business.
+struct BorrowedSection(*mut u8);
+
+impl SectionMemOps for BorrowedSection {
+    fn as_ptr(&self) -> *const u8 {
+        self.0
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.0
+    }
+
+    fn change_perms(&mut self, _perms: SectionPerm) -> bool {
+        true
+    }
+}
+
+/// Build the symbol table and sections of a module by hand, to apply
+/// relocation entries without a module ELF, e.g. to check the patched bytes
+/// of a single RELA in a test.
+///
+/// Sections and symbols are numbered in the order they are added, from 1,
+/// index 0 being the null section and symbol like in an ELF.
+///
+/// ```ignore
+/// let mut text = [0u8; 64];
+/// let mut builder = ModuleLoadInfoBuilder::new(goblin::elf::header::EM_RISCV)
+///     .with_section(".text", &mut text, 32)
+///     .with_symbol("foo", foo_sym);
+/// builder.apply(1, &[rela])?;
+/// drop(builder);
+/// assert_eq!(&text[..4], &expected);
+/// ```
+#[cfg(any(test, feature = "test-utils"))]
+pub struct ModuleLoadInfoBuilder<'m> {
+    machine: u16,
+    syms: Vec<(goblin::elf::sym::Sym, String)>,
+    sechdrs: Vec<SectionHeader>,
+    link_addrs: Vec<u64>,
+    relocations: BTreeMap<usize, &'m [goblin::elf64::reloc::Rela]>,
+    tls_base: Option<u64>,
+    strict: bool,
+    owner: ModuleOwner<NoHelper>,
+    _memory: core::marker::PhantomData<&'m mut [u8]>,
+}
+
+#[cfg(any(test, feature = "test-utils"))]
+impl<'m> ModuleLoadInfoBuilder<'m> {
+    /// Start an empty module for the architecture `machine`, an ELF
+    /// `e_machine`
+    pub fn new(machine: u16) -> Self {
+        ModuleLoadInfoBuilder {
+            machine,
+            syms: alloc::vec![(goblin::elf::sym::Sym::default(), String::new())],
+            sechdrs: alloc::vec![SectionHeader::default()],
+            link_addrs: alloc::vec![0],
+            relocations: BTreeMap::new(),
+            tls_base: None,
+            strict: false,
+            owner: ModuleOwner {
+                name: "test".to_string(),
+                module_info: ModuleInfo::default(),
+                pages: Vec::new(),
+                functions: BTreeMap::new(),
+                got_entries: core::cell::RefCell::new(BTreeMap::new()),
+                init_override: None,
+                init_calls: Vec::new(),
+                exit_calls: Vec::new(),
+                init_text: Vec::new(),
+                used_modules: Vec::new(),
+                module: Module::default(),
+                init_result: None,
+                userdata: None,
+                taints: Taint::empty(),
+                live: false,
+                _helper: core::marker::PhantomData,
+            },
+            _memory: core::marker::PhantomData,
+        }
+    }
+
+    /// Add the section `name` backed by `memory`, which becomes its
+    /// `sh_addr`. The first `sh_size` bytes are the section, the rest is its
+    /// area for relocation stubs and GOT entries.
+    pub fn with_section(mut self, name: &str, memory: &'m mut [u8], sh_size: usize) -> Self {
+        let sh_size = sh_size.min(memory.len());
+        let stub_offset = sh_size.next_multiple_of(8).min(memory.len());
+        self.sechdrs.push(SectionHeader {
+            sh_type: goblin::elf::section_header::SHT_PROGBITS,
+            sh_flags: goblin::elf::section_header::SHF_ALLOC as u64,
+            sh_addr: memory.as_mut_ptr() as u64,
+            sh_size: sh_size as u64,
+            ..Default::default()
+        });
+        self.link_addrs.push(memory.as_mut_ptr() as u64);
+        self.owner.pages.push(SectionPages {
+            name: name.to_string(),
+            addr: Box::new(BorrowedSection(memory.as_mut_ptr())),
+            size: memory.len(),
+            sh_size,
+            perms: SectionPerm::READ | SectionPerm::WRITE,
+            pool: MemoryPool::default(),
+            stub_offset,
+            stub_size: memory.len() - stub_offset,
+            stubs_used: core::cell::Cell::new(0),
+        });
+        self
+    }
+
+    /// Add the symbol `name`. Its `st_value` is the final address, as after
+    /// the loader resolved it.
+    pub fn with_symbol(mut self, name: &str, sym: goblin::elf::sym::Sym) -> Self {
+        self.syms.push((sym, name.to_string()));
+        self
+    }
+
+    /// Link the section `section` at `addr` instead of at its memory, as in
+    /// an image linked at a fixed address
+    pub fn with_link_addr(mut self, section: usize, addr: u64) -> Self {
+        self.link_addrs[section] = addr;
+        self
+    }
+
+    /// Add the relocation section `section` with the entries `relas`, for
+    /// [`ModuleLoadInfo::relocation`]. They are not applied.
+    pub fn with_relocations(
+        mut self,
+        section: usize,
+        relas: &'m [goblin::elf64::reloc::Rela],
+    ) -> Self {
+        self.relocations.insert(section, relas);
+        self
+    }
+
+    /// Set the thread pointer offset of the module's TLS block
+    pub fn with_tls_base(mut self, tls_base: u64) -> Self {
+        self.tls_base = Some(tls_base);
+        self
+    }
+
+    /// See [`LoadOptions::strict_relocations`]
+    pub fn with_strict_relocations(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Get the [`ModuleLoadInfo`] the relocation handlers see
+    pub fn build(&self) -> ModuleLoadInfo<'m> {
+        ModuleLoadInfo {
+            syms: self.syms.clone(),
+            machine: self.machine,
+            relocations: self.relocations.clone(),
+            tls_base: self.tls_base,
+            strict: self.strict,
+            relocation_groups: Vec::new(),
+            init_text: Vec::new(),
+            versions: Vec::new(),
+            link_addrs: self.link_addrs.clone(),
+        }
+    }
+
+    /// Apply `relas` to the section `section`, like the entries of one
+    /// relocation section. Stubs and GOT entries persist across calls.
+    pub fn apply(&self, section: usize, relas: &[goblin::elf64::reloc::Rela]) -> Result<()> {
+        crate::arch::section_header(&self.sechdrs, section)?;
+        let rel_section = SectionHeader {
+            sh_type: goblin::elf::section_header::SHT_RELA,
+            sh_info: section as u32,
+            sh_entsize: core::mem::size_of::<goblin::elf64::reloc::Rela>() as u64,
+            sh_size: core::mem::size_of_val(relas) as u64,
+            ..Default::default()
+        };
+        let mut progress = RelocProgress::new(0, self.machine, self.owner.name(), None);
+        crate::arch::apply_relocate_add(
+            self.machine,
+            relas,
+            &rel_section,
+            &self.sechdrs,
+            &self.build(),
+            &self.owner,
+            &mut progress,
+        )
+    }
 }
 
 impl<'a, H: KernelModuleHelper> ModuleLoader<'a, H> {
     /// create a new ELF loader
     pub fn new(elf_data: &'a [u8]) -> Result<Self> {
-        let elf = Elf::parse(elf_data).map_err(|_| ModuleErr::InvalidElf)?;
-        if !elf.is_64 {
-            return Err(ModuleErr::UnsupportedArch);
-        }
+        Ok(Self::from_parsed(ParsedModule::parse(elf_data)?))
+    }
+
+    /// create a new ELF loader from an already parsed module
+    pub fn from_parsed(parsed: ParsedModule<'a>) -> Self {
+        let ParsedModule {
+            elf,
+            elf_data,
+            rela_sections,
+        } = parsed;
         let module_name = elf.shdr_strtab.get_at(elf.header.e_shstrndx as usize);
-        Ok(ModuleLoader {
+        let link_addrs = elf
+            .section_headers
+            .iter()
+            .map(|shdr| shdr.sh_addr)
+            .collect();
+        ModuleLoader {
             elf,
             elf_data,
+            rela_sections,
+            link_addrs,
             module_name,
+            placer: default_section_placer,
+            options: LoadOptions::default(),
+            userdata: None,
+            warnings: core::cell::RefCell::new(Vec::new()),
+            imported_namespaces: Vec::new(),
+            tls_offsets: Vec::new(),
+            tls_base: None,
+            symbol_map: None,
+            region: None,
+            symbol_hashes: None,
+            session: None,
+            used_modules: core::cell::RefCell::new(BTreeSet::new()),
+            versions: Vec::new(),
             __helper: core::marker::PhantomData,
-        })
+        }
+    }
+
+    /// Use `placer` to choose the memory pool of each allocated section
+    pub fn with_section_placer(mut self, placer: SectionPlacer) -> Self {
+        self.placer = placer;
+        self
+    }
+
+    /// Set the options used to load the module
+    pub fn with_options(mut self, options: LoadOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Attach `userdata` to the loaded module
+    pub fn with_userdata(mut self, userdata: H::UserData) -> Self {
+        self.userdata = Some(userdata);
+        self
+    }
+
+    /// Resolve undefined symbols through `table` first, a list of
+    /// `(symbol_hash(name), name, address)` entries sorted by hash, so bulk
+    /// loading at boot avoids name lookups. The name of an entry is only
+    /// compared once its hash matches, to tell apart names whose hashes
+    /// collide. Symbols missing from the table fall back to
+    /// [`KernelModuleHelper::resolve_symbol`].
+    pub fn with_symbol_hashes(mut self, table: &'a [(u64, &'a str, u64)]) -> Self {
+        debug_assert!(table.is_sorted_by_key(|&(hash, _, _)| hash));
+        self.symbol_hashes = Some(table);
+        self
+    }
+
+    /// Resolve undefined symbols the kernel does not define from the symbols
+    /// exported by the modules registered in `session`, see
+    /// [`LoadSession::register_module_exports`]
+    pub fn with_session(mut self, session: &'a LoadSession) -> Self {
+        self.session = Some(session);
+        self
     }
 
     /// Load the module into kernel space
-    pub fn load_module(mut self) -> Result<ModuleOwner<H>> {
+    pub fn load_module(self) -> Result<ModuleOwner<H>> {
+        self.load_with_warnings().map(|(owner, _)| owner)
+    }
+
+    /// Load the module, resolving its undefined symbols from `symbols`
+    /// instead of calling [`KernelModuleHelper::resolve_symbol`] and
+    /// [`KernelModuleHelper::symbol_namespace`].
+    ///
+    /// Every non-weak undefined symbol must be in the map, this is checked
+    /// before anything is allocated. Section memory is still allocated up
+    /// front with [`KernelModuleHelper::alloc`], so relocation itself does not
+    /// call back into the host; an `alloc` handing out pieces of a
+    /// pre-allocated region makes the whole load callback-free.
+    pub fn load_with_symbols(mut self, symbols: &'a [(&'a str, u64)]) -> Result<ModuleOwner<H>> {
+        self.symbol_map = Some(symbols);
+        self.load_module()
+    }
+
+    /// Like [`ModuleLoader::load_with_symbols`], but the sections are placed
+    /// in `memory` instead of being allocated with
+    /// [`KernelModuleHelper::alloc`], so nothing is allocated through the
+    /// helper at all.
+    ///
+    /// `memory` must be large enough for every section at its alignment, it
+    /// fails with [`ModuleErr::MemoryAllocationFailed`] otherwise. Its
+    /// permissions are not changed, it has to be mapped writable and
+    /// executable. The module keeps it borrowed for good, e.g. a static pool
+    /// reserved for modules loaded in interrupt context.
+    pub fn load_with_symbols_into(
+        mut self,
+        symbols: &'a [(&'a str, u64)],
+        memory: &'static mut [u8],
+    ) -> Result<ModuleOwner<H>> {
+        self.region = Some(memory);
+        self.load_with_symbols(symbols)
+    }
+
+    /// Take `size` bytes aligned to `align` from the start of the `region`
+    /// given to [`ModuleLoader::load_with_symbols_into`]
+    fn carve_region(
+        region: &mut Option<&'static mut [u8]>,
+        size: usize,
+        align: usize,
+    ) -> Option<Box<dyn SectionMemOps>> {
+        let memory = region.take()?;
+        let pad = memory.as_ptr().align_offset(align);
+        if pad.checked_add(size).is_none_or(|end| end > memory.len()) {
+            log::error!(
+                "Region of {:#x} bytes is too small for {:#x} more bytes",
+                memory.len(),
+                size
+            );
+            *region = Some(memory);
+            return None;
+        }
+        let (section, rest) = memory[pad..].split_at_mut(size);
+        *region = Some(rest);
+        Some(Box::new(BorrowedSection(section.as_mut_ptr())))
+    }
+
+    /// Get the address ranges of the allocated `.text.init` and
+    /// `.text.init.N` sections
+    fn init_text(&self) -> Vec<Range<u64>> {
+        self.elf
+            .section_headers
+            .iter()
+            .filter(|shdr| {
+                shdr.sh_flags & goblin::elf::section_header::SHF_ALLOC as u64 != 0
+                    && self
+                        .elf
+                        .shdr_strtab
+                        .get_at(shdr.sh_name)
+                        .is_some_and(|name| name == ".text.init" || name.starts_with(".text.init."))
+            })
+            .map(|shdr| shdr.sh_addr..shdr.sh_addr + shdr.sh_size)
+            .collect()
+    }
+
+    /// Load the module into kernel space, also returning the non-fatal
+    /// conditions found on the way
+    pub fn load_with_warnings(mut self) -> Result<(ModuleOwner<H>, Vec<LoadWarning>)> {
+        self.check_relocation_count()?;
+        self.check_symbol_map()?;
         let mut owner = self.pre_read_modinfo()?;
+        owner.userdata = self.userdata.take();
+        self.check_license(&mut owner);
+        self.check_vermagic(&owner)?;
+        self.imported_namespaces = owner
+            .module_info
+            .imported_namespaces()
+            .map(String::from)
+            .collect();
         log::error!("Module({}) info: {:?}", owner.name(), owner.module_info);
+        self.versions = self.read_versions()?;
         self.layout_and_allocate(&mut owner)?;
-        let load_info = self.simplify_symbols()?;
-        self.apply_relocations(load_info, &owner)?;
+        self.layout_tls();
+        let mut load_info = self.simplify_symbols(&self.elf.syms, &self.elf.strtab)?;
+        load_info.relocation_groups = self.relocation_groups();
+        load_info.init_text = self.init_text();
+        owner.init_text = load_info.init_text.clone();
+        Self::record_functions(&load_info, &mut owner);
+        let mut linked_infos = self.simplify_linked_symtabs()?;
+        for &rela_idx in &self.rela_sections {
+            let shdr = crate::arch::section_header(&self.elf.section_headers, rela_idx)?;
+            linked_infos
+                .get_mut(&(shdr.sh_link as usize))
+                .unwrap_or(&mut load_info)
+                .relocations
+                .insert(rela_idx, self.rela_list(shdr));
+        }
+        self.apply_relocations(load_info, linked_infos, &owner)?;
+        self.apply_alternatives(&owner)?;
 
         self.post_read_modinfo(&mut owner)?;
 
-        self.set_section_perms(&mut owner)?;
+        // Must run after all relocations, relro sections become read-only here
+        owner.finalize_permissions()?;
 
+        owner.used_modules = self.used_modules.take().into_iter().collect();
+        if self.warnings.borrow().iter().any(|warning| {
+            matches!(
+                warning,
+                LoadWarning::ForcedVersion(_) | LoadWarning::ForcedVermagic(_)
+            )
+        }) {
+            owner.taints |= Taint::FORCED_MODULE;
+        }
         log::error!("Module({}) loaded successfully!", owner.name(),);
-        Ok(owner)
+        Ok((owner, self.warnings.take()))
+    }
+
+    /// Check the module imports the `namespace` `sym_name` is exported in
+    ///
+    /// See <https://elixir.bootlin.com/linux/v6.6/source/kernel/module/main.c#L1085>
+    fn check_namespace(&self, sym_name: &str, namespace: Option<String>) -> Result<()> {
+        let Some(namespace) = namespace else {
+            return Ok(());
+        };
+        if self.imported_namespaces.contains(&namespace) {
+            return Ok(());
+        }
+        log::error!(
+            "{}: module uses symbol ({}) from namespace {}, but does not import it.",
+            self.module_name.unwrap_or("<unknown>"),
+            sym_name,
+            namespace
+        );
+        Err(ModuleErr::NamespaceViolation {
+            symbol: sym_name.to_string(),
+            namespace,
+        })
+    }
+
+    /// Parse the `(symbol, crc)` pairs of the `__versions` section, if any.
+    ///
+    /// See <https://elixir.bootlin.com/linux/v6.6/source/include/linux/module.h#L37>
+    fn read_versions(&self) -> Result<Vec<(String, u32)>> {
+        // struct modversion_info { unsigned long crc; char name[56]; }
+        const ENTRY_SIZE: usize = 64;
+        let Some(shdr) = self
+            .elf
+            .section_headers
+            .iter()
+            .find(|shdr| self.elf.shdr_strtab.get_at(shdr.sh_name) == Some("__versions"))
+        else {
+            return Ok(Vec::new());
+        };
+        let data = self
+            .elf_data
+            .get(shdr.sh_offset as usize..)
+            .and_then(|data| data.get(..shdr.sh_size as usize))
+            .filter(|data| data.len().is_multiple_of(ENTRY_SIZE))
+            .ok_or_else(|| {
+                log::error!("Malformed __versions section");
+                ModuleErr::InvalidElf
+            })?;
+        data.chunks_exact(ENTRY_SIZE)
+            .map(|entry| {
+                let crc = u64::from_le_bytes(entry[..8].try_into().unwrap()) as u32;
+                let name = &entry[8..];
+                let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+                let name = core::str::from_utf8(&name[..len]).map_err(|_| ModuleErr::InvalidElf)?;
+                Ok((name.to_string(), crc))
+            })
+            .collect()
+    }
+
+    /// Compare the CRC the module recorded for `sym_name` with the kernel's.
+    ///
+    /// Symbols without a CRC on either side are not checked.
+    ///
+    /// See <https://elixir.bootlin.com/linux/v6.6/source/kernel/module/version.c#L13>
+    fn check_version(&self, sym_name: &str) -> Result<()> {
+        let Some(found) = H::symbol_crc(sym_name) else {
+            return Ok(());
+        };
+        let Some(&(_, expected)) = self.versions.iter().find(|(name, _)| name == sym_name) else {
+            return Ok(());
+        };
+        if expected == found {
+            return Ok(());
+        }
+        let module = self.module_name.unwrap_or("<unknown>");
+        if self.options.force_modversions {
+            module_log!(
+                warn,
+                module,
+                sym = sym_name,
+                "forcing load despite CRC {:#010x}, kernel has {:#010x}, tainting kernel",
+                expected,
+                found
+            );
+            self.warn(LoadWarning::ForcedVersion(sym_name.to_string()));
+            return Ok(());
+        }
+        module_log!(
+            error,
+            module,
+            sym = sym_name,
+            "disagrees about version of symbol: CRC {:#010x}, kernel has {:#010x}",
+            expected,
+            found
+        );
+        Err(ModuleErr::VersionMismatch {
+            symbol: sym_name.to_string(),
+            expected,
+            found,
+        })
+    }
+
+    /// Make sure the symbol map given to [`ModuleLoader::load_with_symbols`]
+    /// covers every undefined symbol that is not weak
+    fn check_symbol_map(&self) -> Result<()> {
+        let Some(map) = self.symbol_map else {
+            return Ok(());
+        };
+        for sym_name in required_symbols(&self.elf) {
+            if !map.iter().any(|(name, _)| *name == sym_name) {
+                log::error!("Symbol '{}' is missing from the symbol map", sym_name);
+                return Err(ModuleErr::UndefinedSymbol);
+            }
+        }
+        Ok(())
+    }
+
+    /// Look `sym_name` up in the table given to
+    /// [`ModuleLoader::with_symbol_hashes`]
+    fn lookup_symbol_hash(&self, sym_name: &str) -> Option<usize> {
+        let table = self.symbol_hashes?;
+        let hash = symbol_hash(sym_name);
+        let start = table.partition_point(|&(entry_hash, _, _)| entry_hash < hash);
+        table[start..]
+            .iter()
+            .take_while(|&&(entry_hash, _, _)| entry_hash == hash)
+            .find(|&&(_, name, _)| name == sym_name)
+            .map(|&(_, _, addr)| addr as usize)
+    }
+
+    /// Resolve the undefined symbols of `syms` the kernel has to look up with
+    /// one [`KernelModuleHelper::resolve_symbols`] call
+    fn resolve_kernel_symbols(
+        &self,
+        syms: &goblin::elf::Symtab,
+        strtab: &goblin::strtab::Strtab,
+    ) -> BTreeMap<String, Option<usize>> {
+        if self.symbol_map.is_some() {
+            return BTreeMap::new();
+        }
+        let names: BTreeSet<String> = syms
+            .iter()
+            .skip(1)
+            .filter(|sym| sym.st_shndx == goblin::elf::section_header::SHN_UNDEF as usize)
+            .map(|sym| {
+                let raw_name = strtab.get_at(sym.st_name).unwrap_or("<unknown>");
+                format!("{:#}", rustc_demangle::demangle(raw_name))
+            })
+            .filter(|name| self.lookup_symbol_hash(name).is_none())
+            .collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let mut addrs = alloc::vec![None; names.len()];
+        H::resolve_symbols(&names, &mut addrs);
+        names.into_iter().map(String::from).zip(addrs).collect()
+    }
+
+    /// Resolve an undefined symbol from the symbol map if one was given,
+    /// otherwise through the hash table, the kernel addresses resolved in
+    /// advance and then the exports of the session
+    fn resolve_symbol(
+        &self,
+        sym_name: &str,
+        kernel_symbols: &BTreeMap<String, Option<usize>>,
+    ) -> Result<Option<usize>> {
+        if let Some(map) = self.symbol_map {
+            let addr = map
+                .iter()
+                .find(|(name, _)| *name == sym_name)
+                .map(|&(_, addr)| addr as usize);
+            self.trace_resolution(sym_name, "symbol map", addr);
+            return Ok(addr);
+        }
+        let hashed = self.lookup_symbol_hash(sym_name);
+        let (addr, namespace) = match hashed {
+            Some(addr) => {
+                self.trace_resolution(sym_name, "symbol hash table", Some(addr));
+                // Show what the hash table shadows, the kernel may define the
+                // symbol too
+                #[cfg(feature = "symbol-trace")]
+                if let Some(shadowed) = H::resolve_symbol(sym_name) {
+                    self.trace_resolution(sym_name, "kernel (shadowed)", Some(shadowed));
+                }
+                (Some(addr), H::symbol_namespace(sym_name))
+            }
+            None => {
+                let addr = kernel_symbols.get(sym_name).copied().flatten();
+                self.trace_resolution(sym_name, "kernel", addr);
+                match addr {
+                    Some(addr) => (Some(addr), H::symbol_namespace(sym_name)),
+                    None => match self.resolve_export(sym_name) {
+                        Some((addr, namespace)) => (Some(addr), namespace.map(String::from)),
+                        None => (None, None),
+                    },
+                }
+            }
+        };
+        if addr.is_some() {
+            self.check_namespace(sym_name, namespace)?;
+            self.check_version(sym_name)?;
+        }
+        Ok(addr)
+    }
+
+    /// Resolve an undefined symbol from the exports of other modules, with
+    /// the namespace it is exported in
+    fn resolve_export(&self, sym_name: &str) -> Option<(usize, Option<&'a str>)> {
+        let session = self.session?;
+        let (addr, owner) = session.resolve_export(sym_name)?;
+        self.trace_resolution(sym_name, owner, Some(addr as usize));
+        self.used_modules.borrow_mut().insert(owner.to_string());
+        Some((addr as usize, session.export_namespace(sym_name)))
+    }
+
+    /// Log which provider resolved the undefined symbol `sym_name`, with the
+    /// `symbol-trace` feature
+    #[allow(unused_variables)]
+    fn trace_resolution(&self, sym_name: &str, provider: &str, addr: Option<usize>) {
+        #[cfg(feature = "symbol-trace")]
+        match addr {
+            Some(addr) => module_log!(
+                trace,
+                self.module_name.unwrap_or("<unknown>"),
+                sym = sym_name,
+                "resolved by {} to {:#x}",
+                provider,
+                addr
+            ),
+            None => module_log!(
+                trace,
+                self.module_name.unwrap_or("<unknown>"),
+                sym = sym_name,
+                "not found in {}",
+                provider
+            ),
+        }
+    }
+
+    fn warn(&self, warning: LoadWarning) {
+        self.warnings.borrow_mut().push(warning);
+    }
+
+    /// Bound the load time by refusing modules with too many relocations,
+    /// before any of them is processed
+    fn check_relocation_count(&self) -> Result<()> {
+        let Some(limit) = self.options.max_relocations else {
+            return Ok(());
+        };
+        // Size of Elf64_Rela
+        let count = self
+            .rela_sections
+            .iter()
+            .map(|&idx| self.elf.section_headers[idx].sh_size as usize / 24)
+            .fold(0usize, usize::saturating_add);
+        if count > limit {
+            log::error!(
+                "Module has {} relocations, more than the limit of {}",
+                count,
+                limit
+            );
+            return Err(ModuleErr::TooManyRelocations { count, limit });
+        }
+        Ok(())
     }
 
     fn find_section(&self, name: &str) -> Result<&SectionHeader> {
@@ -184,48 +1962,105 @@ impl<'a, H: KernelModuleHelper> ModuleLoader<'a, H> {
         Err(ModuleErr::InvalidElf)
     }
 
-    fn pre_read_modinfo(&self) -> Result<ModuleOwner<H>> {
+    /// Read the metadata of every module declared in the `.modinfo` section
+    pub fn module_infos(&self) -> Result<Vec<ModuleInfo>> {
         let modinfo_shdr = self.find_section(".modinfo")?;
         let file_offset = modinfo_shdr.sh_offset as usize;
         let size = modinfo_shdr.sh_size as usize;
 
-        let mut modinfo_data = &self.elf_data[file_offset..file_offset + size];
-        let mut module_info = ModuleInfo::new();
-
         log::info!("Reading .modinfo section (size: {:#x})", size);
+        ModuleInfo::parse_all(&self.elf_data[file_offset..file_offset + size])
+    }
 
-        // read the modinfo data
-        // format is key=value\0key=value\0...
-        loop {
-            if modinfo_data.is_empty() {
-                break;
-            }
-            let cstr = CStr::from_bytes_until_nul(modinfo_data)
-                .map_err(|_| ModuleErr::InvalidElf)
-                .unwrap();
-            let str_slice = cstr.to_str().map_err(|_| ModuleErr::InvalidElf)?;
-            modinfo_data = &modinfo_data[cstr.to_bytes_with_nul().len()..];
-
-            let mut split = str_slice.splitn(2, '=');
-            let key = split.next().ok_or(ModuleErr::InvalidElf)?.to_string();
-            let value = split.next().ok_or(ModuleErr::InvalidElf)?.to_string();
-            module_info.add_kv(key, value);
+    fn pre_read_modinfo(&self) -> Result<ModuleOwner<H>> {
+        let mut infos = self.module_infos()?;
+        if infos.len() > 1 {
+            log::warn!(
+                "Found {} modules in .modinfo, only the first one is loaded",
+                infos.len()
+            );
+            self.warn(LoadWarning::ExtraModules(infos.len()));
         }
-
-        let name = module_info
-            .get("name")
-            .ok_or(ModuleErr::InvalidElf)?
-            .to_string();
+        if infos.is_empty() {
+            return Err(ModuleErr::InvalidElf);
+        }
+        let module_info = infos.swap_remove(0);
+        module_info.validate()?;
+        let name = module_info.name().unwrap_or_default().to_string();
 
         Ok(ModuleOwner {
             name,
             module_info,
             pages: Vec::new(),
+            functions: BTreeMap::new(),
+            got_entries: core::cell::RefCell::new(BTreeMap::new()),
+            init_override: self.options.init_override,
+            init_calls: Vec::new(),
+            exit_calls: Vec::new(),
+            init_text: Vec::new(),
+            used_modules: Vec::new(),
             module: Module::default(),
+            init_result: None,
+            userdata: None,
+            taints: Taint::empty(),
+            live: false,
             _helper: core::marker::PhantomData,
         })
     }
 
+    /// Taint the kernel for modules without a GPL compatible license
+    ///
+    /// See <https://elixir.bootlin.com/linux/v6.6/source/kernel/module/main.c#L1699>
+    fn check_license(&self, owner: &mut ModuleOwner<H>) {
+        match owner.module_info.license() {
+            None | Some("") => {
+                log::warn!(
+                    "{}: module license not specified, tainting kernel",
+                    owner.name
+                );
+                self.warn(LoadWarning::MissingLicense);
+            }
+            Some(license) if !license_is_gpl_compatible(license) => {
+                log::warn!("{}: module license '{}' taints kernel", owner.name, license);
+                self.warn(LoadWarning::ProprietaryLicense(license.to_string()));
+            }
+            Some(_) => return,
+        }
+        owner.taints |= Taint::PROPRIETARY_MODULE;
+    }
+
+    /// Compare the version magic of the module with the kernel's
+    ///
+    /// See <https://elixir.bootlin.com/linux/v6.6/source/kernel/module/main.c#L1987>
+    fn check_vermagic(&self, owner: &ModuleOwner<H>) -> Result<()> {
+        let Some(expected) = H::vermagic() else {
+            return Ok(());
+        };
+        let found = owner.module_info.vermagic();
+        if found == Some(expected) {
+            return Ok(());
+        }
+        if self.options.force_vermagic {
+            log::warn!(
+                "{}: forcing load despite version magic {:?}, tainting kernel",
+                owner.name,
+                found
+            );
+            self.warn(LoadWarning::ForcedVermagic(found.map(String::from)));
+            return Ok(());
+        }
+        log::error!(
+            "{}: version magic {:?} should be '{}'",
+            owner.name,
+            found,
+            expected
+        );
+        Err(ModuleErr::VermagicMismatch {
+            expected: expected.to_string(),
+            found: found.map(String::from),
+        })
+    }
+
     fn post_read_modinfo(&mut self, owner: &mut ModuleOwner<H>) -> Result<()> {
         let modinfo_shdr = self.find_section(".gnu.linkonce.this_module")?;
         let size = modinfo_shdr.sh_size as usize;
@@ -241,35 +2076,81 @@ impl<'a, H: KernelModuleHelper> ModuleLoader<'a, H> {
         // the data address is the allocated virtual address and it has been relocated
         let modinfo_data = modinfo_shdr.sh_addr as *mut u8;
         let module = unsafe { core::ptr::read(modinfo_data as *const Module) };
+        owner.init_calls = self
+            .read_calls::<InitCall>(".init_calls")?
+            .iter()
+            .map(|call| (call.priority(), call.func()))
+            .collect();
+        owner.exit_calls = self
+            .read_calls::<ExitCall>(".exit_calls")?
+            .iter()
+            .map(|call| (call.priority(), call.func()))
+            .collect();
+        // Stable, so functions of the same priority keep their link order
+        owner.init_calls.sort_by_key(|&(priority, _)| priority);
+        owner.exit_calls.sort_by_key(|&(priority, _)| priority);
+        if !module.has_init_fn() && owner.init_calls.is_empty() {
+            log::error!(
+                "{}: the module has neither #[init_fn] nor prioritized init functions",
+                owner.name
+            );
+            return Err(ModuleErr::InvalidModuleInfo("missing init function"));
+        }
         owner.module = module;
         Ok(())
     }
 
-    fn set_section_perms(&self, owner: &mut ModuleOwner<H>) -> Result<()> {
-        for page in &mut owner.pages {
-            if !page.addr.change_perms(page.perms) {
-                log::error!(
-                    "Failed to change permissions of section '{}' to {}",
-                    page.name,
-                    page.perms
-                );
-                return Err(ModuleErr::InvalidOperation);
-            }
-            H::flsuh_cache(page.addr.as_ptr() as usize, page.size);
+    /// Read the relocated descriptors of the allocated section `name`, none
+    /// if the module does not have it. Its size must be a whole number of
+    /// descriptors.
+    fn read_calls<T: 'static>(&self, name: &str) -> Result<&[T]> {
+        let Some(shdr) = self.elf.section_headers.iter().find(|shdr| {
+            shdr.sh_flags & goblin::elf::section_header::SHF_ALLOC as u64 != 0
+                && self.elf.shdr_strtab.get_at(shdr.sh_name) == Some(name)
+        }) else {
+            return Ok(&[]);
+        };
+        if shdr.sh_size == 0 {
+            return Ok(&[]);
         }
-        Ok(())
+        let size = shdr.sh_size as usize;
+        if !size.is_multiple_of(core::mem::size_of::<T>()) {
+            log::error!(
+                "Invalid {} section size: {}, not a multiple of {}",
+                name,
+                size,
+                core::mem::size_of::<T>()
+            );
+            return Err(ModuleErr::InvalidElf);
+        }
+        // The data address is the allocated virtual address
+        Ok(unsafe {
+            core::slice::from_raw_parts(shdr.sh_addr as *const T, size / core::mem::size_of::<T>())
+        })
     }
 
     /// Layout sections and allocate memory
     /// See <https://elixir.bootlin.com/linux/v6.6/source/kernel/module/main.c#L2363>
     fn layout_and_allocate(&mut self, owner: &mut ModuleOwner<H>) -> Result<()> {
-        for shdr in &mut self.elf.section_headers {
+        let stub_sizes = self.stub_reservations();
+        for (idx, shdr) in self.elf.section_headers.iter_mut().enumerate() {
             let sec_name = self
                 .elf
                 .shdr_strtab
                 .get_at(shdr.sh_name)
                 .unwrap_or("<unknown>");
 
+            // Notes are metadata even when a toolchain marks them SHF_ALLOC,
+            // dropping the flag also skips relocations targeting them
+            if shdr.sh_type == goblin::elf::section_header::SHT_NOTE {
+                log::debug!("Not loading note section '{}'", sec_name);
+                shdr.sh_flags &= !(goblin::elf::section_header::SHF_ALLOC as u64);
+            }
+            // Symbol versions are only checked while loading
+            if sec_name == "__versions" {
+                shdr.sh_flags &= !(goblin::elf::section_header::SHF_ALLOC as u64);
+            }
+
             // Skip non-allocatable sections
             if (shdr.sh_flags & goblin::elf::section_header::SHF_ALLOC as u64) == 0 {
                 continue;
@@ -278,30 +2159,95 @@ impl<'a, H: KernelModuleHelper> ModuleLoader<'a, H> {
             let file_offset = shdr.sh_offset as usize;
             let size = shdr.sh_size as usize;
 
-            let perms = SectionPerm::from_elf_flags(shdr.sh_flags);
+            let mut perms = SectionPerm::from_elf_flags(shdr.sh_flags);
+            // relro sections are writable only while relocations are applied,
+            // finalize_permissions makes them read-only afterwards
+            if is_relro_section(sec_name) {
+                perms.remove(SectionPerm::WRITE);
+            }
+            // See <https://elixir.bootlin.com/linux/v6.6/source/kernel/module/strict_rwx.c#L87>
+            if perms.contains(SectionPerm::WRITE | SectionPerm::EXECUTE) {
+                log::error!("Module section '{}' is W+X", sec_name);
+                return Err(ModuleErr::InvalidElf);
+            }
 
             if size == 0 {
                 log::error!("Skipping zero-size section '{}'", sec_name);
                 continue;
             }
 
-            let aligned_size = align_up(size, 4096);
+            // 0 and 1 mean no constraint
+            let sh_align = shdr.sh_addralign.max(1) as usize;
+            if !sh_align.is_power_of_two() {
+                log::error!(
+                    "Section '{}' has an invalid alignment of {}",
+                    sec_name,
+                    sh_align
+                );
+                return Err(ModuleErr::InvalidElf);
+            }
+            let exec = (shdr.sh_flags & goblin::elf::section_header::SHF_EXECINSTR as u64) != 0;
+            let huge_page = self.options.huge_pages && exec;
+            // Each section has its own allocation, page alignment covers all
+            // but the largest sh_addralign
+            let align = if huge_page { HUGE_PAGE_SIZE } else { PAGE_SIZE }.max(sh_align);
+            // Stubs live in the same allocation, right after the section
+            let stub_offset = align_up(size, 8);
+            let stub_size = stub_sizes[idx];
+            let aligned_size = align_up(stub_offset + stub_size, align);
 
-            // Allocate memory for the section
-            let mut addr = H::vmalloc(aligned_size);
+            // Allocate memory for the section from the pool chosen by the
+            // placer, code and data may come from different allocators
+            let hint = AllocHint {
+                pool: (self.placer)(sec_name, shdr.sh_flags, size),
+                align,
+                huge_page,
+            };
+            let mut addr = if self.region.is_some() {
+                Self::carve_region(&mut self.region, aligned_size, align)
+                    .ok_or(ModuleErr::MemoryAllocationFailed)?
+            } else if exec {
+                H::alloc_exec(aligned_size, hint)
+            } else {
+                H::alloc_data(aligned_size, hint)
+            };
             if addr.as_ptr().is_null() {
                 return Err(ModuleErr::MemoryAllocationFailed);
             }
-
-            let raw_addr = addr.as_ptr() as u64;
+
+            let raw_addr = addr.as_ptr() as u64;
+            if !raw_addr.is_multiple_of(sh_align as u64) {
+                log::error!(
+                    "Section '{}' allocated at {:#x}, which is not {} byte aligned",
+                    sec_name,
+                    raw_addr,
+                    sh_align
+                );
+                return Err(ModuleErr::MemoryAllocationFailed);
+            }
+            // The default alloc ignores the hint, the helper may not support
+            // huge pages either
+            if huge_page && !raw_addr.is_multiple_of(HUGE_PAGE_SIZE as u64) {
+                log::warn!(
+                    "Section '{}' allocated at {:#x}, not on a huge page",
+                    sec_name,
+                    raw_addr
+                );
+                self.warnings
+                    .borrow_mut()
+                    .push(LoadWarning::HugePagesIgnored(sec_name.to_string()));
+            }
 
             // Copy section data from ELF to allocated memory
-            // For SHT_NOBITS sections (like .bss), memory is already zeroed by vmalloc
+            // SHT_NOBITS sections (like .bss) have no file data and start zeroed,
+            // the host allocator does not have to hand out zeroed memory
             if shdr.sh_type != goblin::elf::section_header::SHT_NOBITS {
                 let section_data = &self.elf_data[file_offset..file_offset + size];
                 unsafe {
                     core::ptr::copy_nonoverlapping(section_data.as_ptr(), addr.as_mut_ptr(), size);
                 }
+            } else {
+                unsafe { core::ptr::write_bytes(addr.as_mut_ptr(), 0, size) };
             }
 
             // Store the allocated page info
@@ -309,7 +2255,12 @@ impl<'a, H: KernelModuleHelper> ModuleLoader<'a, H> {
                 name: sec_name.to_string(),
                 addr,
                 size: aligned_size,
+                sh_size: size,
                 perms,
+                pool: hint.pool,
+                stub_offset,
+                stub_size,
+                stubs_used: core::cell::Cell::new(0),
             });
 
             // update section address
@@ -320,34 +2271,95 @@ impl<'a, H: KernelModuleHelper> ModuleLoader<'a, H> {
 
         for page in &owner.pages {
             log::error!(
-                "Allocated section '{:>16}' at {:p} [{}] ({:8<#x})",
+                "Allocated section '{:>16}' at {:p} [{}] ({:8<#x}) pool {}",
                 page.name,
                 page.addr.as_ptr(),
                 page.perms,
-                page.size
+                page.size,
+                page.pool.0
             );
         }
 
         Ok(())
     }
 
+    /// Lay out the `SHF_TLS` sections (`.tdata` followed by `.tbss`) as one
+    /// TLS block and reserve it through [`KernelModuleHelper::tls_block_offset`]
+    fn layout_tls(&mut self) {
+        use goblin::elf::section_header::SHF_TLS;
+        let mut size = 0;
+        let mut align = 1;
+        self.tls_offsets = self
+            .elf
+            .section_headers
+            .iter()
+            .map(|shdr| {
+                if shdr.sh_flags & SHF_TLS as u64 == 0 {
+                    return None;
+                }
+                let sec_align = (shdr.sh_addralign as usize).max(1);
+                let offset = align_up(size, sec_align);
+                size = offset + shdr.sh_size as usize;
+                align = align.max(sec_align);
+                Some(offset as u64)
+            })
+            .collect();
+        if size != 0 {
+            self.tls_base = H::tls_block_offset(size, align);
+            module_log!(
+                info,
+                self.module_name.unwrap_or("<unknown>"),
+                "TLS block of {:#x} bytes (align {}) at tp + {:?}",
+                size,
+                align,
+                self.tls_base
+            );
+        }
+    }
+
     /// Change all symbols so that st_value encodes the pointer directly.
     ///
     /// See <https://elixir.bootlin.com/linux/v6.6/source/kernel/module/main.c#L1367>
-    fn simplify_symbols(&self) -> Result<ModuleLoadInfo> {
-        let mut loadinfo = ModuleLoadInfo { syms: Vec::new() };
+    fn simplify_symbols(
+        &self,
+        syms: &goblin::elf::Symtab,
+        strtab: &goblin::strtab::Strtab,
+    ) -> Result<ModuleLoadInfo<'a>> {
+        let mut loadinfo = ModuleLoadInfo {
+            syms: Vec::new(),
+            machine: self.elf.header.e_machine,
+            relocations: BTreeMap::new(),
+            tls_base: self.tls_base,
+            strict: self.options.strict_relocations,
+            relocation_groups: Vec::new(),
+            init_text: Vec::new(),
+            versions: self.versions.clone(),
+            link_addrs: self.link_addrs.clone(),
+        };
+        // Names of the strong (STB_GLOBAL) symbols defined by the module
+        let mut defined_globals = BTreeSet::new();
+        let kernel_symbols = self.resolve_kernel_symbols(syms, strtab);
 
         // Skip the first symbol (index 0), which is always the undefined symbol
-        for (idx, sym) in self.elf.syms.iter().enumerate() {
+        for (idx, sym) in syms.iter().enumerate() {
             if idx == 0 {
                 loadinfo.syms.push((sym, "".to_string()));
                 // Symbol 0 is always SHN_UNDEF and should be skipped
                 continue;
             }
 
-            let sym_name = self.elf.strtab.get_at(sym.st_name).unwrap_or("<unknown>");
+            let raw_name = strtab.get_at(sym.st_name).unwrap_or("<unknown>");
 
-            let sym_name = format!("{:#}", rustc_demangle::demangle(sym_name));
+            let sym_name = format!("{:#}", rustc_demangle::demangle(raw_name));
+
+            // A strong symbol may only be defined once, weak duplicates are fine
+            if sym.st_bind() == goblin::elf::sym::STB_GLOBAL
+                && sym.st_shndx != goblin::elf::section_header::SHN_UNDEF as usize
+                && !defined_globals.insert(raw_name)
+            {
+                log::error!("Duplicate definition of global symbol '{}'", sym_name);
+                return Err(ModuleErr::DuplicateSymbolDefinition(sym_name));
+            }
             let sym_value = sym.st_value;
             let sym_size = sym.st_size;
 
@@ -366,7 +2378,7 @@ impl<'a, H: KernelModuleHelper> ModuleLoader<'a, H> {
             match sym.st_shndx as _ {
                 goblin::elf::section_header::SHN_UNDEF => {
                     // Undefined symbol
-                    let sym_address = H::resolve_symbol(&sym_name);
+                    let sym_address = self.resolve_symbol(&sym_name, &kernel_symbols)?;
                     // Ok if resolved.
                     if let Some(addr) = sym_address {
                         log::error!(
@@ -378,19 +2390,21 @@ impl<'a, H: KernelModuleHelper> ModuleLoader<'a, H> {
                         // Update the symbol table entry's st_value to the resolved address
                         updated_sym.st_value = addr as u64;
                     } else {
-                        // Ok if weak or ignored.
+                        // Ok if weak.
                         if sym.st_bind() == goblin::elf::sym::STB_WEAK {
                             log::warn!(
                                 "  -> Unresolved weak symbol '{}' ({})",
                                 sym_name,
                                 sym_bind_to_str(sym.st_bind())
                             );
+                            self.warn(LoadWarning::UnresolvedWeakSymbol(sym_name.clone()));
                         } else {
-                            log::warn!(
+                            log::error!(
                                 "  -> Unresolved symbol '{}' ({})",
                                 sym_name,
                                 sym_bind_to_str(sym.st_bind())
                             );
+                            return Err(ModuleErr::UnresolvedSymbol(sym_name));
                         }
                     }
                 }
@@ -419,15 +2433,30 @@ impl<'a, H: KernelModuleHelper> ModuleLoader<'a, H> {
                     // TODO: Handle special sections like percpu
                     // Normal symbol defined in a section
                     // Add section base address to symbol's offset within the section
-                    let secbase = self.elf.section_headers[ty as usize].sh_addr;
-                    updated_sym.st_value = sym.st_value.wrapping_add(secbase);
+                    let secbase =
+                        crate::arch::section_header(&self.elf.section_headers, ty as usize)?
+                            .sh_addr;
+                    // In a relocatable object st_value is already relative to
+                    // the section, in a linked image it is the link address
+                    // and has to be rebased onto the load address
+                    let sec_offset = if self.elf.header.e_type == goblin::elf::header::ET_REL {
+                        sym.st_value
+                    } else {
+                        sym.st_value.wrapping_sub(self.link_addrs[ty as usize])
+                    };
+                    updated_sym.st_value = match self.tls_offsets.get(ty as usize) {
+                        // Thread-local variables are addressed relative to
+                        // the TLS block, not to the `.tdata` image
+                        Some(Some(tls_offset)) => sec_offset.wrapping_add(*tls_offset),
+                        _ => sec_offset.wrapping_add(secbase),
+                    };
                     log::trace!(
                         "  -> Defined symbol '{}' in section {} at address 0x{:016x} (base: 0x{:016x} + offset: 0x{:016x})",
                         sym_name,
                         ty,
                         updated_sym.st_value,
                         secbase,
-                        sym.st_value
+                        sec_offset
                     );
                 }
             }
@@ -439,40 +2468,234 @@ impl<'a, H: KernelModuleHelper> ModuleLoader<'a, H> {
         Ok(loadinfo)
     }
 
-    /// See <https://elixir.bootlin.com/linux/v6.6/source/kernel/module/main.c#L1438>
-    fn apply_relocations(&self, load_info: ModuleLoadInfo, owner: &ModuleOwner<H>) -> Result<()> {
-        for (_, shdr) in self.elf.section_headers.iter().enumerate() {
-            let infosec = shdr.sh_info;
+    /// Simplify the symbol tables other than `.symtab` that relocation
+    /// sections link to, e.g. `.dynsym`, keyed by section index
+    fn simplify_linked_symtabs(&self) -> Result<BTreeMap<usize, ModuleLoadInfo<'a>>> {
+        use goblin::elf::section_header::{SHT_DYNSYM, SHT_SYMTAB};
+        // goblin reads `.symtab` from the last SHT_SYMTAB section
+        let symtab_idx = self
+            .elf
+            .section_headers
+            .iter()
+            .rposition(|shdr| shdr.sh_type == SHT_SYMTAB);
+        let ctx = goblin::container::Ctx::new(
+            goblin::container::Container::Big,
+            if self.elf.little_endian {
+                goblin::container::Endian::Little
+            } else {
+                goblin::container::Endian::Big
+            },
+        );
 
-            let sec_name = self
+        let mut infos = BTreeMap::new();
+        for &rela_idx in &self.rela_sections {
+            let link = self.elf.section_headers[rela_idx].sh_link as usize;
+            if Some(link) == symtab_idx || infos.contains_key(&link) {
+                continue;
+            }
+            let shdr = self
                 .elf
-                .shdr_strtab
-                .get_at(shdr.sh_name)
+                .section_headers
+                .get(link)
+                .filter(|shdr| shdr.sh_type == SHT_SYMTAB || shdr.sh_type == SHT_DYNSYM)
+                .ok_or_else(|| {
+                    log::error!(
+                        "Relocation section {} does not link to a symbol table",
+                        rela_idx
+                    );
+                    ModuleErr::InvalidElf
+                })?;
+            let count = shdr.sh_size.checked_div(shdr.sh_entsize).unwrap_or(0) as usize;
+            let syms =
+                goblin::elf::Symtab::parse(self.elf_data, shdr.sh_offset as usize, count, ctx)
+                    .map_err(|_| ModuleErr::InvalidElf)?;
+            let str_shdr = self
+                .elf
+                .section_headers
+                .get(shdr.sh_link as usize)
                 .ok_or(ModuleErr::InvalidElf)?;
+            let strtab = goblin::strtab::Strtab::parse(
+                self.elf_data,
+                str_shdr.sh_offset as usize,
+                str_shdr.sh_size as usize,
+                0x0,
+            )
+            .map_err(|_| ModuleErr::InvalidElf)?;
+            infos.insert(link, self.simplify_symbols(&syms, &strtab)?);
+        }
+        Ok(infos)
+    }
+
+    /// Remember where the functions defined by the module live, so they can
+    /// be patched later
+    fn record_functions(load_info: &ModuleLoadInfo, owner: &mut ModuleOwner<H>) {
+        for (sym, name) in &load_info.syms {
+            if sym.st_type() != goblin::elf::sym::STT_FUNC || sym.st_size == 0 {
+                continue;
+            }
+            match sym.st_shndx as _ {
+                goblin::elf::section_header::SHN_UNDEF
+                | goblin::elf::section_header::SHN_ABS
+                | goblin::elf::section_header::SHN_COMMON => continue,
+                _ => {}
+            }
+            owner
+                .functions
+                .insert(name.clone(), (sym.st_value as usize, sym.st_size as usize));
+        }
+    }
+
+    /// Get the entries of the relocation section `shdr`
+    fn rela_list(&self, shdr: &SectionHeader) -> &'a [goblin::elf64::reloc::Rela] {
+        let offset = shdr.sh_offset as usize;
+        let data_buf = &self.elf_data[offset..offset + shdr.sh_size as usize];
+        unsafe {
+            goblin::elf64::reloc::from_raw_rela(data_buf.as_ptr() as _, shdr.sh_size as usize)
+        }
+    }
 
-            // Not a valid relocation section?
-            if infosec >= self.elf.section_headers.len() as u32 {
+    /// Partition the relocation sections into groups that can be applied
+    /// independently, e.g. in parallel, given as indices of the sections.
+    ///
+    /// Relocation sections patching the same section share its stub area and
+    /// may patch the same location, and a RISC-V `PCREL_LO12` reads the
+    /// `PCREL_HI20` at its label, so such sections land in the same group.
+    pub fn relocation_groups(&self) -> Vec<Vec<usize>> {
+        // Union-find over the section indices, a relocation section joins
+        // the set of the section it patches
+        let mut parent: Vec<usize> = (0..self.elf.section_headers.len()).collect();
+        fn find(parent: &mut [usize], mut idx: usize) -> usize {
+            while parent[idx] != idx {
+                parent[idx] = parent[parent[idx]];
+                idx = parent[idx];
+            }
+            idx
+        }
+        let symtab_idx = self
+            .elf
+            .section_headers
+            .iter()
+            .rposition(|shdr| shdr.sh_type == goblin::elf::section_header::SHT_SYMTAB);
+        for &rela_idx in &self.rela_sections {
+            let shdr = &self.elf.section_headers[rela_idx];
+            let target = shdr.sh_info as usize;
+            if target >= parent.len() {
                 continue;
             }
-            // Don't bother with non-allocated sections
-            if self.elf.section_headers[infosec as usize].sh_flags
-                & goblin::elf::section_header::SHF_ALLOC as u64
-                == 0
+            let root = find(&mut parent, target);
+            let rela_root = find(&mut parent, rela_idx);
+            parent[rela_root] = root;
+            if self.elf.header.e_machine != goblin::elf::header::EM_RISCV
+                || Some(shdr.sh_link as usize) != symtab_idx
             {
                 continue;
             }
+            for rela in self.rela_list(shdr) {
+                if !matches!(
+                    crate::arch::Riscv64RelocationType::try_from(rela.r_info as u32),
+                    Ok(crate::arch::Riscv64RelocationType::R_RISCV_PCREL_LO12_I
+                        | crate::arch::Riscv64RelocationType::R_RISCV_PCREL_LO12_S)
+                ) {
+                    continue;
+                }
+                let label = self.elf.syms.get((rela.r_info >> 32) as usize);
+                if let Some(label_sec) = label
+                    .map(|sym| sym.st_shndx)
+                    .filter(|&shndx| shndx != 0 && shndx < parent.len())
+                {
+                    let root = find(&mut parent, target);
+                    let label_root = find(&mut parent, label_sec);
+                    parent[label_root] = root;
+                }
+            }
+        }
+
+        let mut groups: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for &rela_idx in &self.rela_sections {
+            groups
+                .entry(find(&mut parent, rela_idx))
+                .or_default()
+                .push(rela_idx);
+        }
+        groups.into_values().collect()
+    }
+
+    /// Bytes to reserve after each section for the stubs of its relocations
+    fn stub_reservations(&self) -> Vec<usize> {
+        let mut stub_sizes = alloc::vec![0; self.elf.section_headers.len()];
+        let reservation = match self.elf.header.e_machine {
+            goblin::elf::header::EM_RISCV => crate::arch::Riscv64ArchRelocate::stub_reservation,
+            goblin::elf::header::EM_X86_64 => crate::arch::X86_64ArchRelocate::stub_reservation,
+            _ => return stub_sizes,
+        };
+        for &rela_idx in &self.rela_sections {
+            let shdr = &self.elf.section_headers[rela_idx];
+            if let Some(stub_size) = stub_sizes.get_mut(shdr.sh_info as usize) {
+                *stub_size += reservation(self.rela_list(shdr));
+            }
+        }
+        stub_sizes
+    }
+
+    /// See <https://elixir.bootlin.com/linux/v6.6/source/kernel/module/main.c#L1438>
+    fn apply_relocations(
+        &self,
+        load_info: ModuleLoadInfo<'a>,
+        linked_infos: BTreeMap<usize, ModuleLoadInfo<'a>>,
+        owner: &ModuleOwner<H>,
+    ) -> Result<()> {
+        reject_rel_sections(&self.elf)?;
+        // Failed entries across all sections, if they are collected
+        let mut failures = Vec::new();
+        for &rela_idx in &self.rela_sections {
+            let shdr = crate::arch::section_header(&self.elf.section_headers, rela_idx)?;
+            let infosec = shdr.sh_info;
+            // The symbol table the entries index, `.symtab` unless sh_link
+            // names another one
+            let load_info = linked_infos
+                .get(&(shdr.sh_link as usize))
+                .unwrap_or(&load_info);
+
+            let sec_name = self
+                .elf
+                .shdr_strtab
+                .get_at(shdr.sh_name)
+                .ok_or(ModuleErr::InvalidElf)?;
 
-            // Skip non-relocation sections
-            if shdr.sh_type != goblin::elf::section_header::SHT_RELA {
+            let to_section =
+                crate::arch::section_header(&self.elf.section_headers, infosec as usize)?;
+            // Don't bother with non-allocated sections, notes included
+            if to_section.sh_flags & goblin::elf::section_header::SHF_ALLOC as u64 == 0 {
                 continue;
             }
 
-            let to_section = &self.elf.section_headers[infosec as usize];
             let to_sec_name = self
                 .elf
                 .shdr_strtab
                 .get_at(to_section.sh_name)
                 .ok_or(ModuleErr::InvalidElf)?;
+            // Every allocated section got memory in layout_and_allocate, an
+            // address of 0 means the entries would patch unmapped memory
+            if to_section.sh_addr == 0 && shdr.sh_size != 0 {
+                log::error!(
+                    "Relocation section '{}' targets '{}', which has no memory",
+                    sec_name,
+                    to_sec_name
+                );
+                return Err(ModuleErr::InvalidElf);
+            }
+            // Size of Elf64_Rela, anything else would misread every entry
+            if shdr.sh_entsize != 24 {
+                log::error!(
+                    "Relocation section '{}' has {} byte entries, expected 24",
+                    sec_name,
+                    shdr.sh_entsize
+                );
+                return Err(ModuleErr::BadRelaEntsize {
+                    section: rela_idx,
+                    entsize: shdr.sh_entsize,
+                });
+            }
 
             let rela_entries = shdr.sh_size as usize / shdr.sh_entsize as usize;
             log::error!(
@@ -482,70 +2705,149 @@ impl<'a, H: KernelModuleHelper> ModuleLoader<'a, H> {
                 rela_entries
             );
 
-            let offset = shdr.sh_offset as usize;
-            // Size of Elf64_Rela
-            debug_assert!(shdr.sh_entsize == 24);
-
-            let data_buf = &self.elf_data[offset..offset + shdr.sh_size as usize];
-            let rela_list = unsafe {
-                goblin::elf64::reloc::from_raw_rela(data_buf.as_ptr() as _, shdr.sh_size as usize)
-            };
+            let rela_list = self.rela_list(shdr);
 
-            match self.elf.header.e_machine {
-                goblin::elf::header::EM_RISCV => {
-                    crate::arch::Riscv64ArchRelocate::apply_relocate_add(
-                        &rela_list,
-                        shdr,
-                        &self.elf.section_headers,
-                        &load_info,
-                        owner,
-                    )?;
-                }
-                goblin::elf::header::EM_LOONGARCH => {
-                    crate::arch::Loongarch64ArchRelocate::apply_relocate_add(
-                        &rela_list,
-                        shdr,
-                        &self.elf.section_headers,
-                        &load_info,
-                        owner,
-                    )?;
-                }
-                goblin::elf::header::EM_AARCH64 => {
-                    crate::arch::Aarch64ArchRelocate::apply_relocate_add(
-                        &rela_list,
-                        shdr,
-                        &self.elf.section_headers,
-                        &load_info,
-                        owner,
-                    )?;
+            let mut progress = RelocProgress::new(
+                rela_idx,
+                self.elf.header.e_machine,
+                owner.name(),
+                self.options
+                    .collect_relocation_failures
+                    .then_some(&mut failures),
+            );
+            let res = crate::arch::apply_relocate_add(
+                self.elf.header.e_machine,
+                &rela_list,
+                shdr,
+                &self.elf.section_headers,
+                load_info,
+                owner,
+                &mut progress,
+            );
+            let applied = progress.applied;
+            res.map_err(|err| {
+                log::error!(
+                    "Relocation of '{}' failed after {} of {} entries",
+                    to_sec_name,
+                    applied,
+                    rela_entries
+                );
+                ModuleErr::PartialRelocation {
+                    applied,
+                    err: Box::new(err),
                 }
-                goblin::elf::header::EM_X86_64 => {
-                    crate::arch::X86_64ArchRelocate::apply_relocate_add(
-                        &rela_list,
-                        shdr,
-                        &self.elf.section_headers,
-                        &load_info,
-                        owner,
-                    )?;
+            })?;
+        }
+        if !failures.is_empty() {
+            log::error!("{} relocations failed", failures.len());
+            return Err(ModuleErr::MultipleRelocationFailures(failures));
+        }
+        Ok(())
+    }
+
+    /// Patch in the replacement of every `.altinstructions` entry whose
+    /// feature the CPU has, padding the rest of the original with nops.
+    ///
+    /// The original and the replacement must each lie inside a section of the
+    /// module. A replacement that is a single x86 `call`/`jmp rel32` gets its
+    /// displacement adjusted to the new address, like Linux does.
+    /// See <https://elixir.bootlin.com/linux/v6.6/source/arch/x86/kernel/alternative.c#L432>
+    fn apply_alternatives(&self, owner: &ModuleOwner<H>) -> Result<()> {
+        let Some(shdr) = self.elf.section_headers.iter().find(|shdr| {
+            self.elf.shdr_strtab.get_at(shdr.sh_name) == Some(".altinstructions")
+                && shdr.sh_flags & goblin::elf::section_header::SHF_ALLOC as u64 != 0
+        }) else {
+            return Ok(());
+        };
+        let entry_size = core::mem::size_of::<AltInstr>();
+        if !(shdr.sh_size as usize).is_multiple_of(entry_size) {
+            log::error!(
+                "Invalid .altinstructions size {:#x}, not a multiple of {}",
+                shdr.sh_size,
+                entry_size
+            );
+            return Err(ModuleErr::InvalidElf);
+        }
+        let nop = self.nop_insn();
+        for idx in 0..shdr.sh_size as usize / entry_size {
+            let entry_addr = shdr.sh_addr as usize + idx * entry_size;
+            // Relocated in place, so the entry may not be aligned
+            let entry = unsafe { (entry_addr as *const AltInstr).read_unaligned() };
+            let feature = entry.feature;
+            if !H::cpu_has(feature) {
+                continue;
+            }
+            let (instrlen, replacementlen) =
+                (entry.instrlen as usize, entry.replacementlen as usize);
+            if replacementlen > instrlen || !(instrlen - replacementlen).is_multiple_of(nop.len()) {
+                log::error!(
+                    "Alternative {} of {} bytes does not fit its {} byte original",
+                    idx,
+                    replacementlen,
+                    instrlen
+                );
+                return Err(ModuleErr::InvalidElf);
+            }
+            let instr_addr = (entry_addr as u64).wrapping_add(entry.instr_offset as i64 as u64);
+            let repl_addr = (entry_addr as u64 + 4).wrapping_add(entry.repl_offset as i64 as u64);
+            for (what, addr, len) in [
+                ("original", instr_addr, instrlen),
+                ("replacement", repl_addr, replacementlen),
+            ] {
+                let in_section = owner.section_bounds(addr).is_some_and(|bounds| {
+                    addr.checked_add(len as u64)
+                        .is_some_and(|end| end <= bounds.end)
+                });
+                if !in_section {
+                    log::error!(
+                        "The {} of alternative {} at {:#x}..{:#x} is outside of the module",
+                        what,
+                        idx,
+                        addr,
+                        addr.wrapping_add(len as u64)
+                    );
+                    return Err(ModuleErr::InvalidElf);
                 }
-                _ => {
-                    panic!(
-                        "Relocations for architecture '{}' not supported",
-                        self.get_machine_type()
+            }
+            let instr = instr_addr as *mut u8;
+            let repl = repl_addr as *const u8;
+            log::debug!(
+                "Applying alternative {} for feature {} at {:p}",
+                idx,
+                feature,
+                instr
+            );
+            unsafe {
+                core::ptr::copy(repl, instr, replacementlen);
+                // The displacement was relocated for the replacement's own
+                // address
+                if self.elf.header.e_machine == goblin::elf::header::EM_X86_64
+                    && replacementlen == 5
+                    && matches!(*instr, 0xe8 | 0xe9)
+                {
+                    let disp = instr.add(1) as *mut i32;
+                    disp.write_unaligned(
+                        disp.read_unaligned()
+                            .wrapping_add(repl_addr.wrapping_sub(instr_addr) as i32),
                     );
                 }
+                for pad in (replacementlen..instrlen).step_by(nop.len()) {
+                    core::ptr::copy_nonoverlapping(nop.as_ptr(), instr.add(pad), nop.len());
+                }
             }
         }
         Ok(())
     }
 
-    fn get_machine_type(&self) -> &'static str {
+    /// Smallest nop instruction of the module's architecture, in memory order
+    fn nop_insn(&self) -> &'static [u8] {
         match self.elf.header.e_machine {
-            goblin::elf::header::EM_X86_64 => "x86-64",
-            goblin::elf::header::EM_AARCH64 => "AArch64",
-            goblin::elf::header::EM_RISCV => "RISC-V",
-            goblin::elf::header::EM_LOONGARCH => "LoongArch",
-            _ => "unknown",
+            goblin::elf::header::EM_X86_64 => &[0x90],
+            // addi x0, x0, 0
+            goblin::elf::header::EM_RISCV => &[0x13, 0x00, 0x00, 0x00],
+            goblin::elf::header::EM_AARCH64 => &[0x1f, 0x20, 0x03, 0xd5],
+            // andi $r0, $r0, 0
+            _ => &[0x00, 0x00, 0x40, 0x03],
         }
     }
 }
@@ -573,3 +2875,1474 @@ const fn sym_section_to_str(shndx: u32) -> &'static str {
 }
 
 // #define SHN_LIVEPATCH	0xff20
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{ElfBuilder, TestHelper, capture_logs, init_ok, with_host};
+    use goblin::elf::header::{EM_RISCV, EM_X86_64};
+    use goblin::elf::sym::{STB_GLOBAL, STB_LOCAL, STB_WEAK, STT_FUNC, STT_NOTYPE, STT_OBJECT};
+
+    /// Record an init or exit function running
+    macro_rules! recorded {
+        (init $name:ident, $result:expr) => {
+            unsafe extern "C" fn $name() -> core::ffi::c_int {
+                with_host(|host| host.calls.push(stringify!($name)));
+                $result
+            }
+        };
+        (exit $name:ident) => {
+            unsafe extern "C" fn $name() {
+                with_host(|host| host.calls.push(stringify!($name)));
+            }
+        };
+    }
+
+    #[test]
+    fn huge_pages_hint_text_only() {
+        let elf = ElfBuilder::module(EM_RISCV, init_ok).build();
+        let options = LoadOptions {
+            huge_pages: true,
+            ..Default::default()
+        };
+        let (_owner, warnings) = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .with_options(options.clone())
+            .load_with_warnings()
+            .unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        let allocations = with_host(|host| core::mem::take(&mut host.allocations));
+        // .text, then .gnu.linkonce.this_module
+        assert_eq!(allocations.len(), 2);
+        let (text_size, text) = allocations[0];
+        assert!(text.huge_page);
+        assert_eq!(text.align, HUGE_PAGE_SIZE);
+        assert!(text_size.is_multiple_of(HUGE_PAGE_SIZE));
+        let (_, data) = allocations[1];
+        assert!(!data.huge_page);
+        assert_eq!(data.align, PAGE_SIZE);
+
+        // A helper ignoring the hint is reported
+        with_host(|host| host.ignore_align = true);
+        let (_owner, warnings) = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .with_options(options)
+            .load_with_warnings()
+            .unwrap();
+        assert_eq!(
+            warnings,
+            [LoadWarning::HugePagesIgnored(".text".to_string())]
+        );
+    }
+
+    #[test]
+    fn namespaced_session_export() {
+        let mut session = LoadSession::new();
+        session
+            .register_export(
+                "dma",
+                "dma_map",
+                0x1000,
+                Some("DMA"),
+                &LoadOptions::default(),
+            )
+            .unwrap();
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        builder.symbol("dma_map", STB_GLOBAL, STT_FUNC, 0, 0);
+
+        let elf = builder.build();
+        let err = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .with_session(&session)
+            .load_with_warnings()
+            .err()
+            .expect("namespace not imported");
+        assert!(matches!(
+            err,
+            ModuleErr::NamespaceViolation { ref symbol, ref namespace }
+                if symbol == "dma_map" && namespace == "DMA"
+        ));
+
+        let format_version = format!("format_version={}", kmod::FORMAT_VERSION);
+        builder.modinfo(&["name=test", "version=1.0", &format_version, "import_ns=DMA"]);
+        let elf = builder.build();
+        let (owner, _) = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .with_session(&session)
+            .load_with_warnings()
+            .unwrap();
+        assert!(owner.used_modules().any(|used| used == "dma"));
+    }
+
+    #[test]
+    fn collect_relocation_failures() {
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        let sym = builder.symbol("label", STB_GLOBAL, STT_FUNC, 1, 0);
+        let rela = builder.rela(
+            1,
+            &[
+                // R_RISCV_64 past the end of .text
+                (0x100, sym, 2, 0),
+                // A type RISC-V does not define
+                (0, sym, 60, 0),
+                // R_RISCV_PCREL_LO12_I without its HI20
+                (4, sym, 24, 0),
+                // R_RISCV_64, fine
+                (8, sym, 2, 0),
+            ],
+        );
+        let elf = builder.build();
+
+        let err = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_warnings()
+            .err()
+            .expect("relocations fail");
+        assert!(matches!(
+            err,
+            ModuleErr::PartialRelocation { applied: 0, ref err }
+                if matches!(**err, ModuleErr::RelocationEntry { .. })
+        ));
+
+        let err = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .with_options(LoadOptions {
+                collect_relocation_failures: true,
+                ..Default::default()
+            })
+            .load_with_warnings()
+            .err()
+            .expect("relocations fail");
+        let ModuleErr::MultipleRelocationFailures(failures) = err else {
+            panic!("failures not collected: {}", err);
+        };
+        // Each failure says which entry it comes from
+        let failures: Vec<_> = failures
+            .into_iter()
+            .map(|failure| match failure {
+                ModuleErr::RelocationEntry {
+                    section,
+                    offset,
+                    type_name,
+                    symbol,
+                    err,
+                } => {
+                    assert_eq!(section, rela);
+                    assert_eq!(symbol, "label");
+                    (offset, type_name, *err)
+                }
+                failure => panic!("no relocation context: {}", failure),
+            })
+            .collect();
+        assert_eq!(failures.len(), 3);
+        assert!(matches!(
+            &failures[0],
+            (0x100, name, ModuleErr::RelocationOutOfBounds { .. }) if name == "R_RISCV_64"
+        ));
+        assert!(matches!(
+            &failures[1],
+            (
+                0,
+                _,
+                ModuleErr::UnsupportedRelocation { reloc_type: 60, .. }
+            )
+        ));
+        assert!(matches!(
+            &failures[2],
+            (4, name, ModuleErr::OrphanLo12 { .. }) if name == "R_RISCV_PCREL_LO12_I"
+        ));
+    }
+
+    #[test]
+    fn partial_relocation_count() {
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        let sym = builder.symbol("label", STB_GLOBAL, STT_FUNC, 1, 0);
+        // Ten R_RISCV_64 entries, the fifth past the end of .text
+        let entries: Vec<_> = (0..10)
+            .map(|i| {
+                let offset = if i == 4 { 0x100 } else { (i % 2) * 8 };
+                (offset, sym, 2, 0)
+            })
+            .collect();
+        builder.rela(1, &entries);
+        let elf = builder.build();
+
+        let err = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_warnings()
+            .err()
+            .expect("the fifth relocation fails");
+        assert!(
+            matches!(
+                err,
+                ModuleErr::PartialRelocation { applied: 4, ref err }
+                    if matches!(
+                        **err,
+                        ModuleErr::RelocationEntry { offset: 0x100, ref err, .. }
+                            if matches!(**err, ModuleErr::RelocationOutOfBounds { .. })
+                    )
+            ),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn load_parsed_module() {
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        let sym = builder.symbol("table", STB_GLOBAL, STT_OBJECT, 0, 0);
+        // R_RISCV_64 of the first dword
+        builder.rela(1, &[(0, sym, 2, 0)]);
+        let elf = builder.build();
+        with_host(|host| host.symbols = alloc::vec![("table", 0x1234_5678)]);
+
+        let parsed = ParsedModule::parse(&elf).unwrap();
+        assert_eq!(parsed.undefined_symbols(), ["table"]);
+        parsed.validate().unwrap();
+        let (owner, _) = ModuleLoader::<TestHelper>::from_parsed(parsed)
+            .load_with_warnings()
+            .unwrap();
+        assert_eq!(owner.name(), "test");
+        let text = unsafe { owner.section_bytes(".text") }.unwrap();
+        assert_eq!(text[..8], 0x1234_5678u64.to_le_bytes());
+    }
+
+    #[test]
+    fn parse_limits() {
+        let elf = ElfBuilder::module(EM_RISCV, init_ok).build();
+        let header = Elf::parse_header(&elf).unwrap();
+        let limits = ParseLimits {
+            max_sections: 64,
+            max_symbols: 1000,
+        };
+        ParsedModule::parse_with_limits(&elf, limits).unwrap();
+
+        // e_shnum
+        let mut patched = elf.clone();
+        patched[60..62].copy_from_slice(&100u16.to_le_bytes());
+        let err = ParsedModule::parse_with_limits(&patched, limits)
+            .err()
+            .expect("too many sections");
+        assert!(matches!(
+            err,
+            ModuleErr::TooManySections {
+                count: 100,
+                limit: 64
+            }
+        ));
+
+        // sh_size of the symbol table
+        let symtab = Elf::parse(&elf)
+            .unwrap()
+            .section_headers
+            .iter()
+            .position(|shdr| shdr.sh_type == goblin::elf::section_header::SHT_SYMTAB)
+            .unwrap();
+        let sh_size = header.e_shoff as usize + symtab * 64 + 32;
+        let mut patched = elf.clone();
+        patched[sh_size..sh_size + 8].copy_from_slice(&(2000u64 * 24).to_le_bytes());
+        let err = ParsedModule::parse_with_limits(&patched, limits)
+            .err()
+            .expect("too many symbols");
+        assert!(matches!(
+            err,
+            ModuleErr::TooManySymbols {
+                count: 2000,
+                limit: 1000
+            }
+        ));
+    }
+
+    #[test]
+    fn relocation_groups() {
+        use goblin::elf::section_header::{SHF_ALLOC, SHF_EXECINSTR, SHT_PROGBITS};
+        let build = |cross_label: bool| {
+            let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+            let flags = (SHF_ALLOC | SHF_EXECINSTR) as u64;
+            let a = builder.section(".text.a", SHT_PROGBITS, flags, 4, &[0; 16]);
+            let b = builder.section(".text.b", SHT_PROGBITS, flags, 4, &[0; 16]);
+            let var = builder.symbol("var", STB_GLOBAL, STT_OBJECT, a, 8);
+            let label = builder.symbol(".L0", STB_LOCAL, STT_NOTYPE, a, 0);
+            // R_RISCV_64 in each section
+            let rela_a = builder.rela(a, &[(0, var, 2, 0)]);
+            let rela_b = if cross_label {
+                // R_RISCV_PCREL_LO12_I whose auipc is in .text.a
+                builder.rela(b, &[(0, label, 24, 0)])
+            } else {
+                builder.rela(b, &[(0, var, 2, 0)])
+            };
+            (builder.build(), rela_a, rela_b)
+        };
+
+        let (elf, rela_a, rela_b) = build(false);
+        let groups = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .relocation_groups();
+        assert_eq!(groups, [[rela_a], [rela_b]]);
+
+        let (elf, rela_a, rela_b) = build(true);
+        let groups = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .relocation_groups();
+        assert_eq!(groups, [[rela_a, rela_b]]);
+    }
+
+    #[test]
+    fn decode_relocation() {
+        use goblin::elf64::reloc::Rela;
+        let relas = [
+            // R_RISCV_CALL_PLT of foo
+            Rela {
+                r_offset: 0x10,
+                r_info: (1 << 32) | 19,
+                r_addend: -4,
+            },
+            // R_RISCV_NONE without a symbol
+            Rela {
+                r_offset: 0x20,
+                r_info: 0,
+                r_addend: 0,
+            },
+            // An unknown type of a symbol out of range
+            Rela {
+                r_offset: 0x30,
+                r_info: (7 << 32) | 0xff,
+                r_addend: 0,
+            },
+        ];
+        let builder = ModuleLoadInfoBuilder::new(EM_RISCV)
+            .with_symbol("foo", goblin::elf::sym::Sym::default())
+            .with_relocations(3, &relas);
+        let load_info = builder.build();
+        assert_eq!(
+            load_info.relocation(3, 0),
+            Some(RelocationEntry {
+                offset: 0x10,
+                type_code: 19,
+                type_name: "R_RISCV_CALL_PLT".to_string(),
+                sym_idx: 1,
+                sym_name: "foo".to_string(),
+                addend: -4,
+            })
+        );
+        assert_eq!(
+            load_info.relocation(3, 1),
+            Some(RelocationEntry {
+                offset: 0x20,
+                type_code: 0,
+                type_name: "R_RISCV_NONE".to_string(),
+                sym_idx: 0,
+                sym_name: String::new(),
+                addend: 0,
+            })
+        );
+        assert_eq!(load_info.relocation(3, 2), None);
+        assert_eq!(load_info.relocation(3, 3), None);
+        assert_eq!(load_info.relocation(4, 0), None);
+    }
+
+    #[test]
+    fn max_relocations() {
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        let data = builder.section(
+            ".data",
+            goblin::elf::section_header::SHT_PROGBITS,
+            (goblin::elf::section_header::SHF_ALLOC | goblin::elf::section_header::SHF_WRITE)
+                as u64,
+            8,
+            &[0; 16],
+        );
+        let sym = builder.symbol("table", STB_GLOBAL, STT_OBJECT, 0, 0);
+        // Five R_RISCV_64 entries across two relocation sections
+        builder.rela(1, &[(0, sym, 2, 0), (8, sym, 2, 0), (0, sym, 2, 8)]);
+        builder.rela(data, &[(0, sym, 2, 0), (8, sym, 2, 0)]);
+        let elf = builder.build();
+        with_host(|host| host.symbols = alloc::vec![("table", 0x1000)]);
+        let load = |limit| {
+            ModuleLoader::<TestHelper>::new(&elf)
+                .unwrap()
+                .with_options(LoadOptions {
+                    max_relocations: Some(limit),
+                    ..Default::default()
+                })
+                .load_with_warnings()
+        };
+
+        let err = load(4).err().expect("one relocation too many");
+        assert!(matches!(
+            err,
+            ModuleErr::TooManyRelocations { count: 5, limit: 4 }
+        ));
+        load(5).unwrap();
+    }
+
+    #[test]
+    fn linked_symbol_tables() {
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        let data = builder.section(
+            ".data",
+            goblin::elf::section_header::SHT_PROGBITS,
+            (goblin::elf::section_header::SHF_ALLOC | goblin::elf::section_header::SHF_WRITE)
+                as u64,
+            8,
+            &[0; 8],
+        );
+        let sym = builder.symbol("table", STB_GLOBAL, STT_OBJECT, 0, 0);
+        let dynsym = builder.symbol_table(
+            ".dynsym",
+            goblin::elf::section_header::SHT_DYNSYM,
+            ".dynstr",
+            &[("counter", STB_GLOBAL, STT_OBJECT, 0, 0)],
+        );
+        // R_RISCV_64 of symbol 1, in .symtab for .text and in .dynsym for .data
+        builder.rela(1, &[(0, sym, 2, 0)]);
+        builder.rela_linked(data, dynsym, &[(0, 1, 2, 0)]);
+        let elf = builder.build();
+        with_host(|host| {
+            host.symbols = alloc::vec![("table", 0x1000), ("counter", 0x2000)];
+        });
+
+        let (owner, _) = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_warnings()
+            .unwrap();
+        let text = unsafe { owner.section_bytes(".text") }.unwrap();
+        assert_eq!(text[..8], 0x1000u64.to_le_bytes());
+        let data = unsafe { owner.section_bytes(".data") }.unwrap();
+        assert_eq!(data, 0x2000u64.to_le_bytes());
+    }
+
+    #[test]
+    fn missing_license() {
+        let format_version = alloc::format!("format_version={}", kmod::FORMAT_VERSION);
+        let load = |license: Option<&str>| {
+            let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+            let mut entries = alloc::vec!["name=test", "version=1.0", format_version.as_str()];
+            entries.extend(license);
+            builder.modinfo(&entries);
+            let elf = builder.build();
+            ModuleLoader::<TestHelper>::new(&elf)
+                .unwrap()
+                .load_with_warnings()
+                .unwrap()
+        };
+
+        let (owner, warnings) = load(None);
+        assert_eq!(warnings, [LoadWarning::MissingLicense]);
+        assert_eq!(owner.taints(), Taint::PROPRIETARY_MODULE);
+
+        let (owner, warnings) = load(Some("license=GPL"));
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert!(owner.taints().is_empty());
+    }
+
+    #[test]
+    fn symbol_hash_table() {
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        let alpha = builder.symbol("alpha", STB_GLOBAL, STT_FUNC, 0, 0);
+        let beta = builder.symbol("beta", STB_GLOBAL, STT_OBJECT, 0, 0);
+        // R_RISCV_64 of both at the start of .text
+        builder.rela(1, &[(0, alpha, 2, 0), (8, beta, 2, 0)]);
+        let elf = builder.build();
+        let load = |table: Option<&[(u64, &str, u64)]>| {
+            let mut loader = ModuleLoader::<TestHelper>::new(&elf).unwrap();
+            if let Some(table) = table {
+                loader = loader.with_symbol_hashes(table);
+            }
+            let (owner, _) = loader.load_with_warnings().unwrap();
+            let text = owner.pages[0].addr.as_ptr() as *const u64;
+            unsafe { [text.read_unaligned(), text.add(1).read_unaligned()] }
+        };
+
+        with_host(|host| host.symbols = alloc::vec![("alpha", 0x1000), ("beta", 0x2000)]);
+        let by_name = load(None);
+        assert_eq!(by_name, [0x1000, 0x2000]);
+        let mut table = [
+            (symbol_hash("alpha"), "alpha", 0x1000),
+            (symbol_hash("beta"), "beta", 0x2000),
+        ];
+        table.sort_by_key(|&(hash, _, _)| hash);
+        assert_eq!(load(Some(&table)), by_name);
+
+        // An entry whose hash matches but whose name does not is skipped,
+        // the symbol is resolved by name instead
+        let mut table = [
+            (symbol_hash("alpha"), "alpha", 0x1000),
+            (symbol_hash("beta"), "colliding", 0x3000),
+            (symbol_hash("beta"), "beta", 0x2000),
+        ];
+        table.sort_by_key(|&(hash, _, _)| hash);
+        assert_eq!(load(Some(&table)), by_name);
+        let table = [(symbol_hash("beta"), "colliding", 0x3000)];
+        assert_eq!(load(Some(&table)), by_name);
+    }
+
+    #[cfg(feature = "symbol-trace")]
+    #[test]
+    fn trace_shadowed_symbol() {
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        let alpha = builder.symbol("alpha", STB_GLOBAL, STT_FUNC, 0, 0);
+        // R_RISCV_64 at the start of .text
+        builder.rela(1, &[(0, alpha, 2, 0)]);
+        let elf = builder.build();
+        with_host(|host| host.symbols = alloc::vec![("alpha", 0x9000)]);
+        let table = [(symbol_hash("alpha"), "alpha", 0x1000)];
+
+        let (res, logs) = capture_logs(|| {
+            ModuleLoader::<TestHelper>::new(&elf)
+                .unwrap()
+                .with_symbol_hashes(&table)
+                .load_with_warnings()
+        });
+        let (owner, _) = res.unwrap();
+        let text = owner.pages[0].addr.as_ptr() as *const u64;
+        assert_eq!(unsafe { text.read_unaligned() }, 0x1000);
+        let traces: Vec<_> = logs
+            .iter()
+            .filter(|(level, _, msg)| *level == log::Level::Trace && msg.contains("(alpha)"))
+            .map(|(_, _, msg)| msg.as_str())
+            .collect();
+        assert_eq!(traces.len(), 2, "{:?}", traces);
+        assert!(traces[0].ends_with("resolved by symbol hash table to 0x1000"));
+        assert!(traces[1].ends_with("resolved by kernel (shadowed) to 0x9000"));
+    }
+
+    #[test]
+    fn alternatives() {
+        // mov eax, 1; int3; int3; then the replacements: ret; call target
+        let mut text = [0x90u8; 16];
+        text[..7].copy_from_slice(&[0xb8, 0x01, 0x00, 0x00, 0x00, 0xcc, 0xcc]);
+        text[7..13].copy_from_slice(&[0xc3, 0xe8, 0x00, 0x00, 0x00, 0x00]);
+        let alt = |feature: u32, instrlen: u8, replacementlen: u8| {
+            let mut entry = [0u8; 14];
+            entry[8..12].copy_from_slice(&feature.to_le_bytes());
+            entry[12] = instrlen;
+            entry[13] = replacementlen;
+            entry
+        };
+        let mut builder = ElfBuilder::module(EM_X86_64, init_ok);
+        builder.section_mut(1).data = text.to_vec();
+        let alt_section = builder.section(
+            ".altinstructions",
+            goblin::elf::section_header::SHT_PROGBITS,
+            goblin::elf::section_header::SHF_ALLOC as u64,
+            1,
+            &[alt(1, 5, 5), alt(2, 2, 1)].concat(),
+        );
+        let text_sym = builder.symbol("alt_text", STB_GLOBAL, STT_FUNC, 1, 0);
+        let target = builder.symbol("alt_target", STB_GLOBAL, STT_FUNC, 1, 14);
+        // R_X86_64_PC32 of the call and of the offsets of both entries
+        builder.rela(1, &[(9, target, 2, -4)]);
+        builder.rela(
+            alt_section,
+            &[
+                (0, text_sym, 2, 0),
+                (4, text_sym, 2, 8),
+                (14, text_sym, 2, 5),
+                (18, text_sym, 2, 7),
+            ],
+        );
+        let elf = builder.build();
+        let load = |features: &[u32]| {
+            with_host(|host| host.features = features.to_vec());
+            let (owner, _) = ModuleLoader::<TestHelper>::new(&elf)
+                .unwrap()
+                .load_with_warnings()
+                .unwrap();
+            let text = owner.pages[0].addr.as_ptr();
+            unsafe { core::slice::from_raw_parts(text, 8) }.to_vec()
+        };
+
+        assert_eq!(load(&[]), [0xb8, 0x01, 0x00, 0x00, 0x00, 0xcc, 0xcc, 0xc3]);
+        // The call still reaches its target from the original's address
+        assert_eq!(load(&[1]), [0xe8, 0x09, 0x00, 0x00, 0x00, 0xcc, 0xcc, 0xc3]);
+        assert_eq!(
+            load(&[1, 2]),
+            [0xe8, 0x09, 0x00, 0x00, 0x00, 0xc3, 0x90, 0xc3]
+        );
+
+        // An original pointing outside of the module is rejected
+        let mut builder = ElfBuilder::module(EM_X86_64, init_ok);
+        let mut entry = alt(1, 5, 5);
+        entry[..4].copy_from_slice(&0x10_0000i32.to_le_bytes());
+        builder.section(
+            ".altinstructions",
+            goblin::elf::section_header::SHT_PROGBITS,
+            goblin::elf::section_header::SHF_ALLOC as u64,
+            1,
+            &entry,
+        );
+        let elf = builder.build();
+        with_host(|host| host.features = alloc::vec![1]);
+        let err = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_warnings()
+            .err()
+            .expect("original out of the module");
+        assert!(matches!(err, ModuleErr::InvalidElf));
+    }
+
+    #[test]
+    fn unresolved_symbol() {
+        with_host(|host| host.symbols.clear());
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        builder.symbol("optional", STB_WEAK, STT_FUNC, 0, 0);
+        let elf = builder.build();
+        let (_owner, warnings) = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_warnings()
+            .unwrap();
+        assert_eq!(
+            warnings,
+            [LoadWarning::UnresolvedWeakSymbol("optional".to_string())]
+        );
+
+        builder.symbol("required", STB_GLOBAL, STT_FUNC, 0, 0);
+        let elf = builder.build();
+        let err = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_warnings()
+            .err()
+            .expect("required is not defined");
+        assert!(matches!(err, ModuleErr::UnresolvedSymbol(ref name) if name == "required"));
+    }
+
+    #[test]
+    fn section_alignment() {
+        use goblin::elf::section_header::{SHF_ALLOC, SHF_WRITE, SHT_PROGBITS};
+        let flags = (SHF_ALLOC | SHF_WRITE) as u64;
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        for (name, align) in [
+            (".data.a", 0x10000),
+            (".data.b", 0x2000),
+            (".data.c", 16),
+            (".data.d", 0),
+        ] {
+            builder.section(name, SHT_PROGBITS, flags, align, &[0xaa; 24]);
+        }
+        let elf = builder.build();
+        let (owner, _) = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_warnings()
+            .unwrap();
+        let ranges: Vec<_> = owner
+            .pages
+            .iter()
+            .map(|page| {
+                let start = page.addr.as_ptr() as u64;
+                (page.name.as_str(), start..start + page.size as u64)
+            })
+            .collect();
+        for (name, align) in [(".data.a", 0x10000), (".data.b", 0x2000), (".data.c", 16)] {
+            let (_, range) = ranges.iter().find(|(page, _)| *page == name).unwrap();
+            assert!(
+                range.start.is_multiple_of(align),
+                "{} at {:#x}",
+                name,
+                range.start
+            );
+        }
+        for (i, (name, a)) in ranges.iter().enumerate() {
+            for (other, b) in &ranges[i + 1..] {
+                assert!(
+                    a.end <= b.start || b.end <= a.start,
+                    "{} overlaps {}",
+                    name,
+                    other
+                );
+            }
+        }
+
+        // A host only aligning to pages is caught
+        with_host(|host| host.ignore_align = true);
+        let err = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_warnings()
+            .err()
+            .expect("misaligned allocation");
+        assert!(matches!(err, ModuleErr::MemoryAllocationFailed));
+        with_host(|host| host.ignore_align = false);
+
+        builder.section_mut(1).align = 3;
+        let elf = builder.build();
+        let err = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_warnings()
+            .err()
+            .expect("alignment not a power of two");
+        assert!(matches!(err, ModuleErr::InvalidElf));
+    }
+
+    #[test]
+    fn symbol_versions() {
+        let mut versions = [0u8; 64];
+        versions[..8].copy_from_slice(&0x1234_5678u64.to_le_bytes());
+        versions[8..8 + "printk".len()].copy_from_slice(b"printk");
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        builder.section(
+            "__versions",
+            goblin::elf::section_header::SHT_PROGBITS,
+            0,
+            8,
+            &versions,
+        );
+        builder.symbol("printk", STB_GLOBAL, STT_FUNC, 0, 0);
+        let elf = builder.build();
+        let load = |options: LoadOptions| {
+            ModuleLoader::<TestHelper>::new(&elf)
+                .unwrap()
+                .with_options(options)
+                .load_with_warnings()
+        };
+        with_host(|host| host.symbols = alloc::vec![("printk", 0x1000)]);
+
+        with_host(|host| host.crcs = alloc::vec![("printk", 0x1234_5678)]);
+        let (owner, warnings) = load(LoadOptions::default()).unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert!(owner.taints().is_empty());
+
+        with_host(|host| host.crcs = alloc::vec![("printk", 0x8765_4321)]);
+        let err = load(LoadOptions::default()).err().expect("CRC mismatch");
+        assert!(matches!(
+            err,
+            ModuleErr::VersionMismatch { ref symbol, expected: 0x1234_5678, found: 0x8765_4321 }
+                if symbol == "printk"
+        ));
+        let (owner, warnings) = load(LoadOptions {
+            force_modversions: true,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(warnings, [LoadWarning::ForcedVersion("printk".to_string())]);
+        assert!(owner.taints().contains(Taint::FORCED_MODULE));
+    }
+
+    #[test]
+    fn forced_vermagic() {
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        let format_version = alloc::format!("format_version={}", kmod::FORMAT_VERSION);
+        builder.modinfo(&[
+            "name=test",
+            "version=1.0",
+            "license=GPL",
+            &format_version,
+            "vermagic=6.5.0 SMP mod_unload ",
+        ]);
+        let elf = builder.build();
+        let load = |options: LoadOptions| {
+            ModuleLoader::<TestHelper>::new(&elf)
+                .unwrap()
+                .with_options(options)
+                .load_with_warnings()
+        };
+
+        with_host(|host| host.vermagic = Some("6.5.0 SMP mod_unload "));
+        let (owner, warnings) = load(LoadOptions::default()).unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert!(owner.taints().is_empty());
+
+        with_host(|host| host.vermagic = Some("6.6.0 SMP mod_unload "));
+        let err = load(LoadOptions::default())
+            .err()
+            .expect("vermagic mismatch");
+        assert!(matches!(
+            err,
+            ModuleErr::VermagicMismatch { ref expected, found: Some(ref found) }
+                if expected == "6.6.0 SMP mod_unload " && found == "6.5.0 SMP mod_unload "
+        ));
+        let (owner, warnings) = load(LoadOptions {
+            force_vermagic: true,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            warnings,
+            [LoadWarning::ForcedVermagic(Some(
+                "6.5.0 SMP mod_unload ".to_string()
+            ))]
+        );
+        assert!(owner.taints().contains(Taint::FORCED_MODULE));
+    }
+
+    #[test]
+    fn bad_rela_entsize() {
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        let sym = builder.symbol("counter", STB_GLOBAL, STT_OBJECT, 1, 0);
+        // R_RISCV_64 at the start of .text
+        let rela = builder.rela(1, &[(0, sym, 2, 0)]);
+        for entsize in [0, 16] {
+            builder.section_mut(rela).entsize = entsize;
+            let elf = builder.build();
+            // Skipped when printed, rejected when loaded
+            crate::ElfParser::new(&elf).unwrap().print_relocations();
+            let err = ModuleLoader::<TestHelper>::new(&elf)
+                .unwrap()
+                .load_with_warnings()
+                .err()
+                .expect("bad sh_entsize");
+            assert!(
+                matches!(err, ModuleErr::BadRelaEntsize { section, entsize: found }
+                    if section == rela && found == entsize),
+                "{}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn load_into_caller_memory() {
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        let sym = builder.symbol("counter", STB_GLOBAL, STT_OBJECT, 0, 0);
+        // R_RISCV_64 at the start of .text
+        builder.rela(1, &[(0, sym, 2, 0)]);
+        let elf = builder.build();
+        let symbols = [("counter", 0x1234_5678)];
+        let region = |pages| Box::leak(alloc::vec![0u8; pages * PAGE_SIZE].into_boxed_slice());
+
+        // .text and .gnu.linkonce.this_module, one page each, and the padding
+        // to align the first one
+        let memory = region(3);
+        let range = memory.as_ptr_range();
+        with_host(|host| host.allocations.clear());
+        let owner = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_symbols_into(&symbols, memory)
+            .unwrap();
+        assert!(with_host(|host| host.allocations.is_empty()));
+        let text = owner.pages[0].addr.as_ptr();
+        assert!(range.contains(&text));
+        assert_eq!(
+            unsafe { (text as *const u64).read_unaligned() },
+            0x1234_5678
+        );
+
+        let err = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_symbols_into(&symbols, region(1))
+            .err()
+            .expect("region too small");
+        assert!(matches!(err, ModuleErr::MemoryAllocationFailed));
+        let err = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_symbols_into(&[], region(3))
+            .err()
+            .expect("symbol missing from the map");
+        assert!(matches!(err, ModuleErr::UndefinedSymbol));
+    }
+
+    #[test]
+    fn kernel_got_slot() {
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        let text = &mut builder.section_mut(1).data;
+        // auipc a0, 0; ld a0, 0(a0)
+        text[..4].copy_from_slice(&0x00000517u32.to_le_bytes());
+        text[4..8].copy_from_slice(&0x00053503u32.to_le_bytes());
+        let sym = builder.symbol("counter", STB_GLOBAL, STT_OBJECT, 0, 0);
+        let label = builder.symbol(".L0", STB_LOCAL, STT_NOTYPE, 1, 0);
+        // R_RISCV_GOT_HI20 of the auipc, R_RISCV_PCREL_LO12_I of the ld
+        builder.rela(1, &[(0, sym, 20, 0), (4, label, 24, 0)]);
+        let elf = builder.build();
+
+        // The kernel's slot must be in reach of the auipc, so carve the
+        // sections out of memory that has it in its last page
+        let memory = Box::leak(alloc::vec![0u8; 4 * PAGE_SIZE].into_boxed_slice());
+        let slot = memory.as_ptr_range().end as u64 - 8;
+        with_host(|host| host.got = alloc::vec![("counter", slot)]);
+        let owner = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_symbols_into(&[("counter", 0x1234_5678)], memory)
+            .unwrap();
+
+        let text = owner.pages[0].addr.as_ptr();
+        let insn = |i: usize| unsafe { (text.add(i) as *const u32).read_unaligned() };
+        let hi = (insn(0) & 0xfffff000) as i32 as i64;
+        let lo = (insn(4) as i32 >> 20) as i64;
+        assert_eq!((text as i64 + hi + lo) as u64, slot);
+        // No GOT entry of the module's own
+        assert!(owner.pages.iter().all(|page| page.stubs_used.get() == 0));
+    }
+
+    #[test]
+    fn relro_pointer() {
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        let relro = builder.section(
+            ".data.rel.ro",
+            goblin::elf::section_header::SHT_PROGBITS,
+            (goblin::elf::section_header::SHF_ALLOC | goblin::elf::section_header::SHF_WRITE)
+                as u64,
+            8,
+            &[0; 8],
+        );
+        let sym = builder.symbol("table", STB_GLOBAL, STT_OBJECT, 0, 0);
+        // R_RISCV_64 of the pointer
+        builder.rela(relro, &[(0, sym, 2, 0)]);
+        let elf = builder.build();
+        with_host(|host| host.symbols = alloc::vec![("table", 0x1234_5678)]);
+
+        let (owner, _) = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_warnings()
+            .unwrap();
+        let bytes = unsafe { owner.section_bytes(".data.rel.ro") }.unwrap();
+        assert_eq!(bytes, 0x1234_5678u64.to_le_bytes());
+        let page = owner
+            .pages
+            .iter()
+            .find(|page| page.name == ".data.rel.ro")
+            .unwrap();
+        assert_eq!(page.perms, SectionPerm::READ);
+        let addr = page.addr.as_ptr() as usize;
+        let last = with_host(|host| {
+            host.memory_attributes
+                .iter()
+                .rev()
+                .find(|&&(vaddr, ..)| vaddr == addr)
+                .copied()
+        });
+        // Neither executable nor writable
+        assert_eq!(last, Some((addr, false, false)));
+    }
+
+    #[test]
+    fn patch_function() {
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        let func = builder.symbol("func", STB_GLOBAL, STT_FUNC, 1, 4);
+        builder.symbol_size(func, 8);
+        let elf = builder.build();
+        let (mut owner, _) = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_warnings()
+            .unwrap();
+
+        // addi a0, zero, 1; ret
+        let code = [0x13, 0x05, 0x10, 0x00, 0x67, 0x80, 0x00, 0x00];
+        unsafe { owner.patch_function("func", &code) }.unwrap();
+        let text = unsafe { owner.section_bytes(".text") }.unwrap();
+        assert_eq!(text[4..12], code);
+        assert_eq!(text[..4], [0; 4]);
+        assert_eq!(text[12..], [0; 4]);
+
+        let err = unsafe { owner.patch_function("func", &[0; 12]) }.expect_err("too large");
+        assert!(matches!(
+            err,
+            ModuleErr::PatchTooLarge {
+                size: 12,
+                capacity: 8
+            }
+        ));
+        let err = unsafe { owner.patch_function("missing", &code) }.expect_err("no such function");
+        assert!(matches!(err, ModuleErr::UndefinedSymbol));
+    }
+
+    /// An absolute `lui`/`addi` pair only reaches the low 2GB, so load the
+    /// module into memory mapped there
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn linked_image_self_reference() {
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        builder.elf_type(goblin::elf::header::ET_EXEC);
+        let text = builder.section_mut(1);
+        text.addr = 0x40_0000;
+        // lui a0, 0; addi a0, a0, 0
+        text.data[..4].copy_from_slice(&0x00000537u32.to_le_bytes());
+        text.data[4..8].copy_from_slice(&0x00050513u32.to_le_bytes());
+        // At its link address, 8 bytes into .text
+        let var = builder.symbol("var", STB_LOCAL, STT_OBJECT, 1, 0x40_0008);
+        // R_RISCV_HI20 of the lui, R_RISCV_LO12_I of the addi
+        builder.rela(1, &[(0, var, 26, 0), (4, var, 27, 0)]);
+        let elf = builder.build();
+
+        let size = 4 * PAGE_SIZE;
+        let low = 0x1000_0000usize;
+        let addr = unsafe {
+            libc::mmap(
+                low as *mut libc::c_void,
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_FIXED_NOREPLACE,
+                -1,
+                0,
+            )
+        };
+        assert_eq!(addr as usize, low, "failed to map memory at {:#x}", low);
+        let memory = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, size) };
+        let owner = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_symbols_into(&[], memory)
+            .unwrap();
+
+        let text = owner.pages[0].addr.as_ptr();
+        assert_ne!(text as u64, 0x40_0000);
+        let insn = |i: usize| unsafe { (text.add(i) as *const u32).read_unaligned() };
+        let hi = (insn(0) & 0xfffff000) as i32 as i64;
+        let lo = (insn(4) as i32 >> 20) as i64;
+        assert_eq!((hi + lo) as u64, text as u64 + 8);
+    }
+
+    #[test]
+    fn unwind_succeeded_inits() {
+        recorded!(init init_a, 0);
+        recorded!(init init_b, 0);
+        recorded!(init init_c, -12);
+        recorded!(exit exit_a);
+        recorded!(exit exit_b);
+        recorded!(exit exit_c);
+        recorded!(exit exit_d);
+
+        let elf = ElfBuilder::module(EM_RISCV, init_ok).build();
+        let (mut owner, _) = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_warnings()
+            .unwrap();
+        // The second init function of priority 2 fails
+        owner.init_calls = alloc::vec![(1, init_a as InitFn), (2, init_b), (2, init_c)];
+        owner.exit_calls =
+            alloc::vec![(1, exit_a as ExitFn), (2, exit_b), (2, exit_c), (3, exit_d)];
+        with_host(|host| host.calls.clear());
+        assert_eq!(owner.call_init().unwrap(), -12);
+        assert_eq!(
+            with_host(|host| core::mem::take(&mut host.calls)),
+            ["init_a", "init_b", "init_c", "exit_b", "exit_a"]
+        );
+        // Nothing is left to undo
+        owner.call_exit();
+        assert!(with_host(|host| host.calls.is_empty()));
+    }
+
+    #[test]
+    fn drop_live_module() {
+        recorded!(exit exit_a);
+
+        let elf = ElfBuilder::module(EM_RISCV, init_ok).build();
+        let live_owner = || {
+            let (mut owner, _) = ModuleLoader::<TestHelper>::new(&elf)
+                .unwrap()
+                .load_with_warnings()
+                .unwrap();
+            owner.exit_calls = alloc::vec![(1, exit_a as ExitFn)];
+            assert_eq!(owner.call_init().unwrap(), 0);
+            owner
+        };
+
+        let owner = live_owner();
+        let ((), logs) = capture_logs(|| drop(owner));
+        assert_eq!(logs.len(), 1, "{:?}", logs);
+        assert_eq!(logs[0].0, log::Level::Warn);
+        assert!(
+            logs[0]
+                .2
+                .contains("dropped without calling its exit function")
+        );
+
+        let owner = live_owner();
+        with_host(|host| host.calls.clear());
+        let mut session = LoadSession::new();
+        let (res, logs) = capture_logs(|| owner.unload(&mut session));
+        assert!(res.is_ok());
+        assert_eq!(with_host(|host| host.calls.clone()), ["exit_a"]);
+        assert!(logs.is_empty(), "{:?}", logs);
+    }
+
+    #[test]
+    fn init_result() {
+        recorded!(init init_nonzero, 7);
+
+        let elf = ElfBuilder::module(EM_RISCV, init_nonzero).build();
+        let (mut owner, _) = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_warnings()
+            .unwrap();
+        assert_eq!(owner.init_result(), None);
+        assert_eq!(owner.call_init().unwrap(), 7);
+        assert_eq!(owner.init_result(), Some(7));
+        // Called once only, the result stays
+        assert!(matches!(
+            owner.call_init(),
+            Err(ModuleErr::InvalidOperation)
+        ));
+        assert_eq!(owner.init_result(), Some(7));
+    }
+
+    #[test]
+    fn init_override() {
+        recorded!(init init_original, 5);
+        fn wrap(original: Option<InitFn>) -> i32 {
+            with_host(|host| host.calls.push("wrap_enter"));
+            let result = unsafe { original.expect("no original init")() };
+            with_host(|host| host.calls.push("wrap_leave"));
+            result + 1
+        }
+
+        let elf = ElfBuilder::module(EM_RISCV, init_original).build();
+        let (mut owner, _) = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .with_options(LoadOptions {
+                init_override: Some(wrap),
+                ..Default::default()
+            })
+            .load_with_warnings()
+            .unwrap();
+        assert_eq!(owner.call_init().unwrap(), 6);
+        assert_eq!(owner.init_result(), Some(6));
+        assert_eq!(
+            with_host(|host| core::mem::take(&mut host.calls)),
+            ["wrap_enter", "init_original", "wrap_leave"]
+        );
+    }
+
+    #[test]
+    fn set_param() {
+        use goblin::elf::section_header::{SHF_ALLOC, SHF_WRITE, SHT_PROGBITS};
+        use kmod::ModuleParamType;
+
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        let format_version = alloc::format!("format_version={}", kmod::FORMAT_VERSION);
+        builder.modinfo(&[
+            "name=test",
+            "version=1.0",
+            "license=GPL",
+            &format_version,
+            "parmtype=count:int",
+            "parmtype=msg:string",
+        ]);
+        let rw = (SHF_ALLOC | SHF_WRITE) as u64;
+        // level: i32 at 0, depth: u32 at 4, debug: bool at 8, count: int at
+        // 12, the kparam_string of msg at 16 and its 8 byte buffer at 32
+        let mut data = [0u8; 40];
+        data[16..20].copy_from_slice(&8u32.to_le_bytes());
+        let data = builder.section(".data", SHT_PROGBITS, rw, 8, &data);
+        let rodata = builder.section(
+            ".rodata",
+            SHT_PROGBITS,
+            SHF_ALLOC as u64,
+            1,
+            b"count\0msg\0",
+        );
+
+        let mut modparam = Vec::new();
+        for (name, ty) in [
+            ("level", ModuleParamType::I32),
+            ("depth", ModuleParamType::U32),
+            ("debug", ModuleParamType::Bool),
+        ] {
+            let param = kmod::ModuleParam::new(name, ty, 0, core::ptr::null_mut());
+            modparam.extend_from_slice(unsafe {
+                core::slice::from_raw_parts(
+                    &param as *const kmod::ModuleParam as *const u8,
+                    core::mem::size_of::<kmod::ModuleParam>(),
+                )
+            });
+        }
+        let modparam = builder.section(".modparam", SHT_PROGBITS, rw, 8, &modparam);
+        let kernel_param = core::mem::size_of::<KernelParam>() as u64;
+        let param = builder.section(
+            "__param",
+            SHT_PROGBITS,
+            rw,
+            8,
+            &alloc::vec![0; 2 * kernel_param as usize],
+        );
+
+        let data_sym = builder.symbol("data", STB_LOCAL, STT_OBJECT, data, 0);
+        let rodata_sym = builder.symbol("rodata", STB_LOCAL, STT_OBJECT, rodata, 0);
+        // R_RISCV_64 of the value pointers of the descriptors
+        let value = core::mem::size_of::<kmod::ModuleParam>() as u64 - 8;
+        let stride = core::mem::size_of::<kmod::ModuleParam>() as u64;
+        builder.rela(
+            modparam,
+            &[
+                (value, data_sym, 2, 0),
+                (stride + value, data_sym, 2, 4),
+                (2 * stride + value, data_sym, 2, 8),
+            ],
+        );
+        // The name and argument of each kernel_param, and the buffer of msg
+        let arg = kernel_param - 8;
+        builder.rela(
+            param,
+            &[
+                (0, rodata_sym, 2, 0),
+                (arg, data_sym, 2, 12),
+                (kernel_param, rodata_sym, 2, 6),
+                (kernel_param + arg, data_sym, 2, 16),
+            ],
+        );
+        builder.rela(data, &[(24, data_sym, 2, 32)]);
+        let elf = builder.build();
+
+        let (mut owner, _) = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_warnings()
+            .unwrap();
+        let mut set = |name: &str, value: &[u8]| {
+            unsafe { owner.set_param(name, value) }?;
+            Ok::<_, ModuleErr>(unsafe { owner.section_bytes(".data") }.unwrap().to_vec())
+        };
+
+        assert_eq!(
+            set("level", &(-5i32).to_ne_bytes()).unwrap()[..4],
+            (-5i32).to_ne_bytes()
+        );
+        assert_eq!(
+            set("depth", &0xdead_beefu32.to_ne_bytes()).unwrap()[4..8],
+            0xdead_beefu32.to_ne_bytes()
+        );
+        assert_eq!(set("debug", &[1]).unwrap()[8], 1);
+        assert!(matches!(
+            set("debug", &[2]),
+            Err(ModuleErr::InvalidOperation)
+        ));
+        assert!(matches!(
+            set("level", &[0; 2]),
+            Err(ModuleErr::ParamTypeMismatch { ref name, expected: 4, got: 2 }) if name == "level"
+        ));
+        assert_eq!(
+            set("count", &7i32.to_ne_bytes()).unwrap()[12..16],
+            7i32.to_ne_bytes()
+        );
+
+        // At most maxlen - 1 bytes, NUL terminated
+        assert_eq!(set("msg", b"0123456").unwrap()[32..], *b"0123456\0");
+        assert_eq!(set("msg", b"hi").unwrap()[32..35], *b"hi\0");
+        assert!(matches!(
+            set("msg", b"01234567"),
+            Err(ModuleErr::ParamTypeMismatch {
+                expected: 7,
+                got: 8,
+                ..
+            })
+        ));
+        assert!(matches!(
+            set("missing", &[0; 4]),
+            Err(ModuleErr::UndefinedSymbol)
+        ));
+    }
+
+    #[test]
+    fn tls_without_host_block() {
+        use goblin::elf::section_header::{SHF_ALLOC, SHF_TLS, SHF_WRITE, SHT_PROGBITS};
+
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        let text = &mut builder.section_mut(1).data;
+        // lui a0, 0; addi a0, a0, 0
+        text[..4].copy_from_slice(&0x00000537u32.to_le_bytes());
+        text[4..8].copy_from_slice(&0x00050513u32.to_le_bytes());
+        let tdata = builder.section(
+            ".tdata",
+            SHT_PROGBITS,
+            (SHF_ALLOC | SHF_WRITE | SHF_TLS) as u64,
+            8,
+            &[0; 8],
+        );
+        let var = builder.symbol("var", STB_LOCAL, goblin::elf::sym::STT_TLS, tdata, 4);
+        // R_RISCV_TPREL_HI20 of the lui, R_RISCV_TPREL_LO12_I of the addi
+        builder.rela(1, &[(0, var, 29, 0), (4, var, 30, 0)]);
+        let elf = builder.build();
+
+        with_host(|host| host.tls_offset = None);
+        let err = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_warnings()
+            .err()
+            .expect("the host has no TLS block");
+        assert!(
+            matches!(
+                err,
+                ModuleErr::PartialRelocation { applied: 0, ref err }
+                    if matches!(
+                        **err,
+                        ModuleErr::RelocationEntry { ref err, .. }
+                            if matches!(**err, ModuleErr::TlsNotSupported)
+                    )
+            ),
+            "{}",
+            err
+        );
+
+        with_host(|host| host.tls_offset = Some(0x100));
+        let (owner, _) = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_warnings()
+            .unwrap();
+        let text = unsafe { owner.section_bytes(".text") }.unwrap();
+        // addi a0, a0, 0x104
+        assert_eq!(text[4..8], 0x10450513u32.to_le_bytes());
+    }
+
+    #[test]
+    fn userdata() {
+        let elf = ElfBuilder::module(EM_RISCV, init_ok).build();
+        let (mut owner, _) = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .with_userdata("dev0".into())
+            .load_with_warnings()
+            .unwrap();
+        assert_eq!(owner.userdata().map(String::as_str), Some("dev0"));
+        owner.userdata_mut().unwrap().push_str(".1");
+        assert_eq!(owner.set_userdata("dev1".into()).as_deref(), Some("dev0.1"));
+        assert_eq!(owner.userdata().map(String::as_str), Some("dev1"));
+
+        let (mut owner, _) = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_warnings()
+            .unwrap();
+        assert!(owner.userdata().is_none());
+        assert!(owner.userdata_mut().is_none());
+        assert_eq!(owner.set_userdata("dev2".into()), None);
+        assert_eq!(owner.userdata().map(String::as_str), Some("dev2"));
+    }
+
+    #[test]
+    fn truncated_init_calls() {
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        let descriptor = core::mem::size_of::<InitCall>();
+        builder.section(
+            ".init_calls",
+            goblin::elf::section_header::SHT_PROGBITS,
+            (goblin::elf::section_header::SHF_ALLOC | goblin::elf::section_header::SHF_WRITE)
+                as u64,
+            8,
+            &alloc::vec![0; descriptor + 4],
+        );
+        let elf = builder.build();
+        let err = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_warnings()
+            .err()
+            .expect("partial descriptor");
+        assert!(matches!(err, ModuleErr::InvalidElf), "{}", err);
+    }
+
+    #[test]
+    fn no_helper_allocation_fails() {
+        let elf = ElfBuilder::module(EM_RISCV, init_ok).build();
+        let err = ModuleLoader::<NoHelper>::new(&elf)
+            .unwrap()
+            .load_with_warnings()
+            .err()
+            .expect("NoHelper does not allocate");
+        assert!(matches!(err, ModuleErr::MemoryAllocationFailed), "{}", err);
+    }
+
+    #[test]
+    fn section_placer() {
+        use goblin::elf::section_header::{SHF_ALLOC, SHF_WRITE, SHT_PROGBITS};
+        fn placer(name: &str, _sh_flags: u64, _size: usize) -> MemoryPool {
+            match name {
+                ".text" => MemoryPool(1),
+                ".data" => MemoryPool(2),
+                _ => MemoryPool::default(),
+            }
+        }
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        builder.section(
+            ".data",
+            SHT_PROGBITS,
+            (SHF_ALLOC | SHF_WRITE) as u64,
+            8,
+            &[0; 8],
+        );
+        let elf = builder.build();
+        with_host(|host| host.allocations.clear());
+        let (owner, _) = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .with_section_placer(placer)
+            .load_with_warnings()
+            .unwrap();
+        let pools: Vec<_> = owner
+            .pages
+            .iter()
+            .map(|page| (page.name.as_str(), page.pool))
+            .collect();
+        assert_eq!(
+            pools,
+            [
+                (".text", MemoryPool(1)),
+                (".gnu.linkonce.this_module", MemoryPool(0)),
+                (".data", MemoryPool(2)),
+            ]
+        );
+        // The helper is asked for the same pools, in section order
+        let hints: Vec<_> =
+            with_host(|host| host.allocations.iter().map(|(_, hint)| hint.pool).collect());
+        assert_eq!(hints, [MemoryPool(1), MemoryPool(0), MemoryPool(2)]);
+    }
+
+    #[test]
+    fn alloc_note_section() {
+        use goblin::elf::section_header::{SHF_ALLOC, SHT_NOTE};
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        let note = builder.section(
+            ".note.gnu.property",
+            SHT_NOTE,
+            SHF_ALLOC as u64,
+            8,
+            &[0; 32],
+        );
+        let sym = builder.symbol("counter", STB_GLOBAL, STT_OBJECT, 1, 0);
+        // A relocation type no handler knows, it fails if applied
+        builder.rela(note, &[(0, sym, 0xff, 0)]);
+        let elf = builder.build();
+        with_host(|host| host.allocations.clear());
+        let (owner, warnings) = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_warnings()
+            .unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        let names: Vec<_> = owner.pages.iter().map(|page| page.name.as_str()).collect();
+        assert_eq!(names, [".text", ".gnu.linkonce.this_module"]);
+        assert_eq!(with_host(|host| host.allocations.len()), 2);
+    }
+
+    #[test]
+    fn duplicate_symbol_definition() {
+        // foo defined at the start of .text, then again with `bind`
+        let module = |bind| {
+            let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+            builder.symbol("foo", STB_GLOBAL, STT_FUNC, 1, 0);
+            builder.symbol("foo", bind, STT_FUNC, 1, 8);
+            builder.build()
+        };
+        let elf = module(STB_GLOBAL);
+        let err = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_warnings()
+            .err()
+            .expect("foo is defined twice");
+        assert!(
+            matches!(err, ModuleErr::DuplicateSymbolDefinition(ref name) if name == "foo"),
+            "{}",
+            err
+        );
+
+        // A weak definition next to the strong one is fine
+        let elf = module(STB_WEAK);
+        assert!(
+            ModuleLoader::<TestHelper>::new(&elf)
+                .unwrap()
+                .load_with_warnings()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn relocated_section_bytes() {
+        let mut builder = ElfBuilder::module(EM_RISCV, init_ok);
+        // lui a0, 0
+        builder.section_mut(1).data[..4].copy_from_slice(&0x00000537u32.to_le_bytes());
+        let sym = builder.symbol("table", STB_GLOBAL, STT_OBJECT, 0, 0);
+        // R_RISCV_HI20 of the lui
+        builder.rela(1, &[(0, sym, 26, 0)]);
+        let elf = builder.build();
+        with_host(|host| host.symbols = alloc::vec![("table", 0x1234_5000)]);
+        let (owner, _) = ModuleLoader::<TestHelper>::new(&elf)
+            .unwrap()
+            .load_with_warnings()
+            .unwrap();
+        let text = unsafe { owner.section_bytes(".text") }.unwrap();
+        assert_eq!(text.len(), 16);
+        // lui a0, 0x12345
+        assert_eq!(text[..4], 0x12345537u32.to_le_bytes());
+        assert!(unsafe { owner.section_bytes(".missing") }.is_none());
+    }
+}
@@ -1,8 +1,15 @@
 use core::fmt::Debug;
 
-use alloc::{string::String, vec::Vec};
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::ffi::CStr;
 
-#[derive(Clone)]
+use crate::{ModuleErr, Result};
+
+#[derive(Clone, Default)]
 pub struct ModuleInfo {
     kv: Vec<(String, String)>,
 }
@@ -29,6 +36,69 @@ impl ModuleInfo {
         self.kv.push((key, value));
     }
 
+    /// Parse the contents of a `.modinfo` section.
+    ///
+    /// The format is `key=value\0key=value\0...`. An image may carry the
+    /// metadata of several modules, each one starting with its `name` key,
+    /// so one [`ModuleInfo`] is returned per declared module.
+    pub fn parse_all(modinfo_data: &[u8]) -> Result<Vec<ModuleInfo>> {
+        let mut infos: Vec<ModuleInfo> = Vec::new();
+        for entry in modinfo_entries(modinfo_data) {
+            let (key, value) = entry?;
+            let (key, value) = (key.to_string(), value.to_string());
+            match infos.last_mut() {
+                Some(info) if key != "name" || info.get("name").is_none() => {
+                    info.add_kv(key, value)
+                }
+                _ => {
+                    let mut info = ModuleInfo::new();
+                    info.add_kv(key, value);
+                    infos.push(info);
+                }
+            }
+        }
+        Ok(infos)
+    }
+
+    /// Parse the `.modinfo` section of a single module, e.g. read out of a
+    /// `.ko` file by host tooling.
+    ///
+    /// `None` if the section is malformed, declares more than one module or
+    /// lacks a name or version, see [`ModuleInfo::validate`].
+    #[cfg(feature = "inspect")]
+    pub fn from_bytes(modinfo_data: &[u8]) -> Option<ModuleInfo> {
+        let mut infos = Self::parse_all(modinfo_data).ok()?;
+        if infos.len() != 1 {
+            return None;
+        }
+        let info = infos.pop()?;
+        info.validate().ok()?;
+        Some(info)
+    }
+
+    /// Encode the entries back into the `key=value\0` format of a `.modinfo`
+    /// section, the inverse of [`ModuleInfo::from_bytes`]
+    #[cfg(feature = "inspect")]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for (k, v) in &self.kv {
+            bytes.extend_from_slice(k.as_bytes());
+            bytes.push(b'=');
+            bytes.extend_from_slice(v.as_bytes());
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    /// Get the declared type of the parameter `name`, from its
+    /// `parmtype=name:type` entry
+    pub fn param_type(&self, name: &str) -> Option<&str> {
+        self.kv
+            .iter()
+            .filter(|(k, _)| k == "parmtype")
+            .find_map(|(_, v)| v.strip_prefix(name)?.strip_prefix(':'))
+    }
+
     pub fn get(&self, key: &str) -> Option<&str> {
         for (k, v) in &self.kv {
             if k == key {
@@ -37,4 +107,170 @@ impl ModuleInfo {
         }
         None
     }
+
+    /// Check the metadata the loader relies on is present, i.e. a non-empty
+    /// name and version, and the module was built for the format version of
+    /// this loader. The values are valid UTF-8 once parsed.
+    pub fn validate(&self) -> Result<()> {
+        if self.name().is_none_or(str::is_empty) {
+            log::error!("Module info has no name: {:?}", self);
+            return Err(ModuleErr::InvalidModuleInfo("missing module name"));
+        }
+        if self.version().is_none_or(str::is_empty) {
+            log::error!("Module info has no version: {:?}", self);
+            return Err(ModuleErr::InvalidModuleInfo("missing module version"));
+        }
+        let Some(found) = self.format_version() else {
+            log::error!("Module info has no format version: {:?}", self);
+            return Err(ModuleErr::InvalidModuleInfo("missing format version"));
+        };
+        let expected = crate::supported_format_version();
+        if found.parse::<u16>() != Ok(expected) {
+            log::error!(
+                "Module is built for format version {}, the loader supports {}",
+                found,
+                expected
+            );
+            return Err(ModuleErr::FormatVersionMismatch {
+                expected,
+                found: found.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.get("name")
+    }
+
+    pub fn version(&self) -> Option<&str> {
+        self.get("version")
+    }
+
+    pub fn license(&self) -> Option<&str> {
+        self.get("license")
+    }
+
+    pub fn author(&self) -> Option<&str> {
+        self.get("author")
+    }
+
+    /// Get the [`kmod::FORMAT_VERSION`] the module is built for
+    pub fn format_version(&self) -> Option<&str> {
+        self.get("format_version")
+    }
+
+    pub fn vermagic(&self) -> Option<&str> {
+        self.get("vermagic")
+    }
+
+    /// Get the symbol namespaces the module imports
+    pub fn imported_namespaces(&self) -> impl Iterator<Item = &str> {
+        self.kv
+            .iter()
+            .filter(|(k, _)| k == "import_ns")
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Get the names of the modules this module depends on
+    pub fn depends(&self) -> impl Iterator<Item = &str> {
+        self.get("depends")
+            .unwrap_or("")
+            .split(',')
+            .filter(|dep| !dep.is_empty())
+    }
+}
+
+/// Iterate over the `key=value\0` entries of a `.modinfo` section, skipping
+/// the padding between them
+fn modinfo_entries(mut modinfo_data: &[u8]) -> impl Iterator<Item = Result<(&str, &str)>> {
+    core::iter::from_fn(move || {
+        while !modinfo_data.is_empty() {
+            let entry = CStr::from_bytes_until_nul(modinfo_data)
+                .map_err(|_| ModuleErr::InvalidElf)
+                .and_then(|cstr| {
+                    modinfo_data = &modinfo_data[cstr.to_bytes_with_nul().len()..];
+                    cstr.to_str().map_err(|_| ModuleErr::InvalidElf)
+                });
+            let entry = match entry {
+                Ok("") => continue,
+                Ok(entry) => entry,
+                Err(err) => {
+                    modinfo_data = &[];
+                    return Some(Err(err));
+                }
+            };
+            return Some(entry.split_once('=').ok_or(ModuleErr::InvalidElf));
+        }
+        None
+    })
+}
+
+/// Parse the `.modinfo` section of a Linux module into a map.
+///
+/// Keys such as `alias` or `parm` can repeat, the first value wins. Use
+/// [`ModuleInfo::parse_all`] to keep every entry.
+pub fn parse_linux_modinfo(modinfo_data: &[u8]) -> Result<BTreeMap<String, String>> {
+    let mut map = BTreeMap::new();
+    for entry in modinfo_entries(modinfo_data) {
+        let (key, value) = entry?;
+        map.entry(key.to_string())
+            .or_insert_with(|| value.to_string());
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(entries: &[u8]) -> ModuleInfo {
+        let mut modinfo = b"name=test\0version=1.0\0".to_vec();
+        modinfo.extend_from_slice(entries);
+        ModuleInfo::parse_all(&modinfo).unwrap().pop().unwrap()
+    }
+
+    #[test]
+    fn format_version() {
+        // The padded entry the `module!` macro embeds
+        let module = info(&kmod::__private::__KMOD_FORMAT_VERSION);
+        assert_eq!(
+            module.format_version(),
+            Some(alloc::format!("{}", kmod::FORMAT_VERSION).as_str())
+        );
+        module.validate().unwrap();
+
+        let err = info(b"format_version=65535\0").validate().unwrap_err();
+        assert!(matches!(
+            err,
+            ModuleErr::FormatVersionMismatch { expected: kmod::FORMAT_VERSION, ref found } if found == "65535"
+        ));
+        assert!(matches!(
+            info(b"").validate(),
+            Err(ModuleErr::InvalidModuleInfo("missing format version"))
+        ));
+    }
+
+    #[test]
+    fn linux_modinfo() {
+        // Layout as emitted by modpost, padded between entries and at the end
+        let modinfo = b"license=GPL\0\0\0version=1.0\0depends=bar,baz\0name=foo\0\
+vermagic=6.6.0 SMP mod_unload \0license=MIT\0\0";
+        let map = parse_linux_modinfo(modinfo).unwrap();
+        assert_eq!(map.len(), 5, "{:?}", map);
+        assert_eq!(map["name"], "foo");
+        assert_eq!(map["version"], "1.0");
+        assert_eq!(map["license"], "GPL");
+        assert_eq!(map["vermagic"], "6.6.0 SMP mod_unload ");
+        assert_eq!(map["depends"], "bar,baz");
+
+        assert!(matches!(
+            parse_linux_modinfo(b"name=foo\0license"),
+            Err(ModuleErr::InvalidElf)
+        ));
+        assert!(matches!(
+            parse_linux_modinfo(b"name=foo\0vermagic\0"),
+            Err(ModuleErr::InvalidElf)
+        ));
+    }
 }
@@ -2,9 +2,7 @@ use alloc::string::String;
 use alloc::{collections::BTreeMap, format};
 use goblin::elf::Elf;
 
-use crate::arch::{
-    Aarch64RelocationType, Loongarch64RelocationType, Riscv64RelocationType, X86_64RelocationType,
-};
+use crate::arch::rela_type_name;
 
 pub struct ElfParser<'a> {
     elf: Elf<'a>,
@@ -13,6 +11,9 @@ pub struct ElfParser<'a> {
 
 impl<'a> ElfParser<'a> {
     pub fn new(elf_data: &'a [u8]) -> Result<Self, &'static str> {
+        if elf_data.get(goblin::elf::header::EI_DATA) == Some(&goblin::elf::header::ELFDATA2MSB) {
+            return Err("Only little-endian ELF files are supported");
+        }
         let elf = Elf::parse(elf_data).map_err(|_| "Failed to parse ELF data")?;
         if !elf.is_64 {
             return Err("Only 64-bit ELF files are supported");
@@ -72,7 +73,13 @@ impl<'a> ElfParser<'a> {
 
         for section in self.elf.section_headers.iter() {
             if section.sh_type == goblin::elf::section_header::SHT_REL {
-                panic!("REL relocations are not supported in this parser");
+                let section_name = self
+                    .elf
+                    .shdr_strtab
+                    .get_at(section.sh_name)
+                    .unwrap_or("<unknown>");
+                log::warn!("Section: {} (Type: REL) is not supported", section_name);
+                continue;
             }
             if section.sh_type == goblin::elf::section_header::SHT_RELA {
                 has_relocs = true;
@@ -81,6 +88,15 @@ impl<'a> ElfParser<'a> {
                     .shdr_strtab
                     .get_at(section.sh_name)
                     .unwrap_or("<unknown>");
+                // Size of Elf64_Rela
+                if section.sh_entsize != 24 {
+                    log::error!(
+                        "Section: {} has {} byte relocation entries, expected 24, skipped",
+                        section_name,
+                        section.sh_entsize
+                    );
+                    continue;
+                }
                 log::info!("Section: {} (Type: RELA)", section_name);
                 // println!(
                 //     "{:<16} {:<35} {:<30} {:<16}",
@@ -103,8 +119,6 @@ impl<'a> ElfParser<'a> {
     fn parse_and_print_rela_relocs(&self, section: &goblin::elf::section_header::SectionHeader) {
         let offset = section.sh_offset as usize;
 
-        // Size of Elf64_Rela
-        debug_assert!(section.sh_entsize == 24);
         let data = self.elf_data;
 
         let data_buf = &data[offset..offset + section.sh_size as usize];
@@ -155,19 +169,7 @@ impl<'a> ElfParser<'a> {
     }
 
     fn get_rel_type(&self, rel_type: u32) -> String {
-        let ty = match self.get_machine_type() {
-            "x86-64" => X86_64RelocationType::try_from(rel_type).map(|ty| format!("{ty:?}")),
-            "RISC-V" => Riscv64RelocationType::try_from(rel_type).map(|ty| format!("{ty:?}")),
-            "LoongArch" => {
-                Loongarch64RelocationType::try_from(rel_type).map(|ty| format!("{ty:?}"))
-            }
-            "AArch64" => Aarch64RelocationType::try_from(rel_type).map(|ty| format!("{ty:?}")),
-            ty => unimplemented!(
-                "Relocation type parsing not implemented for machine type: {}",
-                ty
-            ),
-        };
-        ty.unwrap_or_else(|_| format!("Unknown({})", rel_type))
+        rela_type_name(self.elf.header.e_machine, rel_type)
     }
 
     fn get_elf_type(&self) -> &'static str {
@@ -0,0 +1,396 @@
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::{
+    ModuleErr, Result,
+    loader::{KernelModuleHelper, LoadOptions, ModuleOwner},
+};
+
+/// Magic at the start of a [`LoadSession::snapshot`]
+const SNAPSHOT_MAGIC: [u8; 4] = *b"KMSS";
+/// Version of the snapshot layout, bump on any incompatible change
+const SNAPSHOT_VERSION: u16 = 1;
+/// String length marking an absent optional string
+const NO_STRING: u32 = u32::MAX;
+
+/// Metadata of a loaded module kept by a [`LoadSession`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleRecord {
+    pub name: String,
+    /// Lowest address of the module's allocations
+    pub base: u64,
+    pub version: Option<String>,
+    /// Names of the modules this module depends on
+    pub depends: Vec<String>,
+}
+
+/// An exported symbol and the module providing it
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Export {
+    owner: String,
+    address: u64,
+    namespace: Option<String>,
+}
+
+/// Registry of the loaded modules, their dependencies and exported symbols.
+///
+/// Only the metadata is tracked, the module memory is owned by the
+/// [`ModuleOwner`]s. A session can be persisted across a warm reboot with
+/// [`LoadSession::snapshot`] and [`LoadSession::restore`], the exports are
+/// not part of the snapshot and must be registered again.
+///
+/// The snapshot layout is stable and little-endian:
+///
+/// ```text
+/// magic "KMSS" | version: u16 | count: u32 | count * record
+/// record = name: str | base: u64 | version: str or u32::MAX | ndeps: u32 | ndeps * str
+/// str    = len: u32 | len bytes of UTF-8
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoadSession {
+    modules: Vec<ModuleRecord>,
+    /// Providers of each exported symbol, the last one is the active one and
+    /// the ones before it are shadowed
+    exports: BTreeMap<String, Vec<Export>>,
+}
+
+impl LoadSession {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a loaded module, replacing any previous record with the same name.
+    ///
+    /// The module depends on the modules named by its `depends` entry, and on
+    /// the ones whose exports it uses.
+    pub fn record<H: KernelModuleHelper>(&mut self, module: &ModuleOwner<H>) {
+        let info = module.module_info();
+        let mut depends: Vec<String> = info.depends().map(String::from).collect();
+        for used in module.used_modules() {
+            if !depends.iter().any(|dep| dep == used) {
+                depends.push(used.to_string());
+            }
+        }
+        self.insert(ModuleRecord {
+            name: module.name().to_string(),
+            base: module.base_address().unwrap_or(0),
+            version: info.version().map(String::from),
+            depends,
+        });
+    }
+
+    /// Add `record`, replacing any previous record with the same name
+    pub fn insert(&mut self, record: ModuleRecord) {
+        // Keep the exports, the module is only recorded again
+        self.modules.retain(|m| m.name != record.name);
+        self.modules.push(record);
+    }
+
+    /// Forget the module `name` and its exports, returning its record.
+    ///
+    /// Symbols the module shadowed resolve to their previous provider again.
+    pub fn remove(&mut self, name: &str) -> Option<ModuleRecord> {
+        self.remove_exports(name);
+        let idx = self.modules.iter().position(|m| m.name == name)?;
+        Some(self.modules.remove(idx))
+    }
+
+    /// Record that the module `owner` exports `name` at `address`, in
+    /// `namespace` if any.
+    ///
+    /// If another module already exports `name`, this fails with
+    /// [`ModuleErr::DuplicateSymbol`] unless
+    /// [`LoadOptions::allow_symbol_override`] is set, in which case the new
+    /// export shadows the existing one until `owner` is removed.
+    pub fn register_export(
+        &mut self,
+        owner: &str,
+        name: &str,
+        address: u64,
+        namespace: Option<&str>,
+        options: &LoadOptions,
+    ) -> Result<()> {
+        let providers = self.exports.entry(name.to_string()).or_default();
+        if let Some(current) = providers.last().filter(|export| export.owner != owner) {
+            if !options.allow_symbol_override {
+                log::error!(
+                    "{}: symbol '{}' is already exported by {}",
+                    owner,
+                    name,
+                    current.owner
+                );
+                return Err(ModuleErr::DuplicateSymbol {
+                    name: name.to_string(),
+                    owner: current.owner.clone(),
+                });
+            }
+            log::warn!(
+                "{}: symbol '{}' overrides the export of {}",
+                owner,
+                name,
+                current.owner
+            );
+        }
+        // A module exporting the same symbol again only moves it
+        providers.retain(|export| export.owner != owner);
+        providers.push(Export {
+            owner: owner.to_string(),
+            address,
+            namespace: namespace.map(String::from),
+        });
+        Ok(())
+    }
+
+    /// Register every symbol `module` exports with `#[export_symbol]`, so
+    /// modules loaded later with [`ModuleLoader::with_session`] can use them.
+    ///
+    /// Nothing is registered if one of the symbols can not be, see
+    /// [`LoadSession::register_export`].
+    ///
+    /// # Safety
+    ///
+    /// The module's `.ksymtab` section must be relocated.
+    ///
+    /// [`ModuleLoader::with_session`]: crate::loader::ModuleLoader::with_session
+    pub unsafe fn register_module_exports<H: KernelModuleHelper>(
+        &mut self,
+        module: &ModuleOwner<H>,
+        options: &LoadOptions,
+    ) -> Result<()> {
+        let owner = module.name();
+        for symbol in unsafe { module.exported_symbols() } {
+            let Some(name) = (unsafe { symbol.name() }) else {
+                log::error!("{}: exported symbol name is not valid UTF-8", owner);
+                self.remove_exports(owner);
+                return Err(ModuleErr::InvalidModuleInfo("invalid exported symbol name"));
+            };
+            let Ok(namespace) = (unsafe { symbol.namespace() }) else {
+                log::error!("{}: namespace of '{}' is not valid UTF-8", owner, name);
+                self.remove_exports(owner);
+                return Err(ModuleErr::InvalidModuleInfo(
+                    "invalid exported symbol namespace",
+                ));
+            };
+            let address = symbol.value() as u64;
+            if let Err(err) = self.register_export(owner, name, address, namespace, options) {
+                self.remove_exports(owner);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the address and provider of the exported symbol `name`
+    pub fn resolve_export(&self, name: &str) -> Option<(u64, &str)> {
+        let export = self.exports.get(name)?.last()?;
+        Some((export.address, export.owner.as_str()))
+    }
+
+    /// Get the namespace the active provider exports `name` in, if any
+    pub fn export_namespace(&self, name: &str) -> Option<&str> {
+        self.exports.get(name)?.last()?.namespace.as_deref()
+    }
+
+    fn remove_exports(&mut self, owner: &str) {
+        self.exports.retain(|_, providers| {
+            providers.retain(|export| export.owner != owner);
+            !providers.is_empty()
+        });
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ModuleRecord> {
+        self.modules.iter().find(|m| m.name == name)
+    }
+
+    /// Get the records in the order the modules were recorded
+    pub fn modules(&self) -> &[ModuleRecord] {
+        &self.modules
+    }
+
+    /// Get the names of the recorded modules that depend on `name`
+    pub fn dependents<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.modules
+            .iter()
+            .filter(move |m| m.depends.iter().any(|dep| dep == name))
+            .map(|m| m.name.as_str())
+    }
+
+    /// Get the number of recorded modules that depend on `name`, it can only
+    /// be unloaded at 0
+    pub fn refcount(&self, name: &str) -> usize {
+        self.dependents(name).count()
+    }
+
+    /// Serialize the registry, see [`LoadSession`] for the layout
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&SNAPSHOT_MAGIC);
+        out.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        out.extend_from_slice(&(self.modules.len() as u32).to_le_bytes());
+        for module in &self.modules {
+            put_str(&mut out, Some(&module.name));
+            out.extend_from_slice(&module.base.to_le_bytes());
+            put_str(&mut out, module.version.as_deref());
+            out.extend_from_slice(&(module.depends.len() as u32).to_le_bytes());
+            for dep in &module.depends {
+                put_str(&mut out, Some(dep));
+            }
+        }
+        out
+    }
+
+    /// Rebuild a registry from the output of [`LoadSession::snapshot`]
+    pub fn restore(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader(data);
+        if reader.take(4)? != SNAPSHOT_MAGIC {
+            log::error!("Not a module session snapshot");
+            return Err(ModuleErr::InvalidOperation);
+        }
+        let version = u16::from_le_bytes(reader.array()?);
+        if version != SNAPSHOT_VERSION {
+            log::error!(
+                "Unsupported session snapshot version {}, expected {}",
+                version,
+                SNAPSHOT_VERSION
+            );
+            return Err(ModuleErr::InvalidOperation);
+        }
+        let count = reader.u32()?;
+        let mut session = LoadSession::new();
+        for _ in 0..count {
+            let name = reader.string()?.ok_or(ModuleErr::InvalidOperation)?;
+            let base = u64::from_le_bytes(reader.array()?);
+            let version = reader.string()?;
+            let ndeps = reader.u32()?;
+            let depends = (0..ndeps)
+                .map(|_| reader.string()?.ok_or(ModuleErr::InvalidOperation))
+                .collect::<Result<_>>()?;
+            session.modules.push(ModuleRecord {
+                name,
+                base,
+                version,
+                depends,
+            });
+        }
+        if !reader.0.is_empty() {
+            log::error!("Trailing bytes after session snapshot");
+            return Err(ModuleErr::InvalidOperation);
+        }
+        Ok(session)
+    }
+}
+
+fn put_str(out: &mut Vec<u8>, s: Option<&str>) {
+    match s {
+        Some(s) => {
+            out.extend_from_slice(&(s.len() as u32).to_le_bytes());
+            out.extend_from_slice(s.as_bytes());
+        }
+        None => out.extend_from_slice(&NO_STRING.to_le_bytes()),
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            log::error!("Truncated session snapshot");
+            return Err(ModuleErr::InvalidOperation);
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn string(&mut self) -> Result<Option<String>> {
+        let len = self.u32()?;
+        if len == NO_STRING {
+            return Ok(None);
+        }
+        let bytes = self.take(len as usize)?;
+        core::str::from_utf8(bytes)
+            .map(|s| Some(s.to_string()))
+            .map_err(|_| ModuleErr::InvalidOperation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut session = LoadSession::new();
+        session.insert(ModuleRecord {
+            name: "base".to_string(),
+            base: 0xffff_ffc0_0000_0000,
+            version: Some("1.0".to_string()),
+            depends: Vec::new(),
+        });
+        session.insert(ModuleRecord {
+            name: "user".to_string(),
+            base: 0xffff_ffc0_0010_0000,
+            version: None,
+            depends: alloc::vec!["base".to_string()],
+        });
+
+        let snapshot = session.snapshot();
+        let restored = LoadSession::restore(&snapshot).unwrap();
+        assert_eq!(restored, session);
+        assert_eq!(restored.refcount("base"), 1);
+        assert!(restored.dependents("base").eq(["user"]));
+
+        // Truncated, with trailing bytes and with another version
+        assert!(LoadSession::restore(&snapshot[..snapshot.len() - 1]).is_err());
+        let mut trailing = snapshot.clone();
+        trailing.push(0);
+        assert!(LoadSession::restore(&trailing).is_err());
+        let mut version = snapshot;
+        version[4] = version[4].wrapping_add(1);
+        assert!(LoadSession::restore(&version).is_err());
+    }
+
+    #[test]
+    fn duplicate_export() {
+        let mut session = LoadSession::new();
+        let reject = LoadOptions::default();
+        let shadow = LoadOptions {
+            allow_symbol_override: true,
+            ..Default::default()
+        };
+        session
+            .register_export("a", "foo", 0x1000, None, &reject)
+            .unwrap();
+
+        let err = session
+            .register_export("b", "foo", 0x2000, None, &reject)
+            .expect_err("foo is exported by a");
+        assert!(matches!(
+            err,
+            ModuleErr::DuplicateSymbol { ref name, ref owner } if name == "foo" && owner == "a"
+        ));
+        assert_eq!(session.resolve_export("foo"), Some((0x1000, "a")));
+
+        session
+            .register_export("b", "foo", 0x2000, None, &shadow)
+            .unwrap();
+        assert_eq!(session.resolve_export("foo"), Some((0x2000, "b")));
+        // Unloading the overriding module restores the original
+        session.remove("b");
+        assert_eq!(session.resolve_export("foo"), Some((0x1000, "a")));
+        session.remove("a");
+        assert_eq!(session.resolve_export("foo"), None);
+    }
+}
@@ -0,0 +1,576 @@
+//! Module images and a host helper for the loader tests
+//!
+//! goblin only reads ELF files, [`ElfBuilder`] writes the few relocatable
+//! images the tests need, and [`TestHelper`] is a host whose behaviour each
+//! test configures through [`with_host`].
+
+extern crate std;
+
+use alloc::{boxed::Box, string::String, vec::Vec};
+use core::cell::RefCell;
+use goblin::elf::section_header::{SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE, SHT_PROGBITS, SHT_RELA};
+
+use crate::loader::{AllocHint, InitFn, KernelModuleHelper, SectionMemOps, SectionPerm};
+
+/// A section of an [`ElfBuilder`] image
+pub(crate) struct Section {
+    pub name: String,
+    pub sh_type: u32,
+    pub flags: u64,
+    pub align: u64,
+    pub data: Vec<u8>,
+    pub info: u32,
+    pub entsize: u64,
+    /// `sh_addr`, the link address in a linked image
+    pub addr: u64,
+    /// Relocation sections link to the symbol table, whose index is only
+    /// known once the image is built
+    links_symtab: bool,
+    /// `sh_link` otherwise
+    link: u32,
+}
+
+/// A symbol of an [`ElfBuilder`] image
+struct Symbol {
+    name: String,
+    info: u8,
+    shndx: u16,
+    value: u64,
+    size: u64,
+}
+
+/// Writer of minimal `ET_REL` images. Sections and symbols are numbered in
+/// the order they are added, from 1, followed by `.symtab`, `.strtab` and
+/// `.shstrtab`.
+pub(crate) struct ElfBuilder {
+    machine: u16,
+    e_type: u16,
+    sections: Vec<Section>,
+    symbols: Vec<Symbol>,
+}
+
+impl ElfBuilder {
+    pub fn new(machine: u16) -> Self {
+        ElfBuilder {
+            machine,
+            e_type: goblin::elf::header::ET_REL,
+            sections: Vec::new(),
+            symbols: Vec::new(),
+        }
+    }
+
+    /// A module of `machine` whose `#[init_fn]` is `init`, with an empty
+    /// `.text` section 1
+    pub fn module(machine: u16, init: InitFn) -> Self {
+        let mut builder = ElfBuilder::new(machine);
+        builder.section(
+            ".text",
+            SHT_PROGBITS,
+            (SHF_ALLOC | SHF_EXECINSTR) as u64,
+            4,
+            &[0; 16],
+        );
+        let format_version = alloc::format!("format_version={}", kmod::FORMAT_VERSION);
+        builder.modinfo(&["name=test", "version=1.0", "license=GPL", &format_version]);
+        let module = kmod::Module::new("test", Some(init), None);
+        let bytes = unsafe {
+            core::slice::from_raw_parts(
+                &module as *const kmod::Module as *const u8,
+                core::mem::size_of::<kmod::Module>(),
+            )
+        };
+        builder.section(
+            ".gnu.linkonce.this_module",
+            SHT_PROGBITS,
+            (SHF_ALLOC | SHF_WRITE) as u64,
+            8,
+            bytes,
+        );
+        builder
+    }
+
+    /// Add a section, returning its index
+    pub fn section(
+        &mut self,
+        name: &str,
+        sh_type: u32,
+        flags: u64,
+        align: u64,
+        data: &[u8],
+    ) -> usize {
+        self.sections.push(Section {
+            name: name.into(),
+            sh_type,
+            flags,
+            align,
+            data: data.into(),
+            info: 0,
+            entsize: 0,
+            addr: 0,
+            links_symtab: false,
+            link: 0,
+        });
+        self.sections.len()
+    }
+
+    /// Set the `e_type`, e.g. `ET_EXEC` for an image linked at the `addr` of
+    /// its sections
+    pub fn elf_type(&mut self, e_type: u16) {
+        self.e_type = e_type;
+    }
+
+    /// Get the section `index` to adjust its header
+    pub fn section_mut(&mut self, index: usize) -> &mut Section {
+        &mut self.sections[index - 1]
+    }
+
+    /// Set the `.modinfo` section to the `key=value` `entries`, adding it
+    /// if there is none
+    pub fn modinfo(&mut self, entries: &[&str]) {
+        let mut data = Vec::new();
+        for entry in entries {
+            data.extend_from_slice(entry.as_bytes());
+            data.push(0);
+        }
+        match self.sections.iter_mut().find(|s| s.name == ".modinfo") {
+            Some(section) => section.data = data,
+            None => {
+                self.section(".modinfo", SHT_PROGBITS, 0, 1, &data);
+            }
+        }
+    }
+
+    /// Add a symbol, returning its index
+    pub fn symbol(&mut self, name: &str, bind: u8, ty: u8, shndx: usize, value: u64) -> usize {
+        self.symbols.push(Symbol {
+            name: name.into(),
+            info: (bind << 4) | ty,
+            shndx: shndx as u16,
+            value,
+            size: 0,
+        });
+        self.symbols.len()
+    }
+
+    /// Set the `st_size` of the symbol `index`
+    pub fn symbol_size(&mut self, index: usize, size: u64) {
+        self.symbols[index - 1].size = size;
+    }
+
+    /// Add a `SHT_RELA` section patching the section `target` with
+    /// `(r_offset, symbol, type, addend)` entries, returning its index
+    pub fn rela(&mut self, target: usize, entries: &[(u64, usize, u32, i64)]) -> usize {
+        let mut data = Vec::new();
+        for &(offset, sym, ty, addend) in entries {
+            data.extend_from_slice(&offset.to_le_bytes());
+            data.extend_from_slice(&(((sym as u64) << 32) | ty as u64).to_le_bytes());
+            data.extend_from_slice(&addend.to_le_bytes());
+        }
+        let name = alloc::format!(".rela{}", self.sections[target - 1].name);
+        let index = self.section(&name, SHT_RELA, 0, 8, &data);
+        let section = self.section_mut(index);
+        section.info = target as u32;
+        section.entsize = 24;
+        section.links_symtab = true;
+        index
+    }
+
+    /// Add a symbol table other than `.symtab`, e.g. a `SHT_DYNSYM`, with
+    /// `(name, bind, type, shndx, value)` symbols and its string table
+    /// `strtab`, returning its index
+    pub fn symbol_table(
+        &mut self,
+        name: &str,
+        sh_type: u32,
+        strtab: &str,
+        symbols: &[(&str, u8, u8, usize, u64)],
+    ) -> usize {
+        let symbols: Vec<_> = symbols
+            .iter()
+            .map(|&(name, bind, ty, shndx, value)| Symbol {
+                name: name.into(),
+                info: (bind << 4) | ty,
+                shndx: shndx as u16,
+                value,
+                size: 0,
+            })
+            .collect();
+        let (table, strings, locals) = encode_symbols(&symbols);
+        let strtab = self.section(
+            strtab,
+            goblin::elf::section_header::SHT_STRTAB,
+            0,
+            1,
+            &strings,
+        );
+        let index = self.section(name, sh_type, 0, 8, &table);
+        let section = self.section_mut(index);
+        section.link = strtab as u32;
+        section.info = locals;
+        section.entsize = 24;
+        index
+    }
+
+    /// Like [`ElfBuilder::rela`], with entries indexing the symbol table
+    /// `symtab` instead of `.symtab`
+    pub fn rela_linked(
+        &mut self,
+        target: usize,
+        symtab: usize,
+        entries: &[(u64, usize, u32, i64)],
+    ) -> usize {
+        let index = self.rela(target, entries);
+        let section = self.section_mut(index);
+        section.links_symtab = false;
+        section.link = symtab as u32;
+        index
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        use goblin::elf::section_header::{SHT_STRTAB, SHT_SYMTAB};
+        let symtab_idx = self.sections.len() as u32 + 1;
+
+        let (symtab, strtab, locals) = encode_symbols(&self.symbols);
+
+        let tables = [
+            (".symtab", SHT_SYMTAB, symtab, symtab_idx + 1, locals, 24),
+            (".strtab", SHT_STRTAB, strtab, 0, 0, 0),
+        ];
+        let mut shstrtab = alloc::vec![0u8];
+        let mut name_of = |name: &str| {
+            let offset = shstrtab.len() as u32;
+            shstrtab.extend_from_slice(name.as_bytes());
+            shstrtab.push(0);
+            offset
+        };
+        // (sh_name, sh_type, sh_flags, sh_addr, data, sh_link, sh_info, sh_addralign,
+        // sh_entsize)
+        let mut headers = Vec::new();
+        for section in &self.sections {
+            let link = if section.links_symtab {
+                symtab_idx
+            } else {
+                section.link
+            };
+            headers.push((
+                name_of(&section.name),
+                section.sh_type,
+                section.flags,
+                section.addr,
+                section.data.as_slice(),
+                link,
+                section.info,
+                section.align,
+                section.entsize,
+            ));
+        }
+        for (name, sh_type, data, link, info, entsize) in &tables {
+            headers.push((
+                name_of(name),
+                *sh_type,
+                0,
+                0,
+                data.as_slice(),
+                *link,
+                *info,
+                8,
+                *entsize,
+            ));
+        }
+        let shstrtab_name = name_of(".shstrtab");
+        headers.push((
+            shstrtab_name,
+            SHT_STRTAB,
+            0,
+            0,
+            shstrtab.as_slice(),
+            0,
+            0,
+            1,
+            0,
+        ));
+
+        let mut image = alloc::vec![0u8; 64];
+        let mut offsets = Vec::new();
+        for header in &headers {
+            let align = header.7.max(1) as usize;
+            image.resize(image.len().next_multiple_of(align), 0);
+            offsets.push(image.len() as u64);
+            image.extend_from_slice(header.4);
+        }
+        image.resize(image.len().next_multiple_of(8), 0);
+        let shoff = image.len() as u64;
+        // The null section
+        image.extend_from_slice(&[0; 64]);
+        for (header, offset) in headers.iter().zip(offsets) {
+            let (name, sh_type, flags, addr, data, link, info, align, entsize) = *header;
+            image.extend_from_slice(&name.to_le_bytes());
+            image.extend_from_slice(&sh_type.to_le_bytes());
+            image.extend_from_slice(&flags.to_le_bytes());
+            image.extend_from_slice(&addr.to_le_bytes());
+            image.extend_from_slice(&offset.to_le_bytes());
+            image.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            image.extend_from_slice(&link.to_le_bytes());
+            image.extend_from_slice(&info.to_le_bytes());
+            image.extend_from_slice(&align.to_le_bytes());
+            image.extend_from_slice(&entsize.to_le_bytes());
+        }
+
+        let shnum = headers.len() as u16 + 1;
+        let ehdr = &mut image[..64];
+        ehdr[..8].copy_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+        ehdr[16..18].copy_from_slice(&self.e_type.to_le_bytes());
+        ehdr[18..20].copy_from_slice(&self.machine.to_le_bytes());
+        ehdr[20..24].copy_from_slice(&1u32.to_le_bytes());
+        ehdr[40..48].copy_from_slice(&shoff.to_le_bytes());
+        ehdr[52..54].copy_from_slice(&64u16.to_le_bytes());
+        ehdr[58..60].copy_from_slice(&64u16.to_le_bytes());
+        ehdr[60..62].copy_from_slice(&shnum.to_le_bytes());
+        ehdr[62..64].copy_from_slice(&(shnum - 1).to_le_bytes());
+        image
+    }
+}
+
+/// Encode `symbols` after the null symbol, returning the symbol table, its
+/// string table and the `sh_info` of the table, one past the last local
+fn encode_symbols(symbols: &[Symbol]) -> (Vec<u8>, Vec<u8>, u32) {
+    let mut strtab = alloc::vec![0u8];
+    let mut symtab = alloc::vec![0u8; 24];
+    for sym in symbols {
+        symtab.extend_from_slice(&(strtab.len() as u32).to_le_bytes());
+        symtab.push(sym.info);
+        symtab.push(0);
+        symtab.extend_from_slice(&sym.shndx.to_le_bytes());
+        symtab.extend_from_slice(&sym.value.to_le_bytes());
+        symtab.extend_from_slice(&sym.size.to_le_bytes());
+        strtab.extend_from_slice(sym.name.as_bytes());
+        strtab.push(0);
+    }
+    let locals = 1 + symbols.iter().take_while(|sym| sym.info >> 4 == 0).count();
+    (symtab, strtab, locals as u32)
+}
+
+/// What the [`TestHelper`] host provides, set up by each test
+#[derive(Default)]
+pub(crate) struct HostState {
+    /// Kernel symbols
+    pub symbols: Vec<(&'static str, usize)>,
+    /// `(symbol, namespace)` of the kernel symbols exported in a namespace
+    pub namespaces: Vec<(&'static str, &'static str)>,
+    /// Kernel symbol CRCs
+    pub crcs: Vec<(&'static str, u32)>,
+    /// Kernel version magic
+    pub vermagic: Option<&'static str>,
+    /// Kernel GOT slots
+    pub got: Vec<(&'static str, u64)>,
+    /// CPU features
+    pub features: Vec<u32>,
+    /// Offset of the TLS block handed out to modules
+    pub tls_offset: Option<u64>,
+    /// Ignore [`AllocHint::align`], like the default `alloc`
+    pub ignore_align: bool,
+    /// Fail every allocation
+    pub out_of_memory: bool,
+    /// Size and hint of every allocation, in order
+    pub allocations: Vec<(usize, AllocHint)>,
+    /// Init and exit functions run so far, in order
+    pub calls: Vec<&'static str>,
+    /// `(address, exec, write)` of every `set_memory_attributes`, in order
+    pub memory_attributes: Vec<(usize, bool, bool)>,
+}
+
+std::thread_local! {
+    static HOST: RefCell<HostState> = RefCell::new(HostState::default());
+}
+
+/// Access the state of the [`TestHelper`] host of the current test, each
+/// test runs on its own thread
+pub(crate) fn with_host<R>(f: impl FnOnce(&mut HostState) -> R) -> R {
+    HOST.with(|host| f(&mut host.borrow_mut()))
+}
+
+/// A log record captured by [`capture_logs`]: level, target and message
+pub(crate) type LogRecord = (log::Level, String, String);
+
+std::thread_local! {
+    /// Records of the current thread, while it captures them
+    static LOGS: RefCell<Option<Vec<LogRecord>>> = const { RefCell::new(None) };
+}
+
+/// Logger recording the messages of the threads inside [`capture_logs`]
+struct CaptureLogger;
+
+impl log::Log for CaptureLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        LOGS.with(|logs| {
+            if let Some(logs) = logs.borrow_mut().as_mut() {
+                logs.push((
+                    record.level(),
+                    record.target().into(),
+                    alloc::format!("{}", record.args()),
+                ));
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// Run `f`, returning what it logged on the current thread too
+pub(crate) fn capture_logs<R>(f: impl FnOnce() -> R) -> (R, Vec<LogRecord>) {
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+    INSTALL.call_once(|| {
+        log::set_logger(&CaptureLogger).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+    });
+    LOGS.with(|logs| *logs.borrow_mut() = Some(Vec::new()));
+    let result = f();
+    let logs = LOGS.with(|logs| logs.borrow_mut().take().unwrap_or_default());
+    (result, logs)
+}
+
+/// Zeroed host memory standing in for a module allocation
+struct HostMem {
+    base: *mut u8,
+    layout: std::alloc::Layout,
+    /// Offset of the memory handed out from `base`
+    offset: usize,
+}
+
+impl HostMem {
+    fn new(size: usize, align: usize, offset: usize) -> Self {
+        let layout = std::alloc::Layout::from_size_align(size + offset, align).unwrap();
+        let base = unsafe { std::alloc::alloc_zeroed(layout) };
+        assert!(!base.is_null());
+        HostMem {
+            base,
+            layout,
+            offset,
+        }
+    }
+}
+
+impl Drop for HostMem {
+    fn drop(&mut self) {
+        unsafe { std::alloc::dealloc(self.base, self.layout) };
+    }
+}
+
+impl SectionMemOps for HostMem {
+    fn as_ptr(&self) -> *const u8 {
+        self.base.wrapping_add(self.offset)
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.base.wrapping_add(self.offset)
+    }
+
+    fn change_perms(&mut self, _perms: SectionPerm) -> bool {
+        true
+    }
+}
+
+/// Memory of a failed allocation
+struct NullMem;
+
+impl SectionMemOps for NullMem {
+    fn as_ptr(&self) -> *const u8 {
+        core::ptr::null()
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        core::ptr::null_mut()
+    }
+
+    fn change_perms(&mut self, _perms: SectionPerm) -> bool {
+        false
+    }
+}
+
+/// Host helper backed by the [`HostState`] of the current test
+pub(crate) struct TestHelper;
+
+impl KernelModuleHelper for TestHelper {
+    // Whatever a host would keep per module, e.g. a device handle
+    type UserData = String;
+
+    fn vmalloc(size: usize) -> Box<dyn SectionMemOps> {
+        Box::new(HostMem::new(size, 4096, 0))
+    }
+
+    fn alloc(size: usize, hint: AllocHint) -> Box<dyn SectionMemOps> {
+        with_host(|host| {
+            host.allocations.push((size, hint));
+            if host.out_of_memory {
+                return Box::new(NullMem) as Box<dyn SectionMemOps>;
+            }
+            if host.ignore_align {
+                // Page aligned, but deliberately off any larger boundary
+                return Box::new(HostMem::new(size, hint.align.max(8192), 4096));
+            }
+            Box::new(HostMem::new(size, hint.align.max(4096), 0))
+        })
+    }
+
+    fn resolve_symbol(name: &str) -> Option<usize> {
+        with_host(|host| {
+            host.symbols
+                .iter()
+                .find(|(sym, _)| *sym == name)
+                .map(|&(_, addr)| addr)
+        })
+    }
+
+    fn symbol_namespace(name: &str) -> Option<String> {
+        with_host(|host| {
+            host.namespaces
+                .iter()
+                .find(|(sym, _)| *sym == name)
+                .map(|&(_, ns)| ns.into())
+        })
+    }
+
+    fn kernel_got_entry(name: &str) -> Option<u64> {
+        with_host(|host| {
+            host.got
+                .iter()
+                .find(|(sym, _)| *sym == name)
+                .map(|&(_, slot)| slot)
+        })
+    }
+
+    fn tls_block_offset(_size: usize, _align: usize) -> Option<u64> {
+        with_host(|host| host.tls_offset)
+    }
+
+    fn set_memory_attributes(vaddr: usize, _len: usize, exec: bool, write: bool) -> bool {
+        with_host(|host| host.memory_attributes.push((vaddr, exec, write)));
+        true
+    }
+
+    fn cpu_has(feature: u32) -> bool {
+        with_host(|host| host.features.contains(&feature))
+    }
+
+    fn vermagic() -> Option<&'static str> {
+        with_host(|host| host.vermagic)
+    }
+
+    fn symbol_crc(name: &str) -> Option<u32> {
+        with_host(|host| {
+            host.crcs
+                .iter()
+                .find(|(sym, _)| *sym == name)
+                .map(|&(_, crc)| crc)
+        })
+    }
+}
+
+/// An init function returning 0
+pub(crate) unsafe extern "C" fn init_ok() -> core::ffi::c_int {
+    0
+}
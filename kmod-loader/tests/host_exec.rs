@@ -0,0 +1,174 @@
+//! Load the example modules built for the host and run them.
+//!
+//! Each module is built for `x86_64-unknown-none` with the static relocation
+//! model and linked into a relocatable object, like:
+//!
+//! ```text
+//! RUSTFLAGS="-C relocation-model=static -C code-model=large" cargo +nightly build \
+//!     -Zbuild-std=core --target x86_64-unknown-none -p hello
+//! ld -r --whole-archive target/x86_64-unknown-none/debug/libhello.a -o hello.o
+//! ```
+#![cfg(all(target_arch = "x86_64", target_os = "linux"))]
+
+use kmod_loader::loader::{KernelModuleHelper, ModuleLoader, SectionMemOps, SectionPerm};
+use kmod_loader::{LoadSession, ModuleErr};
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Mutex;
+
+/// Keeps the tests from running modules at the same time
+static RUNNING: Mutex<()> = Mutex::new(());
+/// Output of `write_char`
+static OUTPUT: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+/// Provided to the module, as a kernel would
+extern "C" fn write_char(c: u8) {
+    OUTPUT.lock().unwrap().push(c);
+}
+
+/// Take what the module wrote so far
+fn take_output() -> Vec<u8> {
+    std::mem::take(&mut OUTPUT.lock().unwrap())
+}
+
+/// Build the module `name` for the host and get its relocatable object
+fn build_module(name: &str) -> Vec<u8> {
+    let target_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("modules");
+    let status = Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".into()))
+        .args(["build", "-q", "-Zbuild-std=core"])
+        .args(["--target", "x86_64-unknown-none", "-p", name])
+        .env(
+            "RUSTFLAGS",
+            "-C relocation-model=static -C code-model=large",
+        )
+        .env_remove("CARGO_ENCODED_RUSTFLAGS")
+        .env("CARGO_TARGET_DIR", &target_dir)
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .status()
+        .expect("failed to run cargo");
+    assert!(status.success(), "failed to build {}", name);
+    let archive = target_dir.join(format!("x86_64-unknown-none/debug/lib{}.a", name));
+    let object = target_dir.join(format!("{}.o", name));
+    let status = Command::new("ld")
+        .arg("-r")
+        .arg("--whole-archive")
+        .arg(&archive)
+        .arg("-o")
+        .arg(&object)
+        .status()
+        .expect("failed to run ld");
+    assert!(status.success(), "failed to link {}", name);
+    std::fs::read(object).unwrap()
+}
+
+#[test]
+fn hello() {
+    let elf = build_module("hello");
+    let _running = RUNNING.lock().unwrap_or_else(|e| e.into_inner());
+    take_output();
+
+    let owner = ModuleLoader::<HostHelper>::new(&elf)
+        .unwrap()
+        .load_module()
+        .unwrap();
+    let mut session = LoadSession::new();
+    let mut owner = owner
+        .init_module(&mut session, &Default::default())
+        .unwrap();
+    assert_eq!(take_output(), b"Hello, Kernel Module!\n");
+    owner.call_exit();
+    assert_eq!(take_output(), b"Goodbye, Kernel Module!\n");
+}
+
+#[test]
+fn initfail() {
+    let elf = build_module("initfail");
+    let _running = RUNNING.lock().unwrap_or_else(|e| e.into_inner());
+    take_output();
+
+    let owner = ModuleLoader::<HostHelper>::new(&elf)
+        .unwrap()
+        .load_module()
+        .unwrap();
+    let mut session = LoadSession::new();
+    let err = owner
+        .init_module(&mut session, &Default::default())
+        .err()
+        .expect("init should fail");
+    assert!(matches!(err, ModuleErr::InitFailed(-22)), "{:?}", err);
+    assert_eq!(take_output(), b"Initfail module refusing to load\n");
+    assert!(session.get("initfail").is_none());
+}
+
+struct HostHelper;
+
+impl KernelModuleHelper for HostHelper {
+    type UserData = ();
+
+    fn vmalloc(size: usize) -> Box<dyn SectionMemOps> {
+        let mmap = memmap2::MmapOptions::new()
+            .len(size)
+            .populate()
+            .map_anon()
+            .expect("Failed to allocate memory");
+        Box::new(MmapAsPtr(Mapping::Writable(mmap)))
+    }
+
+    fn resolve_symbol(name: &str) -> Option<usize> {
+        match name {
+            "write_char" => Some(write_char as *const () as usize),
+            _ => None,
+        }
+    }
+}
+
+/// A mapping whose protection really changes, so the module can run
+struct MmapAsPtr(Mapping);
+
+enum Mapping {
+    Writable(memmap2::MmapMut),
+    ReadOnly(memmap2::Mmap),
+    /// Only seen while the protection changes
+    Empty,
+}
+
+impl SectionMemOps for MmapAsPtr {
+    fn as_ptr(&self) -> *const u8 {
+        match &self.0 {
+            Mapping::Writable(mmap) => mmap.as_ptr(),
+            Mapping::ReadOnly(mmap) => mmap.as_ptr(),
+            Mapping::Empty => std::ptr::null(),
+        }
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        match &mut self.0 {
+            Mapping::Writable(mmap) => mmap.as_mut_ptr(),
+            _ => std::ptr::null_mut(),
+        }
+    }
+
+    fn change_perms(&mut self, perms: SectionPerm) -> bool {
+        let mmap = match std::mem::replace(&mut self.0, Mapping::Empty) {
+            Mapping::Writable(mmap) => Ok(mmap),
+            Mapping::ReadOnly(mmap) => mmap.make_mut(),
+            Mapping::Empty => return false,
+        };
+        let mapping = mmap.and_then(|mmap| {
+            if perms.contains(SectionPerm::EXECUTE) {
+                mmap.make_exec().map(Mapping::ReadOnly)
+            } else if perms.contains(SectionPerm::WRITE) {
+                Ok(Mapping::Writable(mmap))
+            } else {
+                mmap.make_read_only().map(Mapping::ReadOnly)
+            }
+        });
+        match mapping {
+            Ok(mapping) => {
+                self.0 = mapping;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
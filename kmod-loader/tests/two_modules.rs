@@ -0,0 +1,67 @@
+//! Declare two modules in one crate and enumerate both from `.modinfo`.
+//!
+//! Their `#[init_fn]` and `#[exit_fn]` functions share a scope, so the
+//! generated items must not collide.
+#![cfg(target_os = "linux")]
+
+use kmod::{exit_fn, init_fn, module};
+use kmod_loader::ModuleInfo;
+
+#[init_fn]
+fn alpha_init() -> i32 {
+    0
+}
+
+#[exit_fn]
+fn alpha_exit() {}
+
+#[init_fn]
+fn beta_init() -> i32 {
+    0
+}
+
+#[exit_fn]
+fn beta_exit() {}
+
+module!(
+    name: "alpha",
+    license: "GPL",
+    description: "The first module",
+    version: "0.1.0",
+    init: alpha_init,
+    exit: alpha_exit,
+);
+
+module!(
+    name: "beta",
+    license: "MIT",
+    description: "The second module",
+    version: "0.2.0",
+    init: beta_init,
+    exit: beta_exit,
+);
+
+#[test]
+fn two_modules() {
+    let image = std::fs::read("/proc/self/exe").expect("failed to read the test binary");
+    let elf = goblin::elf::Elf::parse(&image).expect("failed to parse the test binary");
+    let modinfo = elf
+        .section_headers
+        .iter()
+        .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(".modinfo"))
+        .expect("no .modinfo section");
+    let range = modinfo.file_range().expect(".modinfo has no contents");
+
+    let mut infos = ModuleInfo::parse_all(&image[range]).expect("malformed .modinfo");
+    infos.sort_by(|a, b| a.name().cmp(&b.name()));
+    assert_eq!(infos.len(), 2, "{:?}", infos);
+    for (info, (name, version, license)) in infos
+        .iter()
+        .zip([("alpha", "0.1.0", "GPL"), ("beta", "0.2.0", "MIT")])
+    {
+        assert_eq!(info.name(), Some(name));
+        assert_eq!(info.version(), Some(version));
+        assert_eq!(info.license(), Some(license));
+        info.validate().expect("incomplete module info");
+    }
+}
@@ -0,0 +1,54 @@
+/// Descriptor of an init function declared with `#[init_fn(priority = N)]`,
+/// placed in the `.init_calls` section.
+///
+/// The layout is part of the module format, the loader reads the section as
+/// an array of descriptors once the module is relocated, and calls them in
+/// ascending priority after the plain `#[init_fn]`.
+#[repr(C)]
+pub struct InitCall {
+    priority: u32,
+    func: unsafe extern "C" fn() -> core::ffi::c_int,
+}
+
+impl InitCall {
+    pub const fn new(priority: u32, func: unsafe extern "C" fn() -> core::ffi::c_int) -> Self {
+        InitCall { priority, func }
+    }
+
+    /// Get the priority of the init function
+    pub fn priority(&self) -> u32 {
+        self.priority
+    }
+
+    /// Get the init function
+    pub fn func(&self) -> unsafe extern "C" fn() -> core::ffi::c_int {
+        self.func
+    }
+}
+
+/// Descriptor of an exit function declared with `#[exit_fn(priority = N)]`,
+/// placed in the `.exit_calls` section.
+///
+/// The loader calls them in descending priority before the plain
+/// `#[exit_fn]`, undoing the init functions in reverse.
+#[repr(C)]
+pub struct ExitCall {
+    priority: u32,
+    func: unsafe extern "C" fn(),
+}
+
+impl ExitCall {
+    pub const fn new(priority: u32, func: unsafe extern "C" fn()) -> Self {
+        ExitCall { priority, func }
+    }
+
+    /// Get the priority of the exit function
+    pub fn priority(&self) -> u32 {
+        self.priority
+    }
+
+    /// Get the exit function
+    pub fn func(&self) -> unsafe extern "C" fn() {
+        self.func
+    }
+}
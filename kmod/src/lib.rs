@@ -1,8 +1,49 @@
 #![no_std]
 #![feature(linkage)]
 
+mod initcall;
 mod module;
 mod param;
-pub use kmacro::{exit_fn, init_fn, module};
-pub use module::Module;
-pub use param::KernelParam;
+mod symbol;
+pub use initcall::{ExitCall, InitCall};
+pub use kmacro::{exit_fn, export_symbol, init_fn, module, module_param};
+pub use module::{Module, MODULE_NAME_LEN};
+pub use param::{KernelParam, ModuleParam, ModuleParamType, MODULE_PARAM_NAME_LEN};
+pub use symbol::KernelSymbol;
+
+/// Defaults used by the code the macros generate
+#[doc(hidden)]
+pub mod __private {
+    /// Size of [`__KMOD_FORMAT_VERSION`], room for any `u16`
+    pub const __KMOD_FORMAT_VERSION_LEN: usize = b"format_version=65535\0".len();
+
+    /// The `format_version=N` `.modinfo` entry of [`super::FORMAT_VERSION`],
+    /// padded with NULs the loader skips
+    pub const __KMOD_FORMAT_VERSION: [u8; __KMOD_FORMAT_VERSION_LEN] = {
+        let key = b"format_version=";
+        let mut entry = [0u8; __KMOD_FORMAT_VERSION_LEN];
+        let mut i = 0;
+        while i < key.len() {
+            entry[i] = key[i];
+            i += 1;
+        }
+        let mut digits = 1;
+        while super::FORMAT_VERSION as u32 / 10u32.pow(digits) > 0 {
+            digits += 1;
+        }
+        let mut version = super::FORMAT_VERSION;
+        while digits > 0 {
+            digits -= 1;
+            entry[i + digits as usize] = b'0' + (version % 10) as u8;
+            version /= 10;
+        }
+        entry
+    };
+}
+
+/// Version of the module format, i.e. the layout of [`Module`],
+/// [`KernelParam`], [`ModuleParam`], [`KernelSymbol`], [`InitCall`] and
+/// [`ExitCall`] that modules embed and the loader reads.
+///
+/// Bumped whenever that layout changes incompatibly.
+pub const FORMAT_VERSION: u16 = 2;
@@ -1,3 +1,8 @@
+/// Maximum length of a module name, including the NUL terminator
+///
+/// See <https://elixir.bootlin.com/linux/v6.6/source/include/linux/module.h#L35>
+pub const MODULE_NAME_LEN: usize = 56;
+
 /// The `Module` struct represents a kernel module.
 ///
 /// See <https://elixir.bootlin.com/linux/v6.6/source/include/linux/module.h#L402>
@@ -14,8 +19,10 @@ unsafe impl Send for Module {}
 unsafe impl Sync for Module {}
 
 impl Module {
-    /// Creates a new `Module` instance with the given initialization and exit functions.
+    /// Creates a new `Module` instance with the given name, initialization and
+    /// exit functions, failing to compile if `name` is too long
     pub const fn new(
+        name: &str,
         init_fn: Option<unsafe extern "C" fn() -> core::ffi::c_int>,
         exit_fn: Option<unsafe extern "C" fn()>,
     ) -> Self {
@@ -24,11 +31,30 @@ impl Module {
             core::ptr::write_bytes(module.as_mut_ptr(), 0, 1);
             module.assume_init()
         };
+        let bytes = name.as_bytes();
+        assert!(bytes.len() < MODULE_NAME_LEN, "module name is too long");
+        let mut i = 0;
+        while i < bytes.len() {
+            module.name[i] = bytes[i] as core::ffi::c_char;
+            i += 1;
+        }
         module.init = init_fn;
         module.exit = exit_fn;
         Module(module)
     }
 
+    /// Get the name of the module, `None` if it is not valid UTF-8
+    pub fn name(&self) -> Option<&str> {
+        let name = unsafe { &*(&self.0.name as *const _ as *const [u8; MODULE_NAME_LEN]) };
+        let len = name.iter().position(|&b| b == 0).unwrap_or(MODULE_NAME_LEN);
+        core::str::from_utf8(&name[..len]).ok()
+    }
+
+    /// Whether the module has an initialization function
+    pub fn has_init_fn(&self) -> bool {
+        self.0.init.is_some()
+    }
+
     pub fn take_init_fn(&mut self) -> Option<unsafe extern "C" fn() -> core::ffi::c_int> {
         let init_fn = self.0.init.take();
         init_fn
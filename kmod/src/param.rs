@@ -1,4 +1,136 @@
 /// The `KernelParam` struct represents a kernel module parameter.
 ///
 /// See <https://elixir.bootlin.com/linux/v6.6/source/include/linux/moduleparam.h#L69>
+#[repr(transparent)]
 pub struct KernelParam(kbindings::kernel_param);
+
+impl KernelParam {
+    /// Get the name of the parameter
+    ///
+    /// # Safety
+    ///
+    /// The name pointer must be relocated and point to a NUL terminated string.
+    pub unsafe fn name(&self) -> &core::ffi::CStr {
+        unsafe { core::ffi::CStr::from_ptr(self.0.name) }
+    }
+
+    /// Get the pointer to the value of the parameter
+    pub fn arg(&self) -> *mut core::ffi::c_void {
+        unsafe { self.0.__bindgen_anon_1.arg }
+    }
+
+    /// Get the maximum length and the buffer of a `string` parameter
+    ///
+    /// # Safety
+    ///
+    /// The parameter must be a relocated `string` parameter, whose argument
+    /// points to a `kparam_string`.
+    pub unsafe fn string(&self) -> (usize, *mut core::ffi::c_char) {
+        let kps = unsafe { &*self.0.__bindgen_anon_1.str_ };
+        (kps.maxlen as usize, kps.string)
+    }
+}
+
+/// Maximum length of a [`ModuleParam`] name, including the NUL terminator
+pub const MODULE_PARAM_NAME_LEN: usize = 32;
+
+/// Type of a parameter declared with `#[module_param]`
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModuleParamType {
+    I32 = 0,
+    U32 = 1,
+    Bool = 2,
+}
+
+impl ModuleParamType {
+    /// Decode the `ty` field of a [`ModuleParam`]
+    pub const fn from_raw(raw: u32) -> Option<Self> {
+        match raw {
+            0 => Some(ModuleParamType::I32),
+            1 => Some(ModuleParamType::U32),
+            2 => Some(ModuleParamType::Bool),
+            _ => None,
+        }
+    }
+
+    /// Size of a value of this type in bytes
+    pub const fn size(self) -> usize {
+        match self {
+            ModuleParamType::I32 | ModuleParamType::U32 => 4,
+            ModuleParamType::Bool => 1,
+        }
+    }
+}
+
+/// Descriptor of a parameter declared with `#[module_param]`, placed in the
+/// `.modparam` section.
+///
+/// The layout is part of the module format, the loader reads the section as
+/// an array of descriptors.
+#[repr(C)]
+pub struct ModuleParam {
+    /// NUL padded name
+    name: [u8; MODULE_PARAM_NAME_LEN],
+    /// Raw [`ModuleParamType`]
+    ty: u32,
+    /// Bits of the default value, sign extended for [`ModuleParamType::I32`]
+    default: u64,
+    /// Address of the parameter variable, relocated by the loader
+    value: *mut core::ffi::c_void,
+}
+
+unsafe impl Sync for ModuleParam {}
+
+impl ModuleParam {
+    /// Create a descriptor, failing to compile if `name` is too long
+    pub const fn new(
+        name: &str,
+        ty: ModuleParamType,
+        default: u64,
+        value: *mut core::ffi::c_void,
+    ) -> Self {
+        let bytes = name.as_bytes();
+        assert!(
+            bytes.len() < MODULE_PARAM_NAME_LEN,
+            "module parameter name is too long"
+        );
+        let mut buf = [0u8; MODULE_PARAM_NAME_LEN];
+        let mut i = 0;
+        while i < bytes.len() {
+            buf[i] = bytes[i];
+            i += 1;
+        }
+        ModuleParam {
+            name: buf,
+            ty: ty as u32,
+            default,
+            value,
+        }
+    }
+
+    /// Get the name of the parameter, `None` if it is not valid UTF-8
+    pub fn name(&self) -> Option<&str> {
+        let len = self
+            .name
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(MODULE_PARAM_NAME_LEN);
+        core::str::from_utf8(&self.name[..len]).ok()
+    }
+
+    /// Get the type of the parameter, `None` if it is unknown
+    pub fn ty(&self) -> Option<ModuleParamType> {
+        ModuleParamType::from_raw(self.ty)
+    }
+
+    /// Get the bits of the default value
+    pub fn default_bits(&self) -> u64 {
+        self.default
+    }
+
+    /// Get the pointer to the value of the parameter
+    pub fn value(&self) -> *mut core::ffi::c_void {
+        self.value
+    }
+}
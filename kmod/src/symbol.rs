@@ -0,0 +1,67 @@
+/// Descriptor of a symbol exported with `#[export_symbol]`, placed in the
+/// `.ksymtab` section.
+///
+/// The layout is part of the module format, the loader reads the section as
+/// an array of descriptors once the module is relocated.
+///
+/// See <https://elixir.bootlin.com/linux/v6.6/source/include/linux/export.h#L60>
+#[repr(C)]
+pub struct KernelSymbol {
+    /// Address of the symbol
+    value: *const core::ffi::c_void,
+    /// UTF-8 name, not NUL terminated
+    name: *const u8,
+    name_len: usize,
+    /// UTF-8 namespace, empty if the symbol is not in one
+    namespace: *const u8,
+    namespace_len: usize,
+}
+
+unsafe impl Sync for KernelSymbol {}
+
+impl KernelSymbol {
+    pub const fn new(name: &'static str, value: *const core::ffi::c_void) -> Self {
+        KernelSymbol {
+            value,
+            name: name.as_ptr(),
+            name_len: name.len(),
+            namespace: "".as_ptr(),
+            namespace_len: 0,
+        }
+    }
+
+    /// Export the symbol in `namespace`, only modules importing it may use it
+    pub const fn with_namespace(mut self, namespace: &'static str) -> Self {
+        self.namespace = namespace.as_ptr();
+        self.namespace_len = namespace.len();
+        self
+    }
+
+    /// Get the name of the symbol
+    ///
+    /// # Safety
+    ///
+    /// The name pointer must be relocated.
+    pub unsafe fn name(&self) -> Option<&str> {
+        let bytes = unsafe { core::slice::from_raw_parts(self.name, self.name_len) };
+        core::str::from_utf8(bytes).ok()
+    }
+
+    /// Get the namespace of the symbol, `Ok(None)` if it is not in one
+    ///
+    /// # Safety
+    ///
+    /// The namespace pointer must be relocated.
+    pub unsafe fn namespace(&self) -> Result<Option<&str>, core::str::Utf8Error> {
+        if self.namespace_len == 0 {
+            return Ok(None);
+        }
+        let bytes = unsafe { core::slice::from_raw_parts(self.namespace, self.namespace_len) };
+        core::str::from_utf8(bytes).map(Some)
+    }
+
+    /// Get the address of the symbol
+    pub fn value(&self) -> *const core::ffi::c_void {
+        self.value
+    }
+}
@@ -35,4 +35,6 @@ module!(
     license: "GPL",
     description: "A simple hello world kernel module",
     version: "0.1.0",
+    init: hello_init,
+    exit: hello_exit,
 );
@@ -0,0 +1,49 @@
+#![no_std]
+
+use kmod::{exit_fn, init_fn, module};
+
+/// `EINVAL`, returned negated like a Linux init function
+const EINVAL: i32 = 22;
+
+unsafe extern "C" {
+    fn write_char(c: u8);
+}
+
+struct Writer;
+
+impl core::fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for &b in s.as_bytes() {
+            unsafe { write_char(b) };
+        }
+        Ok(())
+    }
+}
+
+/// Always fails, so the loader must free the module without registering it
+#[init_fn]
+pub fn initfail_init() -> i32 {
+    let mut writer = Writer;
+    core::fmt::write(
+        &mut writer,
+        format_args!("Initfail module refusing to load\n"),
+    )
+    .unwrap();
+    -EINVAL
+}
+
+/// Never called, the module is never live
+#[exit_fn]
+fn initfail_exit() {
+    let mut writer = Writer;
+    core::fmt::write(&mut writer, format_args!("Initfail module unloaded\n")).unwrap();
+}
+
+module!(
+    name: "initfail",
+    license: "GPL",
+    description: "A module whose init function fails",
+    version: "0.1.0",
+    init: initfail_init,
+    exit: initfail_exit,
+);
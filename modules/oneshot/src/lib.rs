@@ -0,0 +1,34 @@
+#![no_std]
+
+use kmod::{init_fn, module};
+
+unsafe extern "C" {
+    fn write_char(c: u8);
+}
+
+struct Writer;
+
+impl core::fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for &b in s.as_bytes() {
+            unsafe { write_char(b) };
+        }
+        Ok(())
+    }
+}
+
+/// Nothing to clean up, so the module has no exit function
+#[init_fn]
+pub fn oneshot_init() -> i32 {
+    let mut writer = Writer;
+    core::fmt::write(&mut writer, format_args!("Oneshot module loaded\n")).unwrap();
+    0
+}
+
+module!(
+    name: "oneshot",
+    license: "GPL",
+    description: "A module without an exit function",
+    version: "0.1.0",
+    init: oneshot_init,
+);